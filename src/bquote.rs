@@ -0,0 +1,132 @@
+use crate::object::Object;
+use crate::symbol::Symbol;
+use anyhow::{anyhow, Error, Result};
+
+// `(a ,b ,@c) is read as (bquote (a (comma b) (comma-at c))): the
+// template is quoted, except for what follows a comma, which is evaluated,
+// and what follows a comma-at, which is evaluated and spliced into the
+// list it's in. Commas inside a nested backquote belong to that one.
+
+// e, if obj is (name e)
+fn marked(obj: &Object, name: &str) -> Option<Object> {
+    match obj.to_vec() {
+        Ok(v) if v.len() == 2 && v[0].is_symbol(name) => Some(v[1].clone()),
+        _ => None,
+    }
+}
+
+fn list2(name: &str, obj: Object) -> Object {
+    pair!(Object::Symbol(Symbol::new(name)), pair!(obj, nil!()))
+}
+
+/// The expressions a backquoted template needs the values of, in order
+pub fn commas(template: &Object) -> Result<Vec<Object>, Error> {
+    let mut exps = Vec::new();
+    expand(template, 0, &mut |exp| {
+        exps.push(exp.clone());
+        Ok(nil!())
+    })?;
+    Ok(exps)
+}
+
+/// The template with the values of its commas, in the order commas gives
+/// them, filled in. The parts without commas are shared with the template,
+/// as they would be quoted.
+pub fn fill(template: &Object, values: Vec<Object>) -> Result<Object, Error> {
+    let mut values = values.into_iter();
+    let filled = expand(template, 0, &mut |_| {
+        values
+            .next()
+            .ok_or_else(|| anyhow!("bquote: too few values for {}", template))
+    })?;
+    Ok(filled.unwrap_or_else(|| template.clone()))
+}
+
+// obj with value(e) for each comma e at depth 0, or None if there are none
+// and obj is unchanged
+fn expand(
+    obj: &Object,
+    depth: usize,
+    value: &mut dyn FnMut(&Object) -> Result<Object, Error>,
+) -> Result<Option<Object>, Error> {
+    if let Some(exp) = marked(obj, "comma") {
+        if depth == 0 {
+            return value(&exp).map(Some);
+        }
+        return Ok(expand(&exp, depth - 1, value)?.map(|exp| list2("comma", exp)));
+    }
+    if let Some(exp) = marked(obj, "comma-at") {
+        if depth == 0 {
+            return Err(anyhow!("bquote: comma-at outside a list: {}", obj));
+        }
+        return Ok(expand(&exp, depth - 1, value)?.map(|exp| list2("comma-at", exp)));
+    }
+    if let Some(exp) = marked(obj, "bquote") {
+        return Ok(expand(&exp, depth + 1, value)?.map(|exp| list2("bquote", exp)));
+    }
+    let (car, cdr) = match obj {
+        Object::Pair(_) => obj.extract_pair()?,
+        _ => return Ok(None),
+    };
+
+    let spliced = match marked(&car, "comma-at") {
+        Some(exp) if depth == 0 => Some(value(&exp)?.to_vec()?),
+        _ => None,
+    };
+    let (car_expanded, cdr_expanded) = match spliced {
+        Some(_) => (None, expand(&cdr, depth, value)?),
+        None => (expand(&car, depth, value)?, expand(&cdr, depth, value)?),
+    };
+    match (spliced, car_expanded, cdr_expanded) {
+        (Some(items), _, cdr_expanded) => Ok(Some(
+            items
+                .into_iter()
+                .rev()
+                .fold(cdr_expanded.unwrap_or(cdr), |list, item| pair!(item, list)),
+        )),
+        (None, None, None) => Ok(None),
+        (None, car_expanded, cdr_expanded) => Ok(Some(pair!(
+            car_expanded.unwrap_or(car),
+            cdr_expanded.unwrap_or(cdr)
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn template(text: &str) -> Result<Object, Error> {
+        Ok(parser::parse(text)?.to_vec()?[1].clone())
+    }
+
+    #[test]
+    fn can_fill_templates() -> Result<(), Error> {
+        let t = template("`(a ,b ,@c d)")?;
+        assert_eq!(commas(&t)?, vec![symbol!("b"), symbol!("c")]);
+        let filled = fill(&t, vec![symbol!("x"), parser::parse("(y z)")?])?;
+        assert_eq!(filled, parser::parse("(a x y z d)")?);
+
+        // without commas, the template itself
+        let t = template("`(a (b) c)")?;
+        assert!(commas(&t)?.is_empty());
+        assert!(fill(&t, vec![])?.is_same_pair(&t));
+
+        // the inner backquote's commas are left alone, the outer's filled
+        let t = template("`(a `(b ,c ,,d))")?;
+        assert_eq!(commas(&t)?, vec![symbol!("d")]);
+        let filled = fill(&t, vec![symbol!("x")])?;
+        assert_eq!(filled, parser::parse("(a `(b ,c ,x))")?);
+
+        let t = template("`(a . ,b)")?;
+        assert_eq!(
+            fill(&t, vec![symbol!("x")])?,
+            pair!(symbol!("a"), symbol!("x"))
+        );
+
+        assert!(commas(&template("`,@a")?).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,356 @@
+use crate::bquote;
+use crate::env::Env;
+use crate::eval::{Bel, Form};
+use crate::heap::Tracer;
+use crate::object::Object;
+use crate::params;
+use crate::pretty;
+use crate::scheduler::Call;
+use crate::symbol::Symbol;
+use std::fmt;
+
+/// An instruction for the bytecode VM.
+/// Ops work on the thread's value stack, like the tree-walker's tasks.
+/// A variable bound with dyn is looked up there first by every op that
+/// pushes a variable's value.
+#[derive(Debug, Clone)]
+pub enum Op {
+    /// push a constant
+    Const(Object),
+    /// push the value of a parameter of the function being run;
+    /// parameters are the first frame of its environment
+    Local(usize),
+    /// push the value of a variable the function captured, at the address
+    /// it had in the captured env when the body was compiled; it's looked
+    /// up by name if it isn't there
+    Captured(usize, usize, Symbol),
+    /// push the value of a variable, local if one is bound, otherwise global
+    Var(Symbol),
+    /// throw away the top value
+    Pop,
+    /// continue at the address
+    Jump(usize),
+    /// pop a value, and continue at the address if it is nil
+    JumpIfNil(usize),
+    BeginAtomic,
+    EndAtomic,
+    /// pop n values and make the call with them as arguments
+    Call(Call, usize),
+    /// expand the macro call (name . args), and evaluate the expansion
+    Macro(Object),
+    /// evaluate the expression with the tree-walker; used for forms that
+    /// don't evaluate their arguments in the usual way, like set and zap,
+    /// and for anything malformed, so errors are reported the same way
+    Walk(Object),
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Op::Const(obj) => write!(f, "const {}", pretty::flat(obj)),
+            Op::Local(i) => write!(f, "local {}", i),
+            Op::Captured(depth, i, name) => write!(f, "captured {} {} {}", depth, i, name),
+            Op::Var(name) => write!(f, "var {}", name),
+            Op::Pop => write!(f, "pop"),
+            Op::Jump(to) => write!(f, "jump {}", to),
+            Op::JumpIfNil(to) => write!(f, "jump-if-nil {}", to),
+            Op::BeginAtomic => write!(f, "begin-atomic"),
+            Op::EndAtomic => write!(f, "end-atomic"),
+            Op::Call(call, n) => match call {
+                Call::Primative(name) => write!(f, "call prim {} {}", name, n),
+                Call::Function(name) => write!(f, "call fn {} {}", name, n),
+                Call::Bquote(template) => {
+                    write!(f, "call bquote {} {}", pretty::flat(template), n)
+                }
+                _ => write!(f, "call {:?} {}", call, n),
+            },
+            Op::Macro(call) => write!(f, "macro {}", pretty::flat(call)),
+            Op::Walk(exp) => write!(f, "walk {}", pretty::flat(exp)),
+        }
+    }
+}
+
+/// Compiled code for an expression
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+}
+
+impl Chunk {
+    // mark the constants for the garbage collector
+    pub fn trace(&self, tracer: &mut Tracer) {
+        for op in &self.code {
+            match op {
+                Op::Const(obj) | Op::Macro(obj) | Op::Walk(obj) => tracer.mark(obj),
+                Op::Call(call, _) => call.trace(tracer),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl fmt::Display for Chunk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (pc, op) in self.code.iter().enumerate() {
+            writeln!(f, "{:4} {}", pc, op)?;
+        }
+        Ok(())
+    }
+}
+
+// Compile exp, to be run with params bound in the first frame of the
+// environment, in front of captured, as they are for the body of a closure.
+// Which names are functions, macros and so on is decided now, so compiled
+// code must be thrown away when that changes. Where the captured variables
+// are is decided now too, but that's checked as the code runs, as other
+// closures with the same body may have captured other envs.
+pub fn compile(bel: &Bel, exp: &Object, params: &Object, captured: &Env) -> Chunk {
+    let vmark = bel.vmark();
+    let params = params::names(params, &vmark);
+    let mut compiler = Compiler {
+        bel,
+        vmark,
+        params,
+        captured,
+        code: Vec::new(),
+    };
+    compiler.exp(exp);
+
+    Chunk {
+        code: compiler.code,
+    }
+}
+
+struct Compiler<'a> {
+    bel: &'a Bel,
+    vmark: Object,
+    params: Vec<Symbol>,
+    captured: &'a Env,
+    code: Vec<Op>,
+}
+
+impl Compiler<'_> {
+    // add op, returning its address
+    fn emit(&mut self, op: Op) -> usize {
+        self.code.push(op);
+        self.code.len() - 1
+    }
+
+    // point the jump at address at the next op
+    fn patch(&mut self, address: usize) {
+        let to = self.code.len();
+        match &mut self.code[address] {
+            Op::Jump(target) | Op::JumpIfNil(target) => *target = to,
+            op => panic!("patch: {} is not a jump", op),
+        }
+    }
+
+    fn exp(&mut self, exp: &Object) {
+        match exp {
+            Object::Symbol(name) => self.variable(*name),
+            Object::Pair(_) => match params::uvar_name(exp, &self.vmark) {
+                Some(name) => self.variable(name),
+                None => self.pair(exp),
+            },
+            _ => {
+                self.emit(Op::Walk(exp.clone()));
+            }
+        }
+    }
+
+    fn variable(&mut self, name: Symbol) {
+        let op = match self.params.iter().position(|param| *param == name) {
+            Some(i) => Op::Local(i),
+            // the params are the frame in front of the captured env
+            None => match self.captured.resolve(name) {
+                Some((depth, i)) => Op::Captured(depth + 1, i, name),
+                None => Op::Var(name),
+            },
+        };
+        self.emit(op);
+    }
+
+    fn pair(&mut self, exp: &Object) {
+        let (car, cdr) = match exp.extract_pair() {
+            Ok(pair) => pair,
+            Err(_) => {
+                self.emit(Op::Walk(exp.clone()));
+                return;
+            }
+        };
+        let args = match cdr.to_vec() {
+            Ok(args) => args,
+            Err(_) => {
+                self.emit(Op::Walk(exp.clone()));
+                return;
+            }
+        };
+
+        match self.bel.form_of(&car) {
+            Form::If => self.r#if(&args),
+            Form::Quote if args.len() == 1 => {
+                self.emit(Op::Const(args[0].clone()));
+            }
+            Form::Lit => {
+                self.emit(Op::Const(exp.clone()));
+            }
+            Form::Do => self.sequence(&args),
+            Form::Atomic => {
+                self.emit(Op::BeginAtomic);
+                self.sequence(&args);
+                self.emit(Op::EndAtomic);
+            }
+            Form::Bquote if args.len() == 1 => match bquote::commas(&args[0]) {
+                Ok(commas) => self.call(Call::Bquote(args[0].clone()), &commas),
+                Err(_) => {
+                    self.emit(Op::Walk(exp.clone()));
+                }
+            },
+            Form::Apply => self.call(Call::Apply, &args),
+            Form::Ccc if args.len() == 1 => self.call(Call::Ccc, &args),
+            Form::Primative(name) => self.call(Call::Primative(name), &args),
+            Form::Function => self.call(Call::Function(car), &args),
+            Form::Macro => {
+                // the call itself, so its expansion can be cached
+                self.emit(Op::Macro(exp.clone()));
+            }
+            Form::Dynamic => {
+                let mut elements = vec![car];
+                elements.extend(args);
+                self.call(Call::Dynamic, &elements);
+            }
+            _ => {
+                self.emit(Op::Walk(exp.clone()));
+            }
+        }
+    }
+
+    fn call(&mut self, call: Call, args: &[Object]) {
+        for arg in args {
+            self.exp(arg);
+        }
+        self.emit(Op::Call(call, args.len()));
+    }
+
+    // the value of the last expression, nil if there are none
+    fn sequence(&mut self, exps: &[Object]) {
+        if exps.is_empty() {
+            self.emit(Op::Const(nil!()));
+        }
+        for (i, exp) in exps.iter().enumerate() {
+            if i > 0 {
+                self.emit(Op::Pop);
+            }
+            self.exp(exp);
+        }
+    }
+
+    // (if a1 a2 a3 a4 a5) compiles to
+    //      a1
+    //      jump-if-nil L1
+    //      a2
+    //      jump END
+    //  L1: a3
+    //      jump-if-nil L2
+    //      a4
+    //      jump END
+    //  L2: a5
+    // END:
+    fn r#if(&mut self, args: &[Object]) {
+        let mut ends: Vec<usize> = Vec::new();
+        let mut rest = args;
+        loop {
+            match rest {
+                [] => {
+                    self.emit(Op::Const(nil!()));
+                    break;
+                }
+                [last] => {
+                    self.exp(last);
+                    break;
+                }
+                [test, then, more @ ..] => {
+                    self.exp(test);
+                    let skip = self.emit(Op::JumpIfNil(0));
+                    self.exp(then);
+                    ends.push(self.emit(Op::Jump(0)));
+                    self.patch(skip);
+                    rest = more;
+                }
+            }
+        }
+        for end in ends {
+            self.patch(end);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+    use anyhow::Error;
+
+    fn compile_text(bel: &Bel, text: &str, params: &str) -> Result<String, Error> {
+        let chunk = compile(
+            bel,
+            &parser::parse(text)?,
+            &parser::parse(params)?,
+            &Env::new(),
+        );
+        Ok(chunk.to_string())
+    }
+
+    #[test]
+    fn can_compile_if() -> Result<(), Error> {
+        let bel = Bel::new();
+        assert_eq!(
+            compile_text(&bel, "(if a 'b c)", "(c)")?,
+            "   0 var a\n   1 jump-if-nil 4\n   2 const b\n   3 jump 5\n   4 local 0\n"
+        );
+        assert_eq!(compile_text(&bel, "(if)", "nil")?, "   0 const nil\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_compile_calls() -> Result<(), Error> {
+        let bel = Bel::new();
+        assert_eq!(
+            compile_text(&bel, "(car (x y))", "(y)")?,
+            "   0 var x\n   1 local 0\n   2 call Dynamic 2\n   3 call prim car 1\n"
+        );
+        // forms that don't evaluate their arguments are left to the tree-walker
+        assert_eq!(
+            compile_text(&bel, "(do (set a b) a)", "nil")?,
+            "   0 walk (set a b)\n   1 pop\n   2 var a\n"
+        );
+        // constants are printed as they'd be written
+        assert_eq!(
+            compile_text(&bel, "(car '(a (b) . c))", "nil")?,
+            "   0 const (a (b) . c)\n   1 call prim car 1\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_compile_captured_variables() -> Result<(), Error> {
+        let bel = Bel::new();
+        let captured = Env::new()
+            .extend(vec![(Symbol::new("x"), symbol!("a"))])
+            .extend(vec![(Symbol::new("y"), symbol!("b"))]);
+        let chunk = compile(
+            &bel,
+            &parser::parse("(join x (join y z))")?,
+            &parser::parse("(y)")?,
+            &captured,
+        );
+        assert_eq!(
+            chunk.to_string(),
+            "   0 captured 2 0 x\n   1 local 0\n   2 var z\n   3 call prim join 2\n   4 call prim join 2\n"
+        );
+
+        Ok(())
+    }
+}
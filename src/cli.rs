@@ -0,0 +1,180 @@
+use crate::eval::{new_object_map, Bel};
+use crate::loader;
+use crate::object::{self, Object};
+use crate::pretty;
+use crate::symbol::Symbol;
+use anyhow::{anyhow, Context, Error, Result};
+use log::debug;
+use std::io::{self, Read};
+
+pub const USAGE: &str = "usage: smolbel [--load file]... [-e expr]... [-i] [script | -] [arg...]
+       smolbel fmt [--check] [file...]";
+
+/// the global the script's args are bound to, as a list of strings
+pub const ARGS_NAME: &str = "argv";
+
+/// What the command line asks for
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Options {
+    /// files to load first, in order
+    pub loads: Vec<String>,
+    /// expressions to evaluate and print, in order
+    pub exprs: Vec<String>,
+    /// the file to run, after the loads and exprs; - is stdin
+    pub script: Option<String>,
+    /// everything after the script, or after the options with -e
+    pub args: Vec<String>,
+    /// start the REPL afterwards
+    pub interactive: bool,
+    /// just print the usage
+    pub help: bool,
+}
+
+impl Options {
+    /// Read the options, up to the script or a `--`. Everything after
+    /// that is args for the script. With -e there's no script, and
+    /// everything after the options is args.
+    pub fn parse(args: &[String]) -> Result<Options> {
+        let mut options = Options::default();
+        let mut args = args.iter();
+        let mut rest = Vec::new();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-e" => options.exprs.push(value(arg, args.next())?),
+                "--load" => options.loads.push(value(arg, args.next())?),
+                "-i" => options.interactive = true,
+                "-h" | "--help" => options.help = true,
+                "--" => break,
+                _ if arg.starts_with('-') && arg != "-" => {
+                    return Err(anyhow!("unknown option {}", arg));
+                }
+                _ => {
+                    rest.push(arg.clone());
+                    break;
+                }
+            }
+        }
+        rest.extend(args.cloned());
+
+        let mut rest = rest.into_iter();
+        if options.exprs.is_empty() {
+            options.script = rest.next();
+        }
+        options.args = rest.collect();
+        Ok(options)
+    }
+
+    /// The REPL runs if asked for, or if there's nothing else to do
+    pub fn repl(&self) -> bool {
+        self.interactive || (self.script.is_none() && self.exprs.is_empty())
+    }
+}
+
+fn value(option: &str, value: Option<&String>) -> Result<String> {
+    value
+        .cloned()
+        .ok_or_else(|| anyhow!("{} needs a value", option))
+}
+
+/// Bind the args, load the files, evaluate the expressions printing each
+/// result, and run the script, stopping at the first error. Then run the
+/// threads they started until they finish.
+pub fn run(bel: &mut Bel, options: &Options) -> Result<(), Error> {
+    let args: Vec<Object> = options
+        .args
+        .iter()
+        .map(|arg| object::from_string(arg))
+        .collect();
+    bel.set_global(
+        Object::Symbol(Symbol::new(ARGS_NAME)),
+        object::from_vec(args)?,
+    );
+
+    for filepath in &options.loads {
+        loader::load_source(bel, filepath, None)?;
+    }
+    for expr in &options.exprs {
+        debug!("-e {}", expr);
+        let exp = bel.parse(expr).context("-e")?;
+        let obj = bel.eval(&new_object_map(), &exp)?;
+        println!("{}", pretty::pretty(&obj, pretty::WIDTH));
+    }
+    match options.script.as_deref() {
+        Some("-") => {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text).context("stdin")?;
+            loader::load_text(bel, &text, "stdin", None)?;
+        }
+        Some(script) => loader::load_source(bel, script, None)?,
+        None => {}
+    }
+    bel.run_threads()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Options::parse(&args)
+    }
+
+    #[test]
+    fn can_parse_options() -> Result<(), Error> {
+        let options = parse(&[])?;
+        assert_eq!(options, Options::default());
+        assert!(options.repl());
+
+        let options = parse(&["--load", "a.bel", "--load", "b.bel", "s.bel", "-i", "x"])?;
+        assert_eq!(options.loads, vec!["a.bel", "b.bel"]);
+        assert_eq!(options.script, Some("s.bel".to_string()));
+        assert_eq!(options.args, vec!["-i", "x"]);
+        assert!(!options.repl());
+
+        let options = parse(&["-i", "-e", "(car argv)", "x", "y"])?;
+        assert_eq!(options.exprs, vec!["(car argv)"]);
+        assert_eq!(options.script, None);
+        assert_eq!(options.args, vec!["x", "y"]);
+        assert!(options.repl());
+
+        let options = parse(&["-", "x"])?;
+        assert_eq!(options.script, Some("-".to_string()));
+        assert_eq!(options.args, vec!["x"]);
+
+        let options = parse(&["--", "-s.bel", "-e"])?;
+        assert_eq!(options.script, Some("-s.bel".to_string()));
+        assert_eq!(options.args, vec!["-e"]);
+
+        assert!(parse(&["-x"]).is_err());
+        assert!(parse(&["--load"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn can_bind_args() -> Result<(), Error> {
+        let mut bel = Bel::new();
+        let options = parse(&["-e", "(car (cdr argv))", "a", "bc"])?;
+        run(&mut bel, &options)?;
+        let argv = bel.parse("argv")?;
+        let argv = bel.eval(&new_object_map(), &argv)?;
+        assert_eq!(
+            argv,
+            object::from_vec(vec![object::from_string("a"), object::from_string("bc")])?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn runs_threads_to_the_end() -> Result<(), Error> {
+        let mut bel = Bel::new();
+        let options = parse(&["-e", "(thread (car 'b))", "-e", "(thread (set x 'a))"])?;
+        // an error in a thread is an error of the run, once the rest finish
+        assert!(run(&mut bel, &options).is_err());
+        assert!(bel.scheduler.threads.is_empty());
+        assert_eq!(bel.global(&symbol!("x")), Some(symbol!("a")));
+        Ok(())
+    }
+}
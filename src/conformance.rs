@@ -0,0 +1,181 @@
+use crate::eval::{new_object_map, Bel};
+use crate::limits::Limits;
+use crate::object::Object;
+use crate::parser::Reader;
+use anyhow::{Context, Error, Result};
+use log::debug;
+use std::fmt;
+use std::fs;
+
+/// the Bel source that defines the language
+pub const SOURCE_PATH: &str = "bel_source/bel.bel";
+
+// a form that takes more steps than this to load has probably gone wrong
+const MAX_STEPS: u64 = 1_000_000;
+
+// errors are cut short, to keep to a line of the table
+const MAX_ERROR_LEN: usize = 60;
+
+/// Whether one top level form loaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormResult {
+    /// the line the form starts on
+    pub line: usize,
+    /// the name the form defines, or its first line
+    pub name: String,
+    /// the first error, if it didn't load
+    pub error: Option<String>,
+}
+
+impl FormResult {
+    pub fn loaded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// How much of a source file loads
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    pub forms: Vec<FormResult>,
+    /// the names the source defines that are special forms here, so
+    /// their definitions load but aren't used; see Bel::shadowed
+    pub shadowed: Vec<String>,
+}
+
+impl Report {
+    pub fn loaded(&self) -> usize {
+        self.forms.iter().filter(|form| form.loaded()).count()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .forms
+            .iter()
+            .map(|form| form.name.len())
+            .max()
+            .unwrap_or(0)
+            .max("name".len());
+        writeln!(f, "line  {:width$}  loaded  error", "name", width = width)?;
+        for form in &self.forms {
+            write!(f, "{:4}  {:width$}  ", form.line, form.name, width = width)?;
+            match &form.error {
+                None => writeln!(f, "yes")?,
+                Some(err) => writeln!(f, "no      {}", err)?,
+            }
+        }
+        if !self.shadowed.is_empty() {
+            writeln!(f, "built in, so not used: {}", self.shadowed.join(" "))?;
+        }
+        write!(f, "{} of {} forms loaded", self.loaded(), self.forms.len())
+    }
+}
+
+// Load each top level form in the file into a new Bel, carrying on past
+// the ones that fail
+pub fn check_source(filepath: &str) -> Result<Report> {
+    let text = fs::read_to_string(filepath).context(filepath.to_string())?;
+    Ok(check_text(&text))
+}
+
+// Reading stops at the first object that can't be read
+pub fn check_text(text: &str) -> Report {
+    let mut bel = Bel::new();
+    bel.limits = Limits {
+        max_steps: Some(MAX_STEPS),
+        ..Limits::default()
+    };
+    let mut forms: Vec<FormResult> = Vec::new();
+    for item in Reader::new(text).with_syntax(&bel.syntax) {
+        let (position, exp) = match item {
+            Ok(item) => item,
+            Err(err) => {
+                forms.push(FormResult {
+                    line: err.position.line,
+                    name: err.source_line.clone(),
+                    error: Some(err.message),
+                });
+                break;
+            }
+        };
+        let source = text[position.offset..].lines().next().unwrap_or("");
+        let result = FormResult {
+            line: position.line,
+            name: form_name(&exp, source),
+            error: load_form(&mut bel, &exp).err().map(|err| first_line(&err)),
+        };
+        debug!("check_text: {:?}", result);
+        forms.push(result);
+    }
+    let shadowed = bel
+        .shadowed()
+        .iter()
+        .map(|name| name.name().to_string())
+        .collect();
+    Report { forms, shadowed }
+}
+
+fn load_form(bel: &mut Bel, exp: &Object) -> Result<()> {
+    bel.eval(&new_object_map(), exp)?;
+    bel.run_threads()
+}
+
+// the start of the first line of the error
+fn first_line(err: &Error) -> String {
+    let err = err.to_string();
+    let line = err.lines().next().unwrap_or("");
+    match line.char_indices().nth(MAX_ERROR_LEN) {
+        Some((i, _)) => format!("{}...", &line[..i]),
+        None => line.to_string(),
+    }
+}
+
+// (def name ...) is called name, as are mac, set and so on;
+// anything else by the first line of its source
+fn form_name(exp: &Object, source: &str) -> String {
+    let words = exp.to_vec().unwrap_or_default();
+    match words.as_slice() {
+        [Object::Symbol(head), Object::Symbol(name), ..]
+            if ["def", "mac", "set", "form", "vir", "syn", "com"].contains(&head.name()) =>
+        {
+            name.to_string()
+        }
+        _ => source.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_report_conformance() {
+        let text = "(def no (x) (id x nil))\n\n(def is (x) [= _ x])\n\n(car 'a)\n\n(no nil)\n";
+        let report = check_text(text);
+        assert_eq!(report.forms.len(), 4);
+        assert_eq!(report.loaded(), 3);
+
+        assert_eq!(report.forms[0].name, "no");
+        assert_eq!(report.forms[2].line, 5);
+        assert!(report.forms[0].loaded());
+        assert_eq!(report.forms[1].name, "is");
+        assert!(report.forms[1].loaded());
+        assert_eq!(report.forms[2].name, "(car 'a)");
+        assert!(!report.forms[2].loaded());
+        assert_eq!(report.forms[3].name, "(no nil)");
+
+        let table = report.to_string();
+        assert!(table.starts_with("line  name"));
+        assert!(table.contains("\n   1  no      "));
+        assert!(table.ends_with("3 of 4 forms loaded"));
+
+        // a definition of a special form loads, but isn't used
+        let report = check_text("(mac pop (place) nil)\n");
+        assert_eq!(report.loaded(), 1);
+        assert_eq!(report.shadowed, vec!["pop".to_string()]);
+        assert!(report
+            .to_string()
+            .contains("\nbuilt in, so not used: pop\n"));
+    }
+}
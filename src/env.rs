@@ -0,0 +1,369 @@
+use crate::eval::ObjectMap;
+use crate::heap::Tracer;
+use crate::list::List;
+use crate::object::Object;
+use crate::symbol::Symbol;
+use anyhow::{anyhow, Error};
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// Local variables.
+/// An environment is a chain of frames, innermost first. Each frame holds
+/// the bindings made by one function call, in parameter order, and a link
+/// to the environment the function was defined in. Frames are never copied:
+/// extending an environment, or capturing it in a closure, shares it.
+///
+/// Each binding is a (name . value) pair. A closure's env is an alist in
+/// Bel, so it can share the bindings of the frames it was made from, and
+/// an assignment through either is seen by both. The env an alist was made
+/// from is kept with the alist's first pair, in its slot in the heap, so
+/// calling the closure goes on with that env, rather than one made from
+/// the alist.
+///
+/// Once a frame is captured, where each name in its chain is bound is
+/// worked out, so the closures made with it look up the variables they
+/// captured without searching the frames.
+#[derive(Debug, Clone, Default)]
+pub struct Env(Option<Rc<Frame>>);
+
+#[derive(Debug)]
+pub struct Frame {
+    bindings: Vec<Object>,
+    parent: Env,
+    // the whole chain as an alist, made the first time it is captured
+    alist: OnceCell<Object>,
+    // the address of the innermost binding of each name in the chain,
+    // made when the alist is
+    addresses: OnceCell<HashMap<Symbol, (usize, usize)>>,
+}
+
+impl Frame {
+    pub(crate) fn parent(&self) -> &Env {
+        &self.parent
+    }
+
+    // the pairs the frame holds: its bindings, and its alist if it's been
+    // captured
+    pub(crate) fn objects(&self) -> impl Iterator<Item = &Object> {
+        self.bindings.iter().chain(self.alist.get())
+    }
+
+    // the index of name's binding in this frame
+    fn position(&self, name: Symbol) -> Option<usize> {
+        self.bindings.iter().position(|binding| match binding {
+            Object::Pair(pair) => pair.get().0 == Object::Symbol(name),
+            _ => false,
+        })
+    }
+}
+
+impl Env {
+    pub fn new() -> Self {
+        Env(None)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+
+    pub(crate) fn frame(&self) -> Option<&Rc<Frame>> {
+        self.0.as_ref()
+    }
+
+    pub(crate) fn from_frame(frame: &Rc<Frame>) -> Self {
+        Env(Some(frame.clone()))
+    }
+
+    // a single frame holding the symbols bound in om
+    pub fn from_map(om: &ObjectMap) -> Self {
+        let bindings: Vec<(Symbol, Object)> = om
+            .iter()
+            .filter_map(|(key, value)| match key {
+                Object::Symbol(name) => Some((*name, value.clone())),
+                _ => None,
+            })
+            .collect();
+        Env::new().extend(bindings)
+    }
+
+    // the environment of a closure: the one its env alist was made from,
+    // or else one frame sharing the bindings of the alist, which is kept
+    // with it from then on
+    pub fn from_alist(alist: &Object) -> Result<Self, Error> {
+        let pair = match alist {
+            Object::Pair(pair) => pair,
+            _ if alist.is_nil() => return Ok(Env::new()),
+            _ => return Err(anyhow!("invalid env: {}", alist)),
+        };
+        if let Some(env) = pair.env() {
+            return Ok(env);
+        }
+        let mut bindings: Vec<Object> = Vec::new();
+        let mut list = List::new(alist);
+        while let Some(binding) = list.step()? {
+            match binding.extract_pair() {
+                Ok((Object::Symbol(_), _)) => bindings.push(binding),
+                _ => return Err(anyhow!("invalid env binding: {}", binding)),
+            }
+        }
+        let frame = Frame {
+            bindings,
+            parent: Env::new(),
+            alist: OnceCell::from(alist.clone()),
+            addresses: OnceCell::new(),
+        };
+        let env = Env(Some(Rc::new(frame)));
+        env.resolve_all();
+        pair.set_env(env.clone());
+        Ok(env)
+    }
+
+    // a new frame binding each name to its value, in front of self
+    pub fn extend(&self, bindings: Vec<(Symbol, Object)>) -> Self {
+        let bindings = bindings
+            .into_iter()
+            .map(|(name, value)| pair!(Object::Symbol(name), value))
+            .collect();
+        let frame = Frame {
+            bindings,
+            parent: self.clone(),
+            alist: OnceCell::new(),
+            addresses: OnceCell::new(),
+        };
+        Env(Some(Rc::new(frame)))
+    }
+
+    // the (name . value) pair for the innermost binding of name
+    pub fn binding(&self, name: Symbol) -> Option<Object> {
+        let (depth, index) = self.resolve(name)?;
+        self.slot(depth, index)
+    }
+
+    pub fn get(&self, name: Symbol) -> Option<Object> {
+        self.binding(name)
+            .and_then(|binding| binding.extract_pair().ok())
+            .map(|(_, value)| value)
+    }
+
+    pub fn contains(&self, name: Symbol) -> bool {
+        self.resolve(name).is_some()
+    }
+
+    // assign to an existing binding, returning false if name isn't bound
+    pub fn set(&self, name: Symbol, value: Object) -> bool {
+        match self.binding(name) {
+            Some(binding) => binding.set_cdr(value).is_ok(),
+            None => false,
+        }
+    }
+
+    // The address of the innermost binding of name: how many frames out,
+    // and where in that frame. Compiled code can resolve an address once
+    // and then use slot, rather than looking up the name each time. The
+    // frames in front of the first captured one are searched; where the
+    // names are in that one, and those it leads to, is already known.
+    pub fn resolve(&self, name: Symbol) -> Option<(usize, usize)> {
+        let mut env = self;
+        let mut depth = 0;
+        while let Some(frame) = &env.0 {
+            if let Some(addresses) = frame.addresses.get() {
+                return addresses
+                    .get(&name)
+                    .map(|(outer, index)| (depth + outer, *index));
+            }
+            if let Some(index) = frame.position(name) {
+                return Some((depth, index));
+            }
+            env = &frame.parent;
+            depth += 1;
+        }
+
+        None
+    }
+
+    // work out the address of every name bound in the chain, once
+    fn resolve_all(&self) {
+        if let Some(frame) = &self.0 {
+            frame.addresses.get_or_init(|| {
+                let mut addresses = HashMap::new();
+                let mut env = self;
+                let mut depth = 0;
+                while let Some(frame) = &env.0 {
+                    for (index, binding) in frame.bindings.iter().enumerate() {
+                        if let Ok((Object::Symbol(name), _)) = binding.extract_pair() {
+                            addresses.entry(name).or_insert((depth, index));
+                        }
+                    }
+                    env = &frame.parent;
+                    depth += 1;
+                }
+                addresses
+            });
+        }
+    }
+
+    // the (name . value) pair at an address found by resolve
+    pub fn slot(&self, depth: usize, index: usize) -> Option<Object> {
+        let mut frame = self.0.as_ref()?;
+        for _ in 0..depth {
+            frame = frame.parent.0.as_ref()?;
+        }
+        frame.bindings.get(index).cloned()
+    }
+
+    // the value at an address found by resolve in an env like this one,
+    // if name is bound there; it's looked up by name if not
+    pub fn get_at(&self, depth: usize, index: usize, name: Symbol) -> Option<Object> {
+        let found = self.slot(depth, index).and_then(|binding| match &binding {
+            Object::Pair(pair) => match pair.get() {
+                (Object::Symbol(bound), value) if bound == name => Some(value),
+                _ => None,
+            },
+            _ => None,
+        });
+        found.or_else(|| self.get(name))
+    }
+
+    // mark the bindings for the garbage collector
+    pub fn trace(&self, tracer: &mut Tracer) {
+        tracer.mark_env(self);
+    }
+
+    // the bindings, innermost first, as an alist for a closure's env
+    pub fn to_alist(&self) -> Object {
+        match &self.0 {
+            None => nil!(),
+            Some(frame) => {
+                if let Some(alist) = frame.alist.get() {
+                    return alist.clone();
+                }
+                let mut alist = frame.parent.to_alist();
+                for binding in frame.bindings.iter().rev() {
+                    alist = pair!(binding.clone(), alist);
+                }
+                self.resolve_all();
+                // a frame without bindings shares its parent's alist, and
+                // its env
+                match &alist {
+                    Object::Pair(pair) if pair.env().is_none() => pair.set_env(self.clone()),
+                    _ => {}
+                }
+                frame.alist.get_or_init(|| alist).clone()
+            }
+        }
+    }
+}
+
+impl fmt::Display for Env {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut env = self;
+        while let Some(frame) = &env.0 {
+            write!(f, "[")?;
+            for binding in &frame.bindings {
+                write!(f, " {}", binding)?;
+            }
+            write!(f, " ]")?;
+            env = &frame.parent;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_lookup_through_frames() {
+        let outer = Env::new().extend(vec![
+            (Symbol::new("x"), symbol!("a")),
+            (Symbol::new("y"), symbol!("b")),
+        ]);
+        let inner = outer.extend(vec![(Symbol::new("x"), symbol!("c"))]);
+
+        assert_eq!(inner.get(Symbol::new("x")), Some(symbol!("c")));
+        assert_eq!(inner.get(Symbol::new("y")), Some(symbol!("b")));
+        assert_eq!(inner.get(Symbol::new("z")), None);
+        assert_eq!(inner.resolve(Symbol::new("y")), Some((1, 1)));
+        assert_eq!(outer.get(Symbol::new("x")), Some(symbol!("a")));
+
+        // assignment changes the innermost binding, and is shared
+        assert!(inner.set(Symbol::new("y"), symbol!("d")));
+        assert_eq!(outer.get(Symbol::new("y")), Some(symbol!("d")));
+        assert!(!inner.set(Symbol::new("z"), symbol!("d")));
+    }
+
+    #[test]
+    fn can_capture_as_alist() -> Result<(), Error> {
+        let env = Env::new()
+            .extend(vec![(Symbol::new("x"), symbol!("a"))])
+            .extend(vec![(Symbol::new("y"), symbol!("b"))]);
+        let alist = env.to_alist();
+        assert_eq!(alist.to_vec()?.len(), 2);
+        assert!(alist.is_same_pair(&env.to_alist()));
+
+        // an env made from the alist shares its bindings
+        let captured = Env::from_alist(&alist)?;
+        assert_eq!(captured.get(Symbol::new("x")), Some(symbol!("a")));
+        captured.set(Symbol::new("x"), symbol!("c"));
+        assert_eq!(env.get(Symbol::new("x")), Some(symbol!("c")));
+
+        assert!(Env::from_alist(&nil!())?.is_empty());
+        assert!(Env::from_alist(&pair!(symbol!("x"), nil!())).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_go_on_with_captured_env() -> Result<(), Error> {
+        let env = Env::new()
+            .extend(vec![(Symbol::new("x"), symbol!("a"))])
+            .extend(vec![(Symbol::new("y"), symbol!("b"))]);
+        let alist = env.to_alist();
+
+        // the env the alist was made from, frames and all
+        let captured = Env::from_alist(&alist)?;
+        assert_eq!(captured.resolve(Symbol::new("x")), Some((1, 0)));
+        assert_eq!(captured.get_at(1, 0, Symbol::new("x")), Some(symbol!("a")));
+        // an address that doesn't hold the name falls back on looking it up
+        assert_eq!(captured.get_at(0, 0, Symbol::new("x")), Some(symbol!("a")));
+        assert_eq!(captured.get_at(5, 0, Symbol::new("z")), None);
+
+        // an alist made in Bel gets one frame made from it, which is kept
+        // from then on
+        let made = pair!(pair!(symbol!("y"), symbol!("c")), alist.clone());
+        let flat = Env::from_alist(&made)?;
+        assert_eq!(flat.resolve(Symbol::new("x")), Some((0, 2)));
+        assert!(Rc::ptr_eq(
+            flat.frame().unwrap(),
+            Env::from_alist(&made)?.frame().unwrap()
+        ));
+        assert!(made.is_same_pair(&flat.to_alist()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn finds_captured_variables_by_address() -> Result<(), Error> {
+        let outer = Env::new().extend(vec![
+            (Symbol::new("x"), symbol!("a")),
+            (Symbol::new("y"), symbol!("b")),
+        ]);
+        let inner = outer.extend(vec![(Symbol::new("x"), symbol!("c"))]);
+        inner.to_alist();
+        let addresses = inner.frame().unwrap().addresses.get().unwrap();
+        assert_eq!(addresses.get(&Symbol::new("x")), Some(&(0, 0)));
+        assert_eq!(addresses.get(&Symbol::new("y")), Some(&(1, 1)));
+
+        // a call's frame is searched, then the addresses are used
+        let call = inner.extend(vec![(Symbol::new("z"), symbol!("d"))]);
+        assert_eq!(call.resolve(Symbol::new("z")), Some((0, 0)));
+        assert_eq!(call.resolve(Symbol::new("y")), Some((2, 1)));
+        assert_eq!(call.resolve(Symbol::new("w")), None);
+        assert_eq!(call.get(Symbol::new("x")), Some(symbol!("c")));
+
+        Ok(())
+    }
+}
@@ -1,165 +1,1299 @@
+use crate::bquote;
+use crate::bytecode::{compile, Chunk, Op};
+use crate::env::Env;
 use crate::functions;
+use crate::heap::{self, GcStats};
+use crate::limits::{Interrupted, Limits, Meter};
 use crate::list::List;
 use crate::object;
 use crate::object::Object;
-use crate::primatives::{load_primatives, PrimFunc};
+use crate::params;
+use crate::parser::{self, Syntax};
+use crate::places;
+use crate::places::{load_locfns, LocFunc, Modify, Place, PlaceExp};
+use crate::primatives::{load_primatives, PrimFunc, Signal};
+use crate::scheduler::{Call, Continuation, Scheduler, Task, Thread, DEFAULT_SEED};
+use crate::symbol::Symbol;
 
 use anyhow::{anyhow, Error};
-use log::{debug, trace};
-use std::collections::{HashMap, HashSet};
+use log::{debug, trace, warn};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// continuations are pruned during an evaluation once there are this many
+const MIN_CONTINUATIONS: usize = 1024;
 
 pub type ObjectMap = HashMap<Object, Object>;
 pub fn new_object_map() -> ObjectMap {
     HashMap::new()
 }
+
+// local variables for host code calling Bel::eval
+pub fn new_env(om: ObjectMap) -> Env {
+    Env::from_map(&om)
+}
+
 pub fn dump_object_map(om: &ObjectMap) -> String {
     let mut accum = String::new();
 
-    for (key, val) in  om {
+    for (key, val) in om {
         accum.push_str(&format!(" {} => {}; ", key, val));
     }
 
     accum
 }
 
+/// How Bel::eval runs code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// walk the expressions
+    TreeWalk,
+    /// compile expressions to bytecode, see bytecode.rs
+    Bytecode,
+}
+
+impl Engine {
+    // SMOLBEL_ENGINE=bytecode selects the bytecode engine for new Bels,
+    // e.g. to run the tests with it
+    pub fn from_env() -> Self {
+        match std::env::var("SMOLBEL_ENGINE").as_deref() {
+            Ok("bytecode") => Engine::Bytecode,
+            _ => Engine::TreeWalk,
+        }
+    }
+}
+
+/// What kind of expression a list is, decided by its head.
+/// The tree-walker and the compiler both use this, so they agree.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Form {
+    Set,
+    Def,
+    Mac,
+    If,
+    Quote,
+    /// a (lit ...) evaluates to itself
+    Lit,
+    Thread,
+    Atomic,
+    Do,
+    Fn,
+    Apply,
+    Syn,
+    Loc,
+    Bquote,
+    Ccc,
+    Dyn,
+    Modify(Modify),
+    Primative(Symbol),
+    Function,
+    Macro,
+    /// evaluate every element, then apply the first to the rest if it's
+    /// applicable
+    Dynamic,
+}
+
+/// The names form_of treats as forms of their own, whatever they're bound to
+pub const SPECIAL_FORMS: &[&str] = &[
+    "set", "def", "mac", "if", "quote", "lit", "thread", "atomic", "do", "fn", "apply", "syn",
+    "loc", "bquote", "ccc", "dyn", "where", "zap", "push", "pull", "pop", "swap", "wipe", "clean",
+    "pushnew",
+];
+
+/// What a global name is, e.g. for the REPL to colour it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameKind {
+    SpecialForm,
+    Macro,
+    /// a primitive or a closure
+    Function,
+    /// bound to anything else
+    Variable,
+    Unbound,
+}
+
+/// Counters for :stats
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// macro calls that used a cached expansion
+    pub expansion_hits: u64,
+    /// macro calls that had to be expanded
+    pub expansion_misses: u64,
+    /// expressions and closure bodies compiled to bytecode
+    pub compiles: u64,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let calls = self.expansion_hits + self.expansion_misses;
+        let rate = if calls == 0 {
+            0.0
+        } else {
+            100.0 * self.expansion_hits as f64 / calls as f64
+        };
+        write!(
+            f,
+            "macro expansions: {} hits, {} misses ({:.1}% hit rate); {} compiled",
+            self.expansion_hits, self.expansion_misses, rate, self.compiles
+        )
+    }
+}
+
+// the expansion of the macro call, made by the macro mac
+struct Expansion {
+    call: Object,
+    mac: Object,
+    expansion: Object,
+}
+
 pub struct Bel {
+    /// each global's binding, a (name . value) pair, keyed by name; see
+    /// global and set_global
     pub globals: ObjectMap,
-    pub primatives: HashMap<String, PrimFunc>,
-    pub function_names: HashSet<String>,
-    pub macro_names: HashSet<String>,
+    pub primatives: HashMap<Symbol, PrimFunc>,
+    pub locfns: HashMap<Symbol, LocFunc>,
+    pub scheduler: Scheduler,
+    pub limits: Limits,
+    /// setting this, from any OS thread, stops the current evaluation
+    pub interrupt: Arc<AtomicBool>,
+    pub engine: Engine,
+    // compiled closure bodies, keyed by the addresses of the body and
+    // parameters, which are kept so the addresses aren't reused
+    compiled: HashMap<(usize, usize), (Object, Object, Rc<Chunk>)>,
+    // compiled expressions evaluated outside a closure body, such as those
+    // at the top level and macro expansions, keyed by the address of the
+    // expression, which is kept so the address isn't reused
+    chunks: HashMap<usize, (Object, Rc<Chunk>)>,
+    // macro expansions, keyed by the address of the call
+    expansions: HashMap<usize, Expansion>,
+    // the continuations ccc has made, keyed by the address of the
+    // (lit cont) standing for each, which is kept so the address isn't
+    // reused
+    continuations: HashMap<usize, (Object, Continuation)>,
+    // the number of continuations at which to forget those nothing refers
+    // to, without waiting for the evaluation to end
+    prune_continuations_at: usize,
+    pub stats: Stats,
+    /// the reader macros, added to by syn
+    pub syntax: Syntax,
+    // the first error to end a thread other than the one being evaluated,
+    // since run_threads last returned it
+    thread_error: Option<Error>,
 }
 
 impl Bel {
     pub fn new() -> Self {
-        Bel {
-            // some Symbols bind to themselves
-            globals: HashMap::from([
-                (nil!(), nil!()),
-                (symbol!("t"), symbol!("t")),
-                (symbol!("o"), symbol!("o")),
-                (symbol!("apply"), symbol!("apply")),
-            ]),
+        let mut bel = Bel {
+            globals: HashMap::new(),
             primatives: load_primatives(),
-            function_names: HashSet::new(),
-            macro_names: HashSet::new(),
+            locfns: load_locfns(),
+            scheduler: Scheduler::new(DEFAULT_SEED),
+            limits: Limits::default(),
+            interrupt: Arc::new(AtomicBool::new(false)),
+            engine: Engine::from_env(),
+            compiled: HashMap::new(),
+            chunks: HashMap::new(),
+            expansions: HashMap::new(),
+            continuations: HashMap::new(),
+            prune_continuations_at: MIN_CONTINUATIONS,
+            stats: Stats::default(),
+            syntax: Syntax::default(),
+            thread_error: None,
+        };
+
+        // some Symbols bind to themselves
+        for name in ["nil", "t", "o", "apply"] {
+            bel.set_global(symbol!(name), symbol!(name));
+        }
+        // virtual functions: an alist of (tag . fn) consulted when a
+        // (lit tag ...) object is called
+        bel.set_global(symbol!("virfns"), nil!());
+        // places defined with loc: an alist of (name . fn)
+        bel.set_global(symbol!("locfns"), nil!());
+
+        // primatives are bound as (lit prim name) so they can be passed
+        // around as values, e.g. (apply car '((a b)))
+        let names: Vec<Symbol> = bel.primatives.keys().copied().collect();
+        for name in names {
+            let prim = pair!(
+                Object::Symbol(Symbol::LIT),
+                pair!(
+                    Object::Symbol(Symbol::PRIM),
+                    pair!(Object::Symbol(name), nil!())
+                )
+            );
+            bel.set_global(Object::Symbol(name), prim);
+        }
+
+        bel
+    }
+
+    /// The value of the global name
+    pub fn global(&self, name: &Object) -> Option<Object> {
+        self.globals
+            .get(name)
+            .and_then(|binding| binding.extract_pair().ok())
+            .map(|(_, value)| value)
+    }
+
+    /// Bind the global name to value. An existing binding is changed in
+    /// place, so it's seen by anything holding it, such as (where name).
+    pub fn set_global(&mut self, name: Object, value: Object) {
+        if let Object::Symbol(symbol) = name {
+            let was = self.global_form(symbol);
+            if was != self.value_form(&value) {
+                // compiled code may have treated name as the kind of
+                // thing it was, and calls to it as macro calls
+                self.compiled.clear();
+                self.chunks.clear();
+                if was == Form::Macro {
+                    self.expansions.clear();
+                }
+            }
+        }
+        match self.globals.get(&name) {
+            Some(binding) => {
+                let _ = binding.set_cdr(value);
+            }
+            None => {
+                let binding = pair!(name.clone(), value);
+                self.globals.insert(name, binding);
+            }
         }
     }
 
+    pub fn set_seed(&mut self, seed: u64) {
+        self.scheduler.reseed(seed);
+    }
+
+    // read the single object in text, with the reader macros added by syn
+    pub fn parse(&self, text: &str) -> Result<Object, Error> {
+        parser::parse_with(text, &self.syntax)
+    }
+
+    // evaluate exp in a new thread, running the scheduler until that thread
+    // finishes. Other threads get their share of time while we wait, but
+    // are not waited for.
     pub fn eval(&mut self, locals: &ObjectMap, exp: &Object) -> Result<Object, Error> {
         debug!("eval: exp = {}; locals = {}", exp, dump_object_map(locals));
-        let output = match exp {
-            Object::Symbol(name) => self.get_bound_object(locals, name)?,
-            Object::Pair(_) => self.eval_pair(locals, exp)?,
-            Object::Char(_c) => {
-                return Err(anyhow!("Object::Char not implemented"));
+        // by now the caller has usually dropped the expressions it
+        // evaluated before
+        self.prune_expansions();
+        if heap::should_collect() {
+            self.collect_garbage();
+        }
+        let id = self.scheduler.spawn(exp.clone(), Env::from_map(locals));
+        let output = self
+            .run(Some(id))?
+            .ok_or_else(|| anyhow!("eval: thread {} vanished", id))?;
+
+        trace!("eval: exp = {}; output = {}", exp, output);
+        Ok(output)
+    }
+
+    // free the pairs that can no longer be reached, which counting their
+    // handles misses when they refer to each other.
+    // Only call this between evaluations, when every thread is waiting in
+    // the scheduler
+    pub fn collect_garbage(&mut self) -> GcStats {
+        heap::collect(|tracer| {
+            for (name, value) in &self.globals {
+                tracer.mark(name);
+                tracer.mark(value);
+            }
+            for thread in &self.scheduler.threads {
+                thread.trace(tracer);
+            }
+            for (body, params, chunk) in self.compiled.values() {
+                tracer.mark(body);
+                tracer.mark(params);
+                chunk.trace(tracer);
+            }
+            for (exp, chunk) in self.chunks.values() {
+                tracer.mark(exp);
+                chunk.trace(tracer);
+            }
+            for cached in self.expansions.values() {
+                tracer.mark(&cached.call);
+                tracer.mark(&cached.mac);
+                tracer.mark(&cached.expansion);
+            }
+            for (k, continuation) in self.continuations.values() {
+                tracer.mark(k);
+                continuation.trace(tracer);
+            }
+        })
+    }
+
+    // run the scheduler until every thread has finished, then return the
+    // first error that ended a thread, if there was one
+    pub fn run_threads(&mut self) -> Result<(), Error> {
+        self.run(None)?;
+        match self.thread_error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    // run threads until the thread main_id finishes, returning its value,
+    // or until there are no threads left.
+    // An error in main_id is returned, an error in any other thread
+    // ends that thread, and is kept for run_threads to return
+    //
+    // Going over self.limits, or the interrupt flag being set, ends main_id
+    // and any threads started after it, and returns a LimitExceeded
+    // or Interrupted error
+    fn run(&mut self, main_id: Option<usize>) -> Result<Option<Object>, Error> {
+        let mut meter = Meter::new(self.limits);
+        while let Some(mut thread) = self.scheduler.pick() {
+            let quantum = self.scheduler.quantum();
+            let mut steps: u64 = 0;
+            let mut result = Ok(());
+            while !thread.is_finished() && (steps < quantum || thread.atomic > 0) {
+                let stop: Option<Error> = if self.interrupt.swap(false, Ordering::SeqCst) {
+                    Some(Interrupted.into())
+                } else {
+                    meter.step().err().map(Error::from)
+                };
+                if let Some(err) = stop {
+                    warn!("run: {}", err);
+                    let first_id = main_id.unwrap_or(0);
+                    self.scheduler.threads.retain(|t| t.id < first_id);
+                    if thread.id < first_id {
+                        self.scheduler.threads.push(thread);
+                    }
+                    return Err(err);
+                }
+                result = self
+                    .step(&mut thread)
+                    .or_else(|err| self.signal(&mut thread, err));
+                if result.is_err() {
+                    break;
+                }
+                steps += 1;
+            }
+
+            let is_main = Some(thread.id) == main_id;
+            match result {
+                Err(err) => {
+                    if is_main {
+                        return Err(err);
+                    }
+                    warn!("thread {} failed: {:?}", thread.id, err);
+                    if self.thread_error.is_none() {
+                        self.thread_error = Some(err.context(format!("thread {}", thread.id)));
+                    }
+                }
+                Ok(()) => {
+                    if !thread.is_finished() {
+                        self.scheduler.threads.push(thread);
+                    } else if is_main {
+                        return Ok(Some(thread.pop_value()?));
+                    } else {
+                        debug!("thread {} finished", thread.id);
+                    }
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    // perform the next task of thread
+    fn step(&mut self, thread: &mut Thread) -> Result<(), Error> {
+        let task = thread
+            .tasks
+            .pop()
+            .ok_or_else(|| anyhow!("thread {} has no tasks", thread.id))?;
+        trace!("step: thread {}: {:?}", thread.id, task);
+        match task {
+            Task::Eval { exp, locals } => match (self.engine, &exp) {
+                (Engine::Bytecode, Object::Pair(_)) => {
+                    let chunk = self.compiled_exp(&exp);
+                    self.step_exec(thread, chunk, 0, locals)
+                }
+                _ => self.step_eval(thread, &locals, &exp),
+            },
+            Task::If { rest, locals } => {
+                let test = thread.pop_value()?;
+                self.step_if(thread, &locals, &test, &rest)
+            }
+            Task::Collect { n, call, locals } => {
+                let args = object::from_vec(thread.pop_values(n)?)?;
+                self.step_call(thread, &locals, call, &args)
+            }
+            Task::Apply { f, args, locals } => self.apply_object(thread, &locals, &f, &args),
+            Task::EvalResult { locals } => {
+                let exp = thread.pop_value()?;
+                thread.push_task(Task::Eval { exp, locals });
+                Ok(())
+            }
+            Task::Expanded { call, mac, locals } => {
+                let expansion = thread.pop_value()?;
+                if let Some(address) = call.pair_address() {
+                    self.expansions.insert(
+                        address,
+                        Expansion {
+                            call,
+                            mac,
+                            expansion: expansion.clone(),
+                        },
+                    );
+                }
+                thread.push_task(Task::Eval {
+                    exp: expansion,
+                    locals,
+                });
+                Ok(())
+            }
+            Task::Discard => {
+                thread.pop_value()?;
+                Ok(())
+            }
+            Task::EndAtomic => {
+                thread.atomic -= 1;
+                Ok(())
+            }
+            Task::Exec { chunk, pc, locals } => self.step_exec(thread, chunk, pc, locals),
+            Task::Bind { name, exp, locals } => {
+                let value = thread.pop_value()?;
+                thread.dynamic.push((name, value));
+                thread.push_task(Task::Unbind);
+                thread.push_task(Task::Eval { exp, locals });
+                Ok(())
             }
-            Object::Stream => {
-                return Err(anyhow!("Object::Stream not implemented"));
+            Task::Unbind => {
+                thread.dynamic.pop();
+                Ok(())
             }
+        }
+    }
+
+    // An error in a thread where err is dynamically bound calls it with
+    // what went wrong, the x of (err x) or else the message, as bel.bel's
+    // sigerr does, rather than ending the thread. What it returns is the
+    // value of the step that failed.
+    fn signal(&mut self, thread: &mut Thread, err: Error) -> Result<(), Error> {
+        let handler = match thread.dynamic_value(Symbol::new("err")) {
+            Some(handler) => handler,
+            None => return Err(err),
+        };
+        let message = match err.downcast_ref::<Signal>() {
+            Some(Signal(x)) => x.clone(),
+            None => object::from_string(&format!("{:#}", err)),
         };
+        debug!("signal: thread {}: {} to {}", thread.id, message, handler);
+        self.apply_object(thread, &Env::new(), &handler, &pair!(message, nil!()))
+    }
 
-        trace!("eval: exp = {}; output = {}", exp, output);
-        Ok(output)
+    // Run compiled code from pc until an op needs other tasks to run
+    // first. The rest of the code is pushed to run after them.
+    fn step_exec(
+        &mut self,
+        thread: &mut Thread,
+        chunk: Rc<Chunk>,
+        mut pc: usize,
+        locals: Env,
+    ) -> Result<(), Error> {
+        while let Some(op) = chunk.code.get(pc) {
+            trace!("exec: thread {}: {:4} {}", thread.id, pc, op);
+            pc += 1;
+            match op {
+                Op::Const(obj) => thread.push_value(obj.clone()),
+                Op::Local(i) => {
+                    let binding = locals
+                        .slot(0, *i)
+                        .ok_or_else(|| anyhow!("exec: no local {}", i))?;
+                    let value = match binding.extract_pair()? {
+                        (Object::Symbol(name), value) => {
+                            thread.dynamic_value(name).unwrap_or(value)
+                        }
+                        (_, value) => value,
+                    };
+                    thread.push_value(value);
+                }
+                Op::Captured(depth, i, name) => {
+                    let value = thread
+                        .dynamic_value(*name)
+                        .or_else(|| locals.get_at(*depth, *i, *name));
+                    match value {
+                        Some(value) => thread.push_value(value),
+                        None => thread.push_value(self.get_bound_object(&locals, *name)?),
+                    }
+                }
+                Op::Var(name) => thread.push_value(self.variable(thread, &locals, *name)?),
+                Op::Pop => {
+                    thread.pop_value()?;
+                }
+                Op::Jump(to) => pc = *to,
+                Op::JumpIfNil(to) => {
+                    if thread.pop_value()?.is_nil() {
+                        pc = *to;
+                    }
+                }
+                Op::BeginAtomic => thread.atomic += 1,
+                Op::EndAtomic => thread.atomic -= 1,
+                Op::Call(call, n) => {
+                    let args = object::from_vec(thread.pop_values(*n)?)?;
+                    push_rest(thread, &chunk, pc, &locals);
+                    return self.step_call(thread, &locals, call.clone(), &args);
+                }
+                Op::Macro(call) => {
+                    push_rest(thread, &chunk, pc, &locals);
+                    return self.apply_macro(thread, &locals, call);
+                }
+                Op::Walk(exp) => {
+                    push_rest(thread, &chunk, pc, &locals);
+                    return self.step_eval(thread, &locals, exp);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // the compiled body of a closure, compiled the first time for the env
+    // it captured
+    fn compiled_body(&mut self, body: &Object, params: &Object, captured: &Env) -> Rc<Chunk> {
+        let key = (
+            body.pair_address().unwrap_or(0),
+            params.pair_address().unwrap_or(0),
+        );
+        if key.0 == 0 {
+            // a symbol, nothing worth keeping
+            return Rc::new(compile(self, body, params, captured));
+        }
+        if let Some((_, _, chunk)) = self.compiled.get(&key) {
+            return chunk.clone();
+        }
+        self.stats.compiles += 1;
+        let chunk = Rc::new(compile(self, body, params, captured));
+        self.compiled
+            .insert(key, (body.clone(), params.clone(), chunk.clone()));
+        chunk
+    }
+
+    // the compiled exp, compiled the first time it's evaluated
+    fn compiled_exp(&mut self, exp: &Object) -> Rc<Chunk> {
+        let address = match exp.pair_address() {
+            Some(address) => address,
+            None => return Rc::new(compile(self, exp, &nil!(), &Env::new())),
+        };
+        if let Some((_, chunk)) = self.chunks.get(&address) {
+            return chunk.clone();
+        }
+        self.stats.compiles += 1;
+        let chunk = Rc::new(compile(self, exp, &nil!(), &Env::new()));
+        // a macro call, or a form left to the tree-walker, compiles to an
+        // op holding exp, which would keep it from ever being pruned; and
+        // there's nothing to be saved by keeping it
+        if !matches!(chunk.code.as_slice(), [Op::Macro(_)] | [Op::Walk(_)]) {
+            self.chunks.insert(address, (exp.clone(), chunk.clone()));
+        }
+        chunk
+    }
+
+    /// the bytecode for the body of the function or macro name
+    pub fn compile_function(&mut self, name: &str) -> Result<Rc<Chunk>, Error> {
+        let symbol = Symbol::new(name);
+        let f = self
+            .global(&Object::Symbol(symbol))
+            .ok_or_else(|| anyhow!("unknown name {}", name))?;
+        let clo = if self.global_form(symbol) == Form::Macro {
+            f.to_vec()?
+                .get(2)
+                .cloned()
+                .ok_or_else(|| anyhow!("invalid macro {}: {}", name, f))?
+        } else {
+            f
+        };
+        let function = functions::expand_function(&clo)?;
+        let captured = Env::from_alist(&function.env)?;
+        Ok(self.compiled_body(&function.body, &function.parameters, &captured))
+    }
+
+    // what kind of expression a list with this head is
+    pub fn form_of(&self, head: &Object) -> Form {
+        let name = match head {
+            Object::Symbol(name) => *name,
+            _ => return Form::Dynamic,
+        };
+        match name.name() {
+            "set" => Form::Set,
+            "def" => Form::Def,
+            "mac" => Form::Mac,
+            "if" => Form::If,
+            "quote" => Form::Quote,
+            "lit" => Form::Lit,
+            "thread" => Form::Thread,
+            "atomic" => Form::Atomic,
+            "do" => Form::Do,
+            "fn" => Form::Fn,
+            "apply" => Form::Apply,
+            "syn" => Form::Syn,
+            "loc" => Form::Loc,
+            "bquote" => Form::Bquote,
+            "ccc" => Form::Ccc,
+            "dyn" => Form::Dyn,
+            n => match Modify::from_name(n) {
+                Some(op) => Form::Modify(op),
+                None => self.global_form(name),
+            },
+        }
+    }
+
+    // how a call to the global name is evaluated, decided by what it's
+    // bound to now
+    fn global_form(&self, name: Symbol) -> Form {
+        match self.global(&Object::Symbol(name)) {
+            Some(value) => self.value_form(&value),
+            None => Form::Dynamic,
+        }
+    }
+
+    // a primitive, closure or macro is called as one; anything else is
+    // left to be worked out when the call is made
+    fn value_form(&self, value: &Object) -> Form {
+        let v = match value.to_vec() {
+            Ok(v) if v.len() > 2 && matches!(v[0], Object::Symbol(Symbol::LIT)) => v,
+            _ => return Form::Dynamic,
+        };
+        match (&v[1], &v[2]) {
+            (Object::Symbol(Symbol::PRIM), Object::Symbol(prim))
+                if self.primatives.contains_key(prim) =>
+            {
+                Form::Primative(*prim)
+            }
+            (Object::Symbol(Symbol::CLO), _) => Form::Function,
+            (Object::Symbol(Symbol::MAC), _) => Form::Macro,
+            _ => Form::Dynamic,
+        }
+    }
+
+    /// The special forms that also have a global definition, such as
+    /// bel.bel's macros for set, zap and push. The definition is kept, but
+    /// calls use the built in form, so it's never run. apply, which is
+    /// bound to itself to be passed around, doesn't count until it's
+    /// bound to something else.
+    pub fn shadowed(&self) -> Vec<Symbol> {
+        SPECIAL_FORMS
+            .iter()
+            .map(|name| Symbol::new(name))
+            .filter(|name| {
+                let name = Object::Symbol(*name);
+                matches!(self.global(&name), Some(value) if value != name)
+            })
+            .collect()
+    }
+
+    // what a global name is; closures and macros bound with set count too
+    pub fn name_kind(&self, name: Symbol) -> NameKind {
+        let value = match self.form_of(&Object::Symbol(name)) {
+            Form::Primative(_) | Form::Function => return NameKind::Function,
+            Form::Macro => return NameKind::Macro,
+            Form::Dynamic => self.global(&Object::Symbol(name)),
+            _ => return NameKind::SpecialForm,
+        };
+        let tag = value
+            .as_ref()
+            .and_then(|value| value.to_vec().ok())
+            .filter(|v| v.len() > 2 && matches!(v[0], Object::Symbol(Symbol::LIT)))
+            .map(|v| v[1].clone());
+        match (value, tag) {
+            (None, _) => NameKind::Unbound,
+            (_, Some(Object::Symbol(Symbol::CLO | Symbol::PRIM))) => NameKind::Function,
+            (_, Some(Object::Symbol(Symbol::MAC))) => NameKind::Macro,
+            _ => NameKind::Variable,
+        }
+    }
+
+    /// Expand exp once, if it's a call to a global macro; otherwise it's
+    /// returned as it is. The args aren't expanded.
+    pub fn macroexpand_1(&mut self, exp: &Object) -> Result<Object, Error> {
+        Ok(self.expand_once(exp)?.unwrap_or_else(|| exp.clone()))
+    }
+
+    /// Expand exp until it isn't a macro call
+    pub fn macroexpand(&mut self, exp: &Object) -> Result<Object, Error> {
+        let mut exp = exp.clone();
+        while let Some(expansion) = self.expand_once(&exp)? {
+            exp = expansion;
+        }
+        Ok(exp)
+    }
+
+    /// Expand exp, and every expression in what it expands to, leaving
+    /// only special forms and calls. Quoted data and parameter lists are
+    /// left alone.
+    pub fn expand_all(&mut self, exp: &Object) -> Result<Object, Error> {
+        let exp = self.macroexpand(exp)?;
+        if !exp.is_list() || exp.is_nil() {
+            return Ok(exp);
+        }
+        let v = exp.to_vec()?;
+        // the elements before the first that's evaluated are left alone,
+        // and of set's, only the values, every other one
+        let (skip, every) = match self.form_of(&v[0]) {
+            Form::Quote | Form::Lit | Form::Syn | Form::Bquote => (v.len(), 1),
+            Form::Fn => (2, 1),
+            Form::Def | Form::Mac | Form::Loc => (3, 1),
+            Form::Set => (2, 2),
+            _ => (0, 1),
+        };
+        let mut expanded = Vec::with_capacity(v.len());
+        for (i, obj) in v.iter().enumerate() {
+            if i >= skip && (i - skip) % every == 0 {
+                expanded.push(self.expand_all(obj)?);
+            } else {
+                expanded.push(obj.clone());
+            }
+        }
+        object::from_vec(expanded)
+    }
+
+    // the expansion of exp, if it's a call to a macro bound to a global
+    fn expand_once(&mut self, exp: &Object) -> Result<Option<Object>, Error> {
+        let (head, args) = match exp {
+            Object::Pair(_) => exp.extract_pair()?,
+            _ => return Ok(None),
+        };
+        match head {
+            Object::Symbol(name) if self.name_kind(name) == NameKind::Macro => {}
+            _ => return Ok(None),
+        }
+        let m = self
+            .global(&head)
+            .ok_or_else(|| anyhow!("unknown macro {}", head))?;
+        let m_v = m.to_vec()?;
+        if m_v.len() != 3
+            || !matches!(m_v[0], Object::Symbol(Symbol::LIT))
+            || !matches!(m_v[1], Object::Symbol(Symbol::MAC))
+        {
+            return Err(anyhow!("invalid macro {}: {}", head, m));
+        }
+        trace!("expand_once: {}", exp);
+
+        // (apply '(lit clo ...) 'args), as the args aren't evaluated
+        let quote = |obj: Object| pair!(symbol!("quote"), pair!(obj, nil!()));
+        let call = object::from_vec(vec![symbol!("apply"), quote(m_v[2].clone()), quote(args)])?;
+        self.eval(&new_object_map(), &call).map(Some)
+    }
+
+    fn step_eval(&mut self, thread: &mut Thread, locals: &Env, exp: &Object) -> Result<(), Error> {
+        debug!("eval: exp = {}; locals = {}", exp, locals);
+        match exp {
+            Object::Symbol(name) => {
+                let obj = self.variable(thread, locals, *name)?;
+                thread.push_value(obj);
+                Ok(())
+            }
+            Object::Pair(_) => self.eval_pair(thread, locals, exp),
+            // chars and streams are literals, like t and nil
+            Object::Char(_) | Object::Stream => {
+                thread.push_value(exp.clone());
+                Ok(())
+            }
+        }
     }
 
-    fn get_bound_object(&self, locals: &ObjectMap, name: &str) -> Result<Object, Error> {
-        let key = symbol!(name);
-        match locals.get(&key) {
-            Some(obj) => Ok(obj.clone()),
-            None => match self.globals.get(&key) {
-                Some(obj) => Ok(obj.clone()),
+    // the value of the variable name, where the thread is evaluating; a
+    // binding made by dyn comes before the lexical and global ones
+    fn variable(&self, thread: &Thread, locals: &Env, name: Symbol) -> Result<Object, Error> {
+        match thread.dynamic_value(name) {
+            Some(value) => Ok(value),
+            None => self.get_bound_object(locals, name),
+        }
+    }
+
+    fn get_bound_object(&self, locals: &Env, name: Symbol) -> Result<Object, Error> {
+        match locals.get(name) {
+            Some(obj) => Ok(obj),
+            None => match self.global(&Object::Symbol(name)) {
+                Some(obj) => Ok(obj),
+                // unless they're bound, the global and lexical environments,
+                // as alists of their bindings
+                None if name.name() == "globe" => self.globe(),
+                None if name.name() == "scope" => Ok(locals.to_alist()),
                 None => Err(anyhow!("unbound symbol: {:?}", name)),
             },
         }
     }
 
-    fn eval_pair(&mut self, locals: &ObjectMap, pair: &Object) -> Result<Object, Error> {
+    /// The value of vmark, which marks the uvars bel.bel makes as
+    /// variables, or nil if it isn't a pair
+    pub fn vmark(&self) -> Object {
+        match self.global(&symbol!("vmark")) {
+            Some(vmark @ Object::Pair(_)) => vmark,
+            _ => nil!(),
+        }
+    }
+
+    // the global bindings, which are shared, so changing one changes
+    // the global
+    fn globe(&self) -> Result<Object, Error> {
+        object::from_vec(self.globals.values().cloned().collect())
+    }
+
+    fn eval_pair(&mut self, thread: &mut Thread, locals: &Env, pair: &Object) -> Result<(), Error> {
         let (car, cdr) = pair.extract_pair()?;
-        if let Object::Symbol(name) = car.clone() {
-            match name.as_ref() {
-                "set" => self.set(&cdr),
-                "def" => self.def(&cdr),
-                "mac" => self.mac(&cdr),
-                "if" => self.r#if(locals, &cdr),
-                "quote" => quote(&cdr),
-                "type" => {
-                    let evaluated_list = self.evaluate_list(locals, &cdr)?;
-                    self.r#type(&evaluated_list)
+        // a uvar is a variable
+        if let Object::Pair(_) = car {
+            if let Some(name) = params::uvar_name(pair, &self.vmark()) {
+                let obj = self.variable(thread, locals, name)?;
+                thread.push_value(obj);
+                return Ok(());
+            }
+        }
+        match self.form_of(&car) {
+            Form::Set => self.set(thread, locals, &cdr)?,
+            Form::Def => thread.push_value(self.def(&cdr)?),
+            Form::Mac => thread.push_value(self.mac(&cdr)?),
+            Form::If => self.r#if(thread, locals, &cdr)?,
+            Form::Quote => thread.push_value(quote(&cdr)?),
+            Form::Lit => thread.push_value(pair.clone()),
+            Form::Thread => thread.push_value(self.thread(locals, &cdr)?),
+            Form::Atomic => atomic(thread, locals, &cdr)?,
+            Form::Do => push_sequence(thread, locals, &cdr)?,
+            Form::Fn => thread.push_value(r#fn(locals, &cdr)?),
+            Form::Apply => schedule_args(thread, locals, Call::Apply, &cdr)?,
+            Form::Syn => thread.push_value(self.syn(locals, &cdr)?),
+            Form::Loc => thread.push_value(self.loc(locals, &cdr)?),
+            Form::Bquote => {
+                let template = quote(&cdr)?;
+                let commas = object::from_vec(bquote::commas(&template)?)?;
+                schedule_args(thread, locals, Call::Bquote(template), &commas)?
+            }
+            Form::Ccc => schedule_args(thread, locals, Call::Ccc, &cdr)?,
+            Form::Dyn => self.r#dyn(thread, locals, &cdr)?,
+            Form::Modify(op) => self.modify(thread, locals, &car.to_string(), op, &cdr)?,
+            Form::Primative(name) => {
+                debug!("eval: primative: {}", name);
+                schedule_args(thread, locals, Call::Primative(name), &cdr)?
+            }
+            Form::Function => schedule_args(thread, locals, Call::Function(car), &cdr)?,
+            Form::Macro => self.apply_macro(thread, locals, pair)?,
+            Form::Dynamic => schedule_args(thread, locals, Call::Dynamic, pair)?,
+        }
+
+        Ok(())
+    }
+
+    // called with the evaluated arguments of an expression
+    fn step_call(
+        &mut self,
+        thread: &mut Thread,
+        locals: &Env,
+        call: Call,
+        args: &Object,
+    ) -> Result<(), Error> {
+        match call {
+            Call::Apply => self.apply(thread, locals, args)?,
+            Call::Primative(name) => thread.push_value(self.primatives[&name](args)?),
+            Call::Function(f_name) => self.apply_function(thread, &f_name, args)?,
+            Call::Dynamic => {
+                // If the first element of the list evaluated to something we
+                // can apply, (a closure bound to a local, a primative,
+                // (lit ...) etc.) apply it.
+                // Otherwise the list evaluates to the list of its evaluated
+                // elements.
+                let (f, f_args) = args.extract_pair()?;
+                if is_applicable(&f) {
+                    self.apply_object(thread, locals, &f, &f_args)?;
+                } else {
+                    thread.push_value(args.clone());
                 }
-                n if self.primatives.contains_key(n) => {
-                    debug!("eval: primative: {}", n);
-                    let evaluated_list = self.evaluate_list(locals, &cdr)?;
-                    self.primatives[n](&evaluated_list)
+            }
+            Call::Modify { op, n_pre, places } => {
+                let mut values = args.to_vec()?.into_iter();
+                let pre: Vec<Object> = values.by_ref().take(n_pre).collect();
+                let mut resolved: Vec<Place> = Vec::new();
+                for place_exp in &places {
+                    resolved.push(places::resolve(place_exp, &mut values, &self.locfns)?);
                 }
-                n if self.function_names.contains(n) => {
-                    let evaluated_list = self.evaluate_list(locals, &cdr)?;
-                    self.apply_function(&car, &evaluated_list)
+                let post: Vec<Object> = values.collect();
+                self.modify_places(thread, locals, op, &pre, &resolved, &post)?;
+            }
+            Call::Store(place) => {
+                let (value, _) = args.extract_pair()?;
+                self.store(locals, &place, value.clone())?;
+                thread.push_value(value);
+            }
+            Call::Keep { items, keep_if } => {
+                let mut kept: Vec<Object> = Vec::new();
+                for (item, result) in items.into_iter().zip(args.to_vec()?) {
+                    if result.is_true() == keep_if {
+                        kept.push(item);
+                    }
                 }
-                n if self.macro_names.contains(n) => {
-                    let evaluated_list = self.evaluate_list(locals, &cdr)?;
-                    self.apply_macro(n, &evaluated_list)
+                thread.push_value(object::from_vec(kept)?);
+            }
+            Call::Adjoin { x, list } => {
+                if args.to_vec()?.iter().any(|result| result.is_true()) {
+                    thread.push_value(list);
+                } else {
+                    thread.push_value(pair!(x, list));
                 }
-                _ => self.evaluate_list(locals, pair),
             }
-        } else {
-            self.evaluate_list(locals, pair)
+            Call::Bquote(template) => thread.push_value(bquote::fill(&template, args.to_vec()?)?),
+            Call::Ccc => match args.to_vec()?.as_slice() {
+                [f] => {
+                    let k = self.continuation(thread);
+                    self.apply_object(thread, locals, f, &pair!(k, nil!()))?;
+                }
+                _ => return Err(anyhow!("ccc: expecting a single function: {}", args)),
+            },
         }
+
+        Ok(())
     }
 
-    fn set(&mut self, args: &Object) -> Result<Object, Error> {
-        let list = args.to_vec()?;
-        for i in 0..list.len() - 1 {
-            let key = list[i].clone();
-            if key.t() == "symbol" {
-                self.globals.insert(key, list[i + 1].clone());
-            } else {
-                return Err(anyhow!(
-                    "invalid object: expected: {} found: {}",
-                    "symbol".to_string(),
-                    list[i].t(),
-                ));
-            }
-        }
-        // append nil if the final arg isn't present
-        // an odd number of entries
-        // means the last value is unspecified
-        if list.len() % 2 == 1 {
-            let i = list.len() - 1;
-            let key = list[i].clone();
-            if key.t() == "symbol" {
-                self.globals.insert(key, nil!());
-            } else {
-                return Err(anyhow!(
-                    "invalid object: expected: {} found: {}",
-                    "symbol".to_string(),
-                    list[i].t(),
-                ));
+    // Forms that modify places.
+    // Each subexpression of the form is evaluated, then the places
+    // are resolved and modify_places does the work.
+    // The whole form is atomic, as it is in bel.bel.
+    fn modify(
+        &mut self,
+        thread: &mut Thread,
+        locals: &Env,
+        name: &str,
+        op: Modify,
+        args: &Object,
+    ) -> Result<(), Error> {
+        debug!("modify: {} {}", name, args);
+        let a_v = args.to_vec()?;
+        let arity_error = || anyhow!("{}: wrong number of arguments: {}", name, args);
+
+        // how many arguments precede the place(s), and how many places
+        let (n_pre, n_places) = match op {
+            Modify::Where | Modify::Pop if a_v.len() == 1 => (0, 1),
+            Modify::Set if a_v.len() == 2 => (0, 1),
+            Modify::Zap if a_v.len() >= 2 => (1, 1),
+            Modify::Push | Modify::Clean if a_v.len() == 2 => (1, 1),
+            Modify::Pull | Modify::PushNew if a_v.len() == 2 || a_v.len() == 3 => (1, 1),
+            Modify::Swap | Modify::Wipe => (0, a_v.len()),
+            _ => return Err(arity_error()),
+        };
+
+        let mut subexps: Vec<Object> = a_v[..n_pre].to_vec();
+        let mut place_exps: Vec<PlaceExp> = Vec::new();
+        let defined = self.global(&symbol!("locfns")).unwrap_or(nil!());
+        for exp in &a_v[n_pre..n_pre + n_places] {
+            let (place_exp, place_subexps) = places::place_exp(exp, &defined, &self.locfns)?;
+            place_exps.push(place_exp);
+            subexps.extend(place_subexps);
+        }
+        subexps.extend(a_v[n_pre + n_places..].iter().cloned());
+
+        thread.atomic += 1;
+        thread.push_task(Task::EndAtomic);
+        let call = Call::Modify {
+            op,
+            n_pre,
+            places: place_exps,
+        };
+        schedule_args(thread, locals, call, &object::from_vec(subexps)?)
+    }
+
+    // called with the evaluated arguments and resolved places of a
+    // modifying form
+    fn modify_places(
+        &mut self,
+        thread: &mut Thread,
+        locals: &Env,
+        op: Modify,
+        pre: &[Object],
+        places: &[Place],
+        post: &[Object],
+    ) -> Result<(), Error> {
+        debug!("modify_places: {:?} {:?}", op, places);
+        match op {
+            Modify::Where => thread.push_value(self.r#where(locals, &places[0])?),
+            Modify::Set => {
+                self.store(locals, &places[0], post[0].clone())?;
+                thread.push_value(post[0].clone());
+            }
+            Modify::Zap => {
+                // (apply op old args), then store the result
+                let old = self.place_value(locals, &places[0])?;
+                let f_args = pair!(old, object::from_vec(post.to_vec())?);
+                thread.push_task(Task::Collect {
+                    n: 1,
+                    call: Call::Store(places[0].clone()),
+                    locals: locals.clone(),
+                });
+                self.apply_object(thread, locals, &pre[0], &f_args)?;
+            }
+            Modify::Push => {
+                let old = self.place_value(locals, &places[0])?;
+                let new = pair!(pre[0].clone(), old);
+                self.store(locals, &places[0], new.clone())?;
+                thread.push_value(new);
+            }
+            Modify::Pull => {
+                let old = self.place_value(locals, &places[0])?;
+                match post.first() {
+                    Some(f) => {
+                        // keep the elements e where (f e x) is false
+                        thread.push_task(Task::Collect {
+                            n: 1,
+                            call: Call::Store(places[0].clone()),
+                            locals: locals.clone(),
+                        });
+                        self.call_for_each(thread, locals, f, &old, Some(&pre[0]), false)?;
+                    }
+                    None => {
+                        let mut kept = old.to_vec()?;
+                        kept.retain(|e| e != &pre[0]);
+                        let new = object::from_vec(kept)?;
+                        self.store(locals, &places[0], new.clone())?;
+                        thread.push_value(new);
+                    }
+                }
+            }
+            Modify::Pop => {
+                let old = self.place_value(locals, &places[0])?;
+                let (car, cdr) = if old.is_nil() {
+                    (nil!(), nil!())
+                } else {
+                    old.extract_pair()?
+                };
+                self.store(locals, &places[0], cdr)?;
+                thread.push_value(car);
+            }
+            Modify::Swap => {
+                // each place gets the value of the next; the last gets the first's
+                let mut values: Vec<Object> = Vec::new();
+                for place in places {
+                    values.push(self.place_value(locals, place)?);
+                }
+                for (i, place) in places.iter().enumerate() {
+                    self.store(locals, place, values[(i + 1) % values.len()].clone())?;
+                }
+                thread.push_value(values.first().cloned().unwrap_or(nil!()));
+            }
+            Modify::Wipe => {
+                for place in places {
+                    self.store(locals, place, nil!())?;
+                }
+                thread.push_value(nil!());
+            }
+            Modify::Clean => {
+                // keep the elements e where (f e) is false
+                let old = self.place_value(locals, &places[0])?;
+                thread.push_task(Task::Collect {
+                    n: 1,
+                    call: Call::Store(places[0].clone()),
+                    locals: locals.clone(),
+                });
+                self.call_for_each(thread, locals, &pre[0], &old, None, false)?;
+            }
+            Modify::PushNew => {
+                let old = self.place_value(locals, &places[0])?;
+                match post.first() {
+                    Some(f) => {
+                        thread.push_task(Task::Collect {
+                            n: 1,
+                            call: Call::Store(places[0].clone()),
+                            locals: locals.clone(),
+                        });
+                        let items = old.to_vec()?;
+                        thread.push_task(Task::Collect {
+                            n: items.len(),
+                            call: Call::Adjoin {
+                                x: pre[0].clone(),
+                                list: old,
+                            },
+                            locals: locals.clone(),
+                        });
+                        push_applications(thread, locals, f, &items, Some(&pre[0]))?;
+                    }
+                    None => {
+                        let new = if old.to_vec()?.contains(&pre[0]) {
+                            old
+                        } else {
+                            pair!(pre[0].clone(), old)
+                        };
+                        self.store(locals, &places[0], new.clone())?;
+                        thread.push_value(new);
+                    }
+                }
             }
         }
-        Ok(nil!())
+
+        Ok(())
+    }
+
+    // call (f e) or (f e x) for each element e of list, and then push
+    // the list of elements for which the result was keep_if
+    fn call_for_each(
+        &mut self,
+        thread: &mut Thread,
+        locals: &Env,
+        f: &Object,
+        list: &Object,
+        x: Option<&Object>,
+        keep_if: bool,
+    ) -> Result<(), Error> {
+        let items = list.to_vec()?;
+        thread.push_task(Task::Collect {
+            n: items.len(),
+            call: Call::Keep {
+                items: items.clone(),
+                keep_if,
+            },
+            locals: locals.clone(),
+        });
+        push_applications(thread, locals, f, &items, x)
+    }
+
+    // (cell loc), as bel.bel's where gives it; a variable's cell is its
+    // binding, the innermost local one or else the global one
+    fn r#where(&self, locals: &Env, place: &Place) -> Result<Object, Error> {
+        let (cell, loc) = match place {
+            Place::Var(name) => {
+                let binding = locals
+                    .binding(*name)
+                    .or_else(|| self.globals.get(&Object::Symbol(*name)).cloned())
+                    .ok_or_else(|| anyhow!("where: unbound symbol: {}", name))?;
+                (binding, "d")
+            }
+            Place::Car(cell) => (cell.clone(), "a"),
+            Place::Cdr(cell) => (cell.clone(), "d"),
+        };
+        object::from_vec(vec![cell, symbol!(loc)])
+    }
+
+    fn place_value(&self, locals: &Env, place: &Place) -> Result<Object, Error> {
+        match place {
+            Place::Var(name) => self.get_bound_object(locals, *name),
+            Place::Car(cell) => Ok(cell.extract_pair()?.0),
+            Place::Cdr(cell) => Ok(cell.extract_pair()?.1),
+        }
+    }
+
+    // assigning to a variable changes the local binding if there is one,
+    // otherwise the global one
+    fn store(&mut self, locals: &Env, place: &Place, value: Object) -> Result<(), Error> {
+        debug!("store: {:?} = {}", place, value);
+        match place {
+            Place::Var(name) => {
+                if !locals.set(*name, value.clone()) {
+                    self.set_global(Object::Symbol(*name), value);
+                }
+                Ok(())
+            }
+            Place::Car(cell) => cell.set_car(value),
+            Place::Cdr(cell) => cell.set_cdr(value),
+        }
+    }
+
+    // (set p1 e1 ... pn en) stores the value of each e in its place p, in
+    // turn, and gives the last value. A variable is set where it's bound,
+    // or is made a global if it isn't. A missing last e is t.
+    fn set(&mut self, thread: &mut Thread, locals: &Env, args: &Object) -> Result<(), Error> {
+        let mut a_v = args.to_vec()?;
+        if a_v.len() % 2 == 1 {
+            a_v.push(t!());
+        }
+        match a_v.len() {
+            0 => thread.push_value(nil!()),
+            2 => self.modify(thread, locals, "set", Modify::Set, &object::from_vec(a_v)?)?,
+            _ => {
+                let mut sets: Vec<Object> = Vec::new();
+                for assignment in a_v.chunks(2) {
+                    sets.push(pair!(
+                        symbol!("set"),
+                        object::from_vec(assignment.to_vec())?
+                    ));
+                }
+                push_sequence(thread, locals, &object::from_vec(sets)?)?
+            }
+        }
+        Ok(())
     }
 
     // When you see
-    //  (def n p e)
+    //  (def n p . body)
     // treat it as an abbreviation for
-    //  (set n (lit clo nil p e))
+    //  (set n '(lit clo nil p e))
+    // where e is the single expression of body, or (do . body)
     fn def(&mut self, args: &Object) -> Result<Object, Error> {
         let (name, body) = define_closure(args)?;
-        let fn_def = object::from_vec(vec![symbol!(name), body])?;
-        self.function_names.insert(name);
-        self.set(&fn_def)
+        self.set_global(Object::Symbol(name), body);
+        Ok(nil!())
     }
 
     // when you see
-    //  (mac n p e)
+    //  (mac n p . body)
     // treat it as an abbreviation for
-    //  (set n (lit mac (lit clo nil p e)))
+    //  (set n '(lit mac (lit clo nil p e)))
     fn mac(&mut self, args: &Object) -> Result<Object, Error> {
         let (name, body) = define_closure(args)?;
         let mac_body = object::from_vec(vec![symbol!("lit"), symbol!("mac"), body])?;
-        let mac_def = object::from_vec(vec![symbol!(name), mac_body])?;
-        self.macro_names.insert(name);
-        self.set(&mac_def)
+        self.set_global(Object::Symbol(name), mac_body);
+        Ok(nil!())
+    }
+
+    // (syn c (x) e) gives the char c syntax of its own: c followed by an
+    // object reads as (c x), and c is defined as a macro that expands
+    // that to e. The fn is added to syntax too, as bel.bel's syn does.
+    //
+    // bel.bel's own syns take a stream to read from, (s base share),
+    // which we don't have, so those are only added to syntax.
+    fn syn(&mut self, locals: &Env, args: &Object) -> Result<Object, Error> {
+        let list = args.to_vec()?;
+        let (c, parms, e) = match list.as_slice() {
+            [Object::Char(name), parms, e] if name.chars().count() == 1 => {
+                (name.chars().next().unwrap_or_default(), parms, e)
+            }
+            _ => return Err(anyhow!("invalid syn {}", args)),
+        };
+        if parms.to_vec().is_ok_and(|parms| parms.len() == 1) {
+            let name = Symbol::new(&c.to_string());
+            self.syntax.insert(c, move |source| {
+                let obj = source.read()?;
+                Ok(pair!(Object::Symbol(name), pair!(obj, nil!())))
+            })?;
+            self.mac(&object::from_vec(vec![
+                Object::Symbol(name),
+                parms.clone(),
+                e.clone(),
+            ])?)?;
+        } else {
+            debug!("syn: {} reads from a stream; not added to the reader", c);
+        }
+
+        let f = r#fn(locals, &args.extract_pair()?.1)?;
+        let syntax = self.global(&symbol!("syntax"));
+        self.set_global(
+            symbol!("syntax"),
+            pair!(pair!(list[0].clone(), f), syntax.unwrap_or(nil!())),
+        );
+        Ok(nil!())
+    }
+
+    // (loc name parms . body) defines the place (name a1 ... an): where,
+    // and so zap, push and the rest, call (fn parms . body) with the values
+    // of a1 ... an, and it gives the (cell loc) of the place. It's added to
+    // the front of locfns, and comes before the built in places.
+    //
+    // bel.bel's own locs are for its interpreter, and are tested against
+    // the function rather than named, so those aren't added.
+    fn loc(&mut self, locals: &Env, args: &Object) -> Result<Object, Error> {
+        let (name, rest) = args
+            .extract_pair()
+            .map_err(|_| anyhow!("loc: expecting name: {}", args))?;
+        if !matches!(name, Object::Symbol(_)) {
+            debug!("loc: {} isn't a name; not added", name);
+            return Ok(nil!());
+        }
+        let f = r#fn(locals, &rest)?;
+        let locfns = self.global(&symbol!("locfns")).unwrap_or(nil!());
+        self.set_global(symbol!("locfns"), pair!(pair!(name, f), locfns));
+        Ok(nil!())
     }
 
     // An if expression with an odd number of arguments
@@ -175,340 +1309,1544 @@ impl Bel {
     //  (if a1 a2 ... an)
     // is equivalent to
     //  (if a1 a2 ... an nil)
-    fn r#if(&mut self, locals: &ObjectMap, args: &Object) -> Result<Object, Error> {
+    fn r#if(&mut self, thread: &mut Thread, locals: &Env, args: &Object) -> Result<(), Error> {
         debug!("#if: {}", args);
         let mut list = List::new(args);
 
-        while let Some(odd_item) = list.step()? {
-            match list.step()? {
-                // even
-                Some(even_item) => {
-                    trace!("#if: even_item: {}", even_item);
-                    let x = self.eval(locals, &odd_item)?;
-                    trace!("#if: odd_item: {}", odd_item);
-                    if x.is_true() {
-                        return self.eval(locals, &even_item);
-                    }
+        match list.step()? {
+            Some(odd_item) => {
+                let rest = list.rest();
+                if rest.is_nil() {
+                    // an odd number of arguments so return 'an'
+                    thread.push_task(Task::Eval {
+                        exp: odd_item,
+                        locals: locals.clone(),
+                    });
+                } else {
+                    thread.push_task(Task::If {
+                        rest,
+                        locals: locals.clone(),
+                    });
+                    thread.push_task(Task::Eval {
+                        exp: odd_item,
+                        locals: locals.clone(),
+                    });
+                }
+            }
+            // if we make it here, we had an even number of arguments
+            // and none of the predicates was satisified
+            None => thread.push_value(nil!()),
+        }
+
+        Ok(())
+    }
+
+    // called with the value of an odd numbered argument, rest is
+    // the if expression following it
+    fn step_if(
+        &mut self,
+        thread: &mut Thread,
+        locals: &Env,
+        test: &Object,
+        rest: &Object,
+    ) -> Result<(), Error> {
+        let (even_item, rest) = rest.extract_pair()?;
+        trace!("#if: test: {}; even_item: {}", test, even_item);
+        if test.is_true() {
+            thread.push_task(Task::Eval {
+                exp: even_item,
+                locals: locals.clone(),
+            });
+            Ok(())
+        } else {
+            self.r#if(thread, locals, &rest)
+        }
+    }
+
+    // (ccc f) calls f with the continuation of the ccc expression, a
+    // (lit cont) that, called with a value, carries on from there as if
+    // ccc had given that value, dropping whatever the thread was doing
+    fn continuation(&mut self, thread: &Thread) -> Object {
+        if self.continuations.len() >= self.prune_continuations_at {
+            self.continuations.retain(|_, (k, _)| k.ref_count() > 1);
+            self.prune_continuations_at = MIN_CONTINUATIONS.max(2 * self.continuations.len());
+        }
+        let k = pair!(Object::Symbol(Symbol::LIT), pair!(symbol!("cont"), nil!()));
+        if let Some(address) = k.pair_address() {
+            self.continuations
+                .insert(address, (k.clone(), thread.continuation()));
+        }
+        k
+    }
+
+    // (dyn v e1 e2)
+    // evaluates e2 with v bound to the value of e1, for everything that's
+    // evaluated meanwhile, not just the code in e2; the binding comes
+    // before any lexical or global binding of v
+    fn r#dyn(&mut self, thread: &mut Thread, locals: &Env, args: &Object) -> Result<(), Error> {
+        match args.to_vec()?.as_slice() {
+            [Object::Symbol(name), e1, e2] => {
+                thread.push_task(Task::Bind {
+                    name: *name,
+                    exp: e2.clone(),
+                    locals: locals.clone(),
+                });
+                thread.push_task(Task::Eval {
+                    exp: e1.clone(),
+                    locals: locals.clone(),
+                });
+                Ok(())
+            }
+            _ => Err(anyhow!("dyn: cannot bind: {}", args)),
+        }
+    }
+
+    // (thread e)
+    // evaluates e in a new thread, returning nil immediately
+    fn thread(&mut self, locals: &Env, args: &Object) -> Result<Object, Error> {
+        let (exp, cdr) = args.extract_pair()?;
+        if !cdr.is_nil() {
+            return Err(anyhow!("thread: expecting a single expression: {}", args));
+        }
+        let id = self.scheduler.spawn(exp, locals.clone());
+        debug!("thread: spawned thread {}", id);
+        Ok(nil!())
+    }
+
+    // (apply f a1 ... an args)
+    // calls f with the arguments a1 ... an consed onto the list args
+    fn apply(&mut self, thread: &mut Thread, locals: &Env, args: &Object) -> Result<(), Error> {
+        debug!("apply: {}", args);
+        let mut args_v = args.to_vec()?;
+        if args_v.is_empty() {
+            return Err(anyhow!("apply: no function given"));
+        }
+        let f = args_v.remove(0);
+
+        let f_args = match args_v.pop() {
+            Some(last) => {
+                if !(last.is_nil() || last.is_list()) {
+                    return Err(anyhow!(
+                        "apply: last argument must be a list; found {}: {}",
+                        last.t(),
+                        last
+                    ));
+                }
+                let mut f_args = last;
+                for obj in args_v.into_iter().rev() {
+                    f_args = object::join(obj, f_args)?;
+                }
+                f_args
+            }
+            None => nil!(),
+        };
+
+        self.apply_object(thread, locals, &f, &f_args)
+    }
+
+    // apply an evaluated function object to a list of evaluated arguments
+    fn apply_object(
+        &mut self,
+        thread: &mut Thread,
+        locals: &Env,
+        f: &Object,
+        args: &Object,
+    ) -> Result<(), Error> {
+        debug!("apply_object: f = {}; args = {}", f, args);
+        if f.is_symbol("apply") {
+            return self.apply(thread, locals, args);
+        }
+
+        let f_v = f
+            .to_vec()
+            .map_err(|_| anyhow!("apply_object: cannot apply {}", f))?;
+        if f_v.len() < 2 || !matches!(f_v[0], Object::Symbol(Symbol::LIT)) {
+            return Err(anyhow!("apply_object: cannot apply {}", f));
+        }
+
+        match &f_v[1] {
+            Object::Symbol(Symbol::PRIM) => match f_v.get(2) {
+                Some(Object::Symbol(name)) if self.primatives.contains_key(name) => {
+                    thread.push_value(self.primatives[name](args)?);
+                    Ok(())
                 }
-                // None here indicates an odd number of arguments
-                // so return 'an'
-                None => return self.eval(locals, &odd_item),
+                _ => Err(anyhow!("apply_object: unknown primative {}", f)),
+            },
+            Object::Symbol(Symbol::CLO) => self.apply_closure(thread, f, f, args),
+            Object::Symbol(Symbol::MAC) => {
+                // as bel.bel's applym: the macro gets the args as they are,
+                // and what it expands to is evaluated
+                thread.push_task(Task::EvalResult {
+                    locals: locals.clone(),
+                });
+                self.expand_macro(thread, f, f, args)
+            }
+            Object::Symbol(tag) if tag.name() == "cont" => {
+                let (_, continuation) = f
+                    .pair_address()
+                    .and_then(|address| self.continuations.get(&address))
+                    .ok_or_else(|| anyhow!("apply_object: unknown continuation {}", f))?;
+                let value = match args.to_vec()?.as_slice() {
+                    [] => nil!(),
+                    [value] => value.clone(),
+                    _ => return Err(anyhow!("apply_object: {} takes one value: {}", f, args)),
+                };
+                thread.resume(continuation, value);
+                Ok(())
             }
+            Object::Symbol(tag) => match self.get_virfn(*tag)? {
+                Some(virfn) => {
+                    // a virtual function returns an expression that is evaluated
+                    // in place of the call
+                    let quoted_args = quote_list(args)?;
+                    let virfn_args = object::from_vec(vec![f.clone(), quoted_args])?;
+                    thread.push_task(Task::EvalResult {
+                        locals: locals.clone(),
+                    });
+                    self.apply_object(thread, locals, &virfn, &virfn_args)
+                }
+                None => Err(anyhow!("apply_object: unapplyable {}", f)),
+            },
+            _ => Err(anyhow!("apply_object: cannot apply {}", f)),
+        }
+    }
+
+    // look up tag in the alist bound to virfns
+    fn get_virfn(&self, tag: Symbol) -> Result<Option<Object>, Error> {
+        let virfns = self.get_bound_object(&Env::new(), Symbol::new("virfns"))?;
+        let mut list = List::new(&virfns);
+        while let Some(entry) = list.step()? {
+            let (key, virfn) = entry.extract_pair()?;
+            if key == Object::Symbol(tag) {
+                return Ok(Some(virfn));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn apply_function(
+        &mut self,
+        thread: &mut Thread,
+        f_name: &Object,
+        args: &Object,
+    ) -> Result<(), Error> {
+        let f = if let Some(f) = self.global(f_name) {
+            f
+        } else {
+            return Err(anyhow!("unknown function {}", f_name));
+        };
+
+        self.apply_closure(thread, f_name, &f, args)
+    }
+
+    fn apply_closure(
+        &mut self,
+        thread: &mut Thread,
+        f_name: &Object,
+        f: &Object,
+        args: &Object,
+    ) -> Result<(), Error> {
+        let function = functions::expand_function(f)?;
+
+        // the body sees the parameters, then the variables the closure
+        // captured, then globals
+        let captured = Env::from_alist(&function.env)?;
+        let (locals, defaults) =
+            merge_args_with_params(args, &function.parameters, &self.vmark(), &captured)?;
+        debug!(
+            "apply_function: f_name= {}, args= {}, locals = {}",
+            f_name, args, locals,
+        );
+
+        // the function expression should be a list, of the form
+        // (no (id (type x) 'pair))
+        // with mixed calls to primatives and to other functions
+        // the first element of the list should be the name of a function or
+        // a primative
+        match self.engine {
+            Engine::TreeWalk => thread.push_task(Task::Eval {
+                exp: function.body,
+                locals: locals.clone(),
+            }),
+            Engine::Bytecode => thread.push_task(Task::Exec {
+                chunk: self.compiled_body(&function.body, &function.parameters, &captured),
+                pc: 0,
+                locals: locals.clone(),
+            }),
+        }
+        // an optional parameter without an argument gets the value of its
+        // default, evaluated where the parameters before it are bound
+        for (binding, default) in defaults.into_iter().rev() {
+            thread.push_task(Task::Discard);
+            schedule_args(
+                thread,
+                &locals,
+                Call::Store(Place::Cdr(binding)),
+                &pair!(default, nil!()),
+            )?;
+        }
+        Ok(())
+    }
+
+    // a macro call is expanded by applying the macro's closure to the
+    // unevaluated arguments; the expansion is then evaluated in place of the call
+    // The expansion of each call is cached, so a function body that uses
+    // macros only expands them the first time it runs. The cached
+    // expansion is used for as long as the macro isn't redefined.
+    fn apply_macro(
+        &mut self,
+        thread: &mut Thread,
+        locals: &Env,
+        call: &Object,
+    ) -> Result<(), Error> {
+        let (name, args) = call.extract_pair()?;
+        let m = if let Some(m) = self.global(&name) {
+            m
+        } else {
+            return Err(anyhow!("unknown macro {}", name));
+        };
+
+        let cached = call
+            .pair_address()
+            .and_then(|address| self.expansions.get(&address))
+            .filter(|cached| cached.mac.is_same_pair(&m));
+        if let Some(cached) = cached {
+            trace!("apply_macro: cached {} => {}", call, cached.expansion);
+            self.stats.expansion_hits += 1;
+            thread.push_task(Task::Eval {
+                exp: cached.expansion.clone(),
+                locals: locals.clone(),
+            });
+            return Ok(());
+        }
+
+        debug!("apply_macro: name = {}; args = {}", name, args);
+        self.stats.expansion_misses += 1;
+        thread.push_task(Task::Expanded {
+            call: call.clone(),
+            mac: m.clone(),
+            locals: locals.clone(),
+        });
+        self.expand_macro(thread, &name, &m, &args)
+    }
+
+    // forget the expansions of calls, the compiled expressions and the
+    // continuations that nothing else refers to any more, e.g. those typed
+    // at the REPL
+    fn prune_expansions(&mut self) {
+        self.expansions
+            .retain(|_, cached| cached.call.ref_count() > 1);
+        self.chunks.retain(|_, (exp, _)| exp.ref_count() > 1);
+        self.continuations.retain(|_, (k, _)| k.ref_count() > 1);
+    }
+
+    // m should be of the form (lit mac (lit clo nil p e))
+    fn expand_macro(
+        &mut self,
+        thread: &mut Thread,
+        name: &Object,
+        m: &Object,
+        args: &Object,
+    ) -> Result<(), Error> {
+        let m_v = m.to_vec()?;
+        if m_v.len() != 3
+            || !matches!(m_v[0], Object::Symbol(Symbol::LIT))
+            || !matches!(m_v[1], Object::Symbol(Symbol::MAC))
+        {
+            return Err(anyhow!("expand_macro: invalid macro {}: {}", name, m));
+        }
+
+        self.apply_closure(thread, name, &m_v[2], args)
+    }
+}
+
+impl Default for Bel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// push a task to run the rest of chunk, from pc, if there is any
+fn push_rest(thread: &mut Thread, chunk: &Rc<Chunk>, pc: usize, locals: &Env) {
+    if pc < chunk.code.len() {
+        thread.push_task(Task::Exec {
+            chunk: chunk.clone(),
+            pc,
+            locals: locals.clone(),
+        });
+    }
+}
+
+// The quote operator returns its argument without evaluating it.
+// Its purpose is to prevent evaluation.
+fn quote(pair: &Object) -> Result<Object, Error> {
+    let (car, cdr) = pair.extract_pair()?;
+    if cdr.is_nil() {
+        Ok(car)
+    } else {
+        Err(anyhow!(
+            "quote expecting single element list; found {:?}",
+            cdr
+        ))
+    }
+}
+
+// push tasks to evaluate each element of list, then make call
+// with the results
+fn schedule_args(
+    thread: &mut Thread,
+    locals: &Env,
+    call: Call,
+    list: &Object,
+) -> Result<(), Error> {
+    let args = list.to_vec()?;
+    thread.push_task(Task::Collect {
+        n: args.len(),
+        call,
+        locals: locals.clone(),
+    });
+    for exp in args.into_iter().rev() {
+        thread.push_task(Task::Eval {
+            exp,
+            locals: locals.clone(),
+        });
+    }
+
+    Ok(())
+}
+
+// push tasks to apply f to (e) or (e x) for each e in items
+fn push_applications(
+    thread: &mut Thread,
+    locals: &Env,
+    f: &Object,
+    items: &[Object],
+    x: Option<&Object>,
+) -> Result<(), Error> {
+    for item in items.iter().rev() {
+        let mut args = vec![item.clone()];
+        if let Some(x) = x {
+            args.push(x.clone());
+        }
+        thread.push_task(Task::Apply {
+            f: f.clone(),
+            args: object::from_vec(args)?,
+            locals: locals.clone(),
+        });
+    }
+
+    Ok(())
+}
+
+// (atomic e1 ... en)
+// evaluates e1 ... en in order, returning the value of en, without
+// letting any other thread run in between
+fn atomic(thread: &mut Thread, locals: &Env, args: &Object) -> Result<(), Error> {
+    thread.atomic += 1;
+    thread.push_task(Task::EndAtomic);
+    push_sequence(thread, locals, args)
+}
+
+// (do e1 ... en)
+// evaluates e1 ... en in order, returning the value of en
+fn push_sequence(thread: &mut Thread, locals: &Env, args: &Object) -> Result<(), Error> {
+    let body = args.to_vec()?;
+    if body.is_empty() {
+        thread.push_value(nil!());
+    }
+    for (i, exp) in body.into_iter().enumerate().rev() {
+        thread.push_task(Task::Eval {
+            exp,
+            locals: locals.clone(),
+        });
+        if i > 0 {
+            thread.push_task(Task::Discard);
+        }
+    }
+
+    Ok(())
+}
+
+// (fn parms e1 ... en)
+// makes a closure that captures the current local variables
+//  (lit clo env parms e)
+// where e is (do e1 ... en) if there's more than one expression
+fn r#fn(locals: &Env, args: &Object) -> Result<Object, Error> {
+    let (parms, body) = args
+        .extract_pair()
+        .map_err(|_| anyhow!("fn: expecting parameters: {}", args))?;
+    let body_v = body.to_vec()?;
+    let e = match body_v.len() {
+        0 => nil!(),
+        1 => body_v[0].clone(),
+        _ => pair!(symbol!("do"), body),
+    };
+    object::from_vec(vec![
+        symbol!("lit"),
+        symbol!("clo"),
+        locals.to_alist(),
+        parms,
+        e,
+    ])
+}
+
+// can obj be called by apply_object
+fn is_applicable(obj: &Object) -> bool {
+    if obj.is_symbol("apply") {
+        return true;
+    }
+    match obj.extract_pair() {
+        Ok((car, _)) => matches!(car, Object::Symbol(Symbol::LIT)),
+        Err(_) => false,
+    }
+}
+
+// (a b) => ('a 'b)
+fn quote_list(list: &Object) -> Result<Object, Error> {
+    let mut accum: Vec<Object> = Vec::new();
+    for obj in list.to_vec()? {
+        accum.push(object::from_vec(vec![symbol!("quote"), obj])?);
+    }
+    object::from_vec(accum)
+}
+
+fn define_closure(list: &Object) -> Result<(Symbol, Object), Error> {
+    let (name, rest) = list
+        .extract_pair()
+        .map_err(|_| anyhow!("invalid def {:?}", list))?;
+    let name = match name {
+        Object::Symbol(name) => name,
+        _ => return Err(anyhow!("invalid def name {:?}", list)),
+    };
+    if !rest.is_list() {
+        return Err(anyhow!("invalid def {:?}", list));
+    }
+    // the parameters and the body, made into a closure as fn would
+    match r#fn(&Env::new(), &rest)?.to_vec()?.as_slice() {
+        [lit, clo, _, p, e] => Ok((
+            name,
+            object::from_vec(vec![lit.clone(), clo.clone(), nil!(), p.clone(), e.clone()])?,
+        )),
+        _ => Err(anyhow!("invalid def {:?}", list)),
+    }
+}
+
+// a new frame in front of parent binding params to args, and the
+// optional parameters whose defaults are to be evaluated in it, as
+// (binding . default)
+fn merge_args_with_params(
+    args: &Object,
+    params: &Object,
+    vmark: &Object,
+    parent: &Env,
+) -> Result<(Env, Vec<(Object, Object)>), Error> {
+    debug!(
+        "merge_args_with_params: args = {}, params = {}",
+        args, params
+    );
+    let bound = params::bind(params, args, vmark)?;
+    let locals = parent.extend(bound.bindings);
+    let defaults = bound
+        .defaults
+        .into_iter()
+        .filter_map(|(index, default)| Some((locals.slot(0, index)?, default)))
+        .collect();
+    trace!("merge_args_with_params: locals {}", locals);
+    Ok((locals, defaults))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::LimitExceeded;
+    use crate::parser;
+    use std::time::Duration;
+
+    #[test]
+    fn can_get_object() -> Result<(), Error> {
+        let mut bel = Bel::new();
+        let exp = parser::parse("t")?;
+        let obj = bel.eval(&new_object_map(), &exp)?;
+        assert_eq!(exp, obj);
+        Ok(())
+    }
+
+    #[test]
+    fn can_set_object() -> Result<(), Error> {
+        let mut bel = Bel::new();
+        let exp = parser::parse("(set a 'b)")?;
+        let obj = bel.eval(&new_object_map(), &exp)?;
+        assert_eq!(obj, symbol!("b"));
+
+        let exp = parser::parse("a")?;
+        let obj = bel.eval(&new_object_map(), &exp)?;
+        assert_eq!(obj, symbol!("b"));
+        Ok(())
+    }
+
+    #[test]
+    fn can_set_multiple() -> Result<(), Error> {
+        let mut bel = Bel::new();
+
+        let parse_obj = parser::parse("(set a 'b c 'd e 'f)")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert_eq!(obj, symbol!("f"));
+
+        for (key, val) in &[
+            ("a", "b".to_string()),
+            ("c", "d".to_string()),
+            ("e", "f".to_string()),
+        ] {
+            let parse_obj = parser::parse(key)?;
+            let obj = bel.eval(&new_object_map(), &parse_obj)?;
+            if let Object::Symbol(s) = obj {
+                assert_eq!(s.name(), val);
+            } else {
+                panic!("unexpected object {:?}", obj);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_set_multiple_with_default() -> Result<(), Error> {
+        let mut bel = Bel::new();
+
+        let parse_obj = parser::parse("(set a 'b c 'd e)")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert!(obj.is_true());
+
+        for (key, val) in &[
+            ("a", "b".to_string()),
+            ("c", "d".to_string()),
+            ("e", "t".to_string()),
+        ] {
+            let parse_obj = parser::parse(key)?;
+            let obj = bel.eval(&new_object_map(), &parse_obj)?;
+            if let Object::Symbol(s) = obj {
+                assert_eq!(s.name(), val);
+            } else {
+                panic!("unexpected object {:?}", obj);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_quote_object() -> Result<(), Error> {
+        let mut bel = Bel::new();
+
+        let parse_obj = parser::parse("(set a 'b)")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert_eq!(obj, symbol!("b"));
+
+        let parse_obj = parser::parse("(quote a)")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        if let Object::Symbol(s) = obj {
+            assert_eq!(s.name(), "a");
+        } else {
+            panic!("unexpected object {:?}", obj);
+        }
+
+        let parse_obj = parser::parse("(quote ( x ))")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert_eq!(obj, pair!(symbol!("x"), nil!()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_backquote_object() -> Result<(), Error> {
+        let mut bel = Bel::new();
+        eval_text(&mut bel, "(set xs '(b c))")?;
+
+        let obj = eval_text(&mut bel, "`(a ,(car xs) ,@xs . d)")?;
+        assert_eq!(obj, parser::parse("(a b b c . d)")?);
+        let obj = eval_text(&mut bel, "((fn (x) `(,x ,@nil)) 'a)")?;
+        assert_eq!(obj, parser::parse("(a)")?);
+        assert!(eval_text(&mut bel, "`(a ,@'b)").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_bind_optional_params() -> Result<(), Error> {
+        let mut bel = Bel::new();
+
+        // a default is evaluated after the params before it are bound
+        eval_text(&mut bel, "(def f (x (o y x) . zs) (join y zs))")?;
+        assert_eq!(eval_text(&mut bel, "(f 'a)")?, parser::parse("(a)")?);
+        assert_eq!(
+            eval_text(&mut bel, "(f 'a 'b 'c)")?,
+            parser::parse("(b c)")?
+        );
+        eval_text(&mut bel, "(def g ((x y)) y)")?;
+        assert_eq!(eval_text(&mut bel, "(g '(a b))")?, symbol!("b"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_evaluate_uvars() -> Result<(), Error> {
+        let mut bel = Bel::new();
+
+        // a list whose car is vmark is a variable, as bel.bel's uvar makes
+        eval_text(&mut bel, "(set vmark (join) v (join vmark nil))")?;
+        eval_text(&mut bel, "(mac pair-v (x) `((fn (,v) (join ,v ,v)) ,x))")?;
+        assert_eq!(
+            eval_text(&mut bel, "(pair-v 'a)")?,
+            pair!(symbol!("a"), symbol!("a"))
+        );
+        // and is unbound outside the fn that binds it
+        eval_text(&mut bel, "(mac v-itself () v)")?;
+        assert!(eval_text(&mut bel, "(v-itself)").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_def_a_function() -> Result<(), Error> {
+        let mut bel = Bel::new();
+
+        let parse_obj = parser::parse(
+            r#"(def xnox (x)
+                (id x nil))
+          "#,
+        )?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert!(obj.is_nil());
+
+        let parse_obj = parser::parse("(xnox nil)")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert!(obj.is_true());
+
+        let parse_obj = parser::parse("(xnox `a)")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert!(obj.is_nil(), "{:?}", obj);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_evaluate_if() -> Result<(), Error> {
+        let mut bel = Bel::new();
+
+        let parse_obj = parser::parse("(if t 'a 'b)")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert!(obj.is_symbol("a"));
+
+        let parse_obj = parser::parse("(if nil 'a 'b)")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert!(obj.is_symbol("b"));
+
+        let parse_obj = parser::parse("(if nil 'a)")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert!(obj.is_nil());
+
+        let parse_obj = parser::parse("(if nil 'a nil 'b 'c)")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert!(obj.is_symbol("c"));
+
+        let parse_obj = parser::parse("(if (id nil nil) 'a 'b)")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert!(obj.is_symbol("a"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_recurse_function() -> Result<(), Error> {
+        let mut bel = Bel::new();
+
+        let parse_obj = parser::parse(
+            r#"(def rrr (xs)
+                       (if nil      t
+                           (cdr xs)))
+          "#,
+        )?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert!(obj.is_nil());
+
+        let parse_obj = parser::parse("(rrr nil)")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert!(obj.is_nil());
+
+        let parse_obj = parser::parse("(rrr ('a))")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert!(obj.is_nil());
+
+        // cdr takes the cdr of its argument, not of its argument list
+        let parse_obj = parser::parse("(rrr ('a 'b 'c))")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert_eq!(obj, parser::parse("(b c)")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_apply_primative() -> Result<(), Error> {
+        let mut bel = Bel::new();
+
+        let parse_obj = parser::parse("(apply id '(a a))")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert!(obj.is_true());
+
+        let parse_obj = parser::parse("(apply id 'a '(b))")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert!(obj.is_nil());
+
+        let parse_obj = parser::parse("(apply id 'a 'a nil)")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert!(obj.is_true());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_apply_closure() -> Result<(), Error> {
+        let mut bel = Bel::new();
+
+        let parse_obj = parser::parse("(def xnox (x) (id x nil))")?;
+        bel.eval(&new_object_map(), &parse_obj)?;
+
+        let parse_obj = parser::parse("(apply xnox '(nil))")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert!(obj.is_true());
+
+        let parse_obj = parser::parse("(apply xnox 'a nil)")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert!(obj.is_nil());
+
+        // a closure passed as an argument
+        let parse_obj = parser::parse("(def call1 (f x) (apply f x nil))")?;
+        bel.eval(&new_object_map(), &parse_obj)?;
+
+        let parse_obj = parser::parse("(call1 xnox nil)")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert!(obj.is_true());
+
+        // apply applying apply
+        let parse_obj = parser::parse("(apply apply xnox '((nil)))")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert!(obj.is_true());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_apply_bracket_fn() -> Result<(), Error> {
+        let mut bel = Bel::new();
+
+        let parse_obj = parser::parse("([join _ 'b] 'a)")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert_eq!(obj, pair!(symbol!("a"), symbol!("b")));
+
+        // the inner fn's _ shadows the outer one's
+        let parse_obj = parser::parse("([apply [join _ 'c] _ nil] 'a)")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert_eq!(obj, pair!(symbol!("a"), symbol!("c")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_add_syntax() -> Result<(), Error> {
+        let mut bel = Bel::new();
+
+        // $x reads as ($ x), which expands to (quote x)
+        let parse_obj = parser::parse("(syn \\$ (x) (join 'quote (join x nil)))")?;
+        bel.eval(&new_object_map(), &parse_obj)?;
+        let parse_obj = bel.parse("(join $a $b)")?;
+        assert_eq!(
+            parse_obj.to_vec()?[1],
+            pair!(symbol!("$"), pair!(symbol!("a"), nil!()))
+        );
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert_eq!(obj, pair!(symbol!("a"), symbol!("b")));
+
+        // bel.bel's syns read from streams; they're only added to syntax
+        let parse_obj = parser::parse("(syn \\, (s base share) (rdwrap s 'comma base share))")?;
+        bel.eval(&new_object_map(), &parse_obj)?;
+        assert!(!bel.syntax.contains(','));
+        let syntax = bel.global(&symbol!("syntax")).unwrap().to_vec()?;
+        assert_eq!(syntax.len(), 2);
+        assert_eq!(syntax[0].extract_pair()?.0, char!(","));
+
+        let parse_obj = parser::parse("(syn \\( (x) x)")?;
+        let err = bel.eval(&new_object_map(), &parse_obj).unwrap_err();
+        assert!(err.to_string().contains("built into the reader"), "{}", err);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_apply_macro() -> Result<(), Error> {
+        let mut bel = Bel::new();
+
+        // the expansion of (mq e) is e
+        let parse_obj = parser::parse("(mac mq (x) x)")?;
+        bel.eval(&new_object_map(), &parse_obj)?;
+
+        let parse_obj = parser::parse("(mq (id 'a 'a))")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert!(obj.is_true());
+
+        // apply passes the args to the macro as they are, and evaluates
+        // the expansion
+        let parse_obj = parser::parse("(apply mq '((id 'a 'a)))")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert!(obj.is_true(), "{}", obj);
+
+        let parse_obj = parser::parse("(apply mq '(b))")?;
+        assert!(bel.eval(&new_object_map(), &parse_obj).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_apply_virtual_function() -> Result<(), Error> {
+        let mut bel = Bel::new();
+
+        // the virtual function for tag foo returns its (quoted) args
+        let parse_obj = parser::parse("(set virfns '((foo lit clo nil (f args) args)))")?;
+        bel.eval(&new_object_map(), &parse_obj)?;
+
+        let parse_obj = parser::parse("(apply '(lit foo) 'a '(b))")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert_eq!(obj, parser::parse("(a b)")?);
+
+        let parse_obj = parser::parse("(apply '(lit bar) '(b))")?;
+        assert!(bel.eval(&new_object_map(), &parse_obj).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn apply_reports_invalid_args() -> Result<(), Error> {
+        let mut bel = Bel::new();
+
+        let parse_obj = parser::parse("(apply id 'a 'b)")?;
+        let err = bel.eval(&new_object_map(), &parse_obj).unwrap_err();
+        assert!(
+            err.to_string().contains("last argument must be a list"),
+            "{}",
+            err
+        );
+
+        let parse_obj = parser::parse("(apply)")?;
+        assert!(bel.eval(&new_object_map(), &parse_obj).is_err());
+
+        let parse_obj = parser::parse("(apply 'a nil)")?;
+        assert!(bel.eval(&new_object_map(), &parse_obj).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_run_thread() -> Result<(), Error> {
+        let mut bel = Bel::new();
+
+        let parse_obj = parser::parse("(thread (set a 'b))")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert!(obj.is_nil());
+
+        bel.run_threads()?;
+        assert!(bel.scheduler.threads.is_empty());
+        assert_eq!(bel.global(&symbol!("a")), Some(symbol!("b")));
+
+        // an error ends the thread, but not the others, and run_threads
+        // returns it once they have all finished
+        let parse_obj = parser::parse("(thread (car 'a))")?;
+        bel.eval(&new_object_map(), &parse_obj)?;
+        let parse_obj = parser::parse("(thread (set c 'd))")?;
+        bel.eval(&new_object_map(), &parse_obj)?;
+        let err = bel.run_threads().unwrap_err();
+        assert!(format!("{:#}", err).starts_with("thread "), "{:#}", err);
+        assert!(bel.scheduler.threads.is_empty());
+        assert_eq!(bel.global(&symbol!("c")), Some(symbol!("d")));
+        // and only once
+        bel.run_threads()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_evaluate_atomic() -> Result<(), Error> {
+        let mut bel = Bel::new();
+
+        let parse_obj = parser::parse("(atomic (set a 'b) 'c)")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert!(obj.is_symbol("c"));
+        assert_eq!(bel.global(&symbol!("a")), Some(symbol!("b")));
+
+        let parse_obj = parser::parse("(atomic)")?;
+        let obj = bel.eval(&new_object_map(), &parse_obj)?;
+        assert!(obj.is_nil());
+
+        Ok(())
+    }
+
+    // run two threads that each set v and w, returning the final values
+    fn race(seed: u64, body_a: &str, body_b: &str) -> Result<(Object, Object), Error> {
+        let mut bel = Bel::new();
+        bel.set_seed(seed);
+        for body in [body_a, body_b] {
+            let parse_obj = parser::parse(&format!("(thread {})", body))?;
+            bel.eval(&new_object_map(), &parse_obj)?;
         }
+        bel.run_threads()?;
+
+        Ok((
+            bel.global(&symbol!("v")).unwrap(),
+            bel.global(&symbol!("w")).unwrap(),
+        ))
+    }
+
+    #[test]
+    fn threads_are_deterministic() -> Result<(), Error> {
+        // (do (set v 'a) (set w 'a)) sets v then w
+        let body_a = "(do (set v 'a) (set w 'a))";
+        let body_b = "(do (set v 'b) (set w 'b))";
+
+        let mut interleaved = false;
+        for seed in 0..64 {
+            let (v, w) = race(seed, body_a, body_b)?;
+            assert_eq!((v.clone(), w.clone()), race(seed, body_a, body_b)?);
+            interleaved |= v != w;
+        }
+        assert!(interleaved, "no seed interleaved the threads");
+
+        Ok(())
+    }
+
+    #[test]
+    fn atomic_blocks_preemption() -> Result<(), Error> {
+        let body_a = "(atomic (set v 'a) (set w 'a))";
+        let body_b = "(atomic (set v 'b) (set w 'b))";
+
+        for seed in 0..64 {
+            let (v, w) = race(seed, body_a, body_b)?;
+            assert_eq!(v, w, "seed {}", seed);
+        }
+
+        Ok(())
+    }
+
+    fn eval_text(bel: &mut Bel, text: &str) -> Result<Object, Error> {
+        let parse_obj = parser::parse(text)?;
+        bel.eval(&new_object_map(), &parse_obj)
+    }
+
+    #[test]
+    fn can_modify_variables() -> Result<(), Error> {
+        let mut bel = Bel::new();
+
+        // global
+        eval_text(&mut bel, "(set xs nil)")?;
+        eval_text(&mut bel, "(push 'a xs)")?;
+        let obj = eval_text(&mut bel, "(push 'b xs)")?;
+        assert_eq!(obj, parser::parse("(b a)")?);
+        assert_eq!(eval_text(&mut bel, "xs")?, obj);
+
+        let obj = eval_text(&mut bel, "(pop xs)")?;
+        assert!(obj.is_symbol("b"));
+        assert_eq!(eval_text(&mut bel, "xs")?, parser::parse("(a)")?);
+
+        // local: the global of the same name is untouched
+        eval_text(&mut bel, "(def f (xs) (if (push 'c xs) xs))")?;
+        let obj = eval_text(&mut bel, "(f '(d))")?;
+        assert_eq!(obj, parser::parse("(c d)")?);
+        assert_eq!(eval_text(&mut bel, "xs")?, parser::parse("(a)")?);
+
+        // zap applies a function to the value
+        let obj = eval_text(&mut bel, "(zap join xs 'z)")?;
+        assert_eq!(obj, pair!(parser::parse("(a)")?, symbol!("z")));
+
+        assert!(eval_text(&mut bel, "(push 'a unbound)").is_err());
+        assert!(eval_text(&mut bel, "(push 'a nil)").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_modify_pair_slots() -> Result<(), Error> {
+        let mut bel = Bel::new();
+
+        eval_text(&mut bel, "(set p '(x y))")?;
+        eval_text(&mut bel, "(push 'a (cdr p))")?;
+        assert_eq!(eval_text(&mut bel, "p")?, parser::parse("(x a y)")?);
 
-        // if we make it here, we had an even number of arguments
-        // and none of the predicates was satisified
-        Ok(nil!())
-    }
+        // the argument of f is the same pair as p
+        eval_text(&mut bel, "(def f (l) (pop (cdr l)))")?;
+        let obj = eval_text(&mut bel, "(f p)")?;
+        assert!(obj.is_symbol("a"));
+        assert_eq!(eval_text(&mut bel, "p")?, parser::parse("(x y)")?);
+
+        // where gives the cell and which half of it
+        let obj = eval_text(&mut bel, "(where (cdr p))")?;
+        let (cell, loc) = obj.extract_pair()?;
+        assert!(cell.is_same_pair(&bel.global(&symbol!("p")).unwrap()));
+        assert_eq!(loc, parser::parse("(d)")?);
+        assert!(eval_text(&mut bel, "(where (car nil))").is_err());
+
+        // a variable's cell is its binding
+        let obj = eval_text(&mut bel, "(where p)")?;
+        assert_eq!(obj, parser::parse("((p x y) d)")?);
+        eval_text(&mut bel, "(xdr (car (where p)) 'q)")?;
+        assert!(eval_text(&mut bel, "p")?.is_symbol("q"));
+        eval_text(&mut bel, "(def g (x) (do (xdr (car (where x)) 'r) x))")?;
+        assert!(eval_text(&mut bel, "(g 'a)")?.is_symbol("r"));
+        assert!(eval_text(&mut bel, "p")?.is_symbol("q"));
+        assert!(eval_text(&mut bel, "(where unbound)").is_err());
 
-    fn r#type(&mut self, args: &Object) -> Result<Object, Error> {
-        debug!("#type: {}", args);
-        // we assume we have a list like (type a), so args is a pair x . nil
-        let (car, cdr) = args.extract_pair()?;
-        if cdr.is_nil() {
-//            let obj = self.eval(locals, &car)?;
-            Ok(symbol!(car.t()))
-        } else {
-            Err(anyhow!("#type: expecting nil cdr: {}", args))
-        }
+        Ok(())
     }
 
-    fn apply_function(&mut self, f_name: &Object, args: &Object) -> Result<Object, Error> {
-        let function = if let Some(f) = self.globals.get(f_name) {
-            functions::expand_function(f_name, f)?
-        } else {
-            return Err(anyhow!("unknown function {}", f_name));
-        };
+    #[test]
+    fn can_define_places() -> Result<(), Error> {
+        let mut bel = Bel::new();
 
-        let locals = merge_args_with_params(args, &function.parameters)?;
-        debug!(
-            "apply_function: f_name= {}, args= {}, locals = {}",
-            f_name, args, dump_object_map(&locals),
-        );
+        // (second xs) is the car of the cdr of xs
+        eval_text(&mut bel, "(loc second (xs) (join (cdr xs) (join 'a nil)))")?;
+        eval_text(&mut bel, "(set p '(x y z))")?;
+        eval_text(&mut bel, "(push 'a (second p))")?;
+        let second = eval_text(&mut bel, "(car (cdr p))")?;
+        assert_eq!(second, pair!(symbol!("a"), symbol!("y")));
+        let obj = eval_text(&mut bel, "(where (second p))")?;
+        assert_eq!(obj.to_vec()?[1], symbol!("a"));
+        assert_eq!(eval_text(&mut bel, "(pop (second p))")?, symbol!("a"));
+
+        // they come before the built in places, and are in locfns
+        eval_text(&mut bel, "(loc car (xs) (join (cdr xs) (join 'd nil)))")?;
+        eval_text(&mut bel, "(wipe (car p))")?;
+        assert_eq!(eval_text(&mut bel, "p")?, parser::parse("(x y)")?);
+        assert_eq!(eval_text(&mut bel, "locfns")?.to_vec()?.len(), 2);
+
+        // bel.bel's own, for its interpreter, aren't added
+        eval_text(&mut bel, "(loc (is car) (f args a s r m) nil)")?;
+        assert_eq!(eval_text(&mut bel, "locfns")?.to_vec()?.len(), 2);
+        assert!(eval_text(&mut bel, "(loc)").is_err());
 
-        // the function expression should be a list, of the form
-        // (no (id (type x) 'pair))
-        // with mixed calls to primatives and to other functions
-        // the first element of the list should be the name of a function or
-        // a primative
-        self.eval(&locals, &function.body)
+        Ok(())
     }
 
-    fn apply_macro(&mut self, _name: &str, _args: &Object) -> Result<Object, Error> {
-        Err(anyhow!("apply_macro not implemented"))
-    }
+    #[test]
+    fn can_modify_table_entries() -> Result<(), Error> {
+        let mut bel = Bel::new();
 
-    fn evaluate_list(&mut self, locals: &ObjectMap, o: &Object) -> Result<Object, Error> {
-        debug!("evaluate_list: locals = {}; o = {}", dump_object_map(locals), o);
-        let mut accum: Object = nil!();
-        let mut list = List::new(o);
+        eval_text(&mut bel, "(set tab '(lit tab))")?;
+        eval_text(&mut bel, "(push '(a) (tabref tab 'k))")?;
+        eval_text(&mut bel, "(push 'b (cdr (tabref tab 'k)))")?;
+        let obj = eval_text(&mut bel, "(tabref tab 'k)")?;
+        assert_eq!(obj, parser::parse("((a) b)")?);
 
-        while let Some(obj) = list.step()? {
-            let eval_obj = self.eval(locals, &obj)?;
-            accum = object::join(eval_obj, accum)?;
-        }
+        // a table can be used as the head of a place
+        eval_text(&mut bel, "(push 'c (tab 'k))")?;
+        let obj = eval_text(&mut bel, "(tabref tab 'k)")?;
+        assert_eq!(obj, parser::parse("(c (a) b)")?);
 
-        Ok(accum)
-    }
-}
+        assert!(eval_text(&mut bel, "(tabref tab 'j)")?.is_nil());
+        assert!(eval_text(&mut bel, "(push 'c (p 'k))").is_err());
 
-impl Default for Bel {
-    fn default() -> Self {
-        Self::new()
+        Ok(())
     }
-}
 
-// The quote operator returns its argument without evaluating it.
-// Its purpose is to prevent evaluation.
-fn quote(pair: &Object) -> Result<Object, Error> {
-    let (car, cdr) = pair.extract_pair()?;
-    if cdr.is_nil() {
-        Ok(car)
-    } else {
-        Err(anyhow!(
-            "quote expecting single element list; found {:?}",
-            cdr
-        ))
-    }
-}
+    #[test]
+    fn can_pull_and_clean() -> Result<(), Error> {
+        let mut bel = Bel::new();
 
-fn define_closure(list: &Object) -> Result<(String, Object), Error> {
-    let args = list.to_vec()?;
-    if args.len() == 3 {
-        if let Object::Symbol(name) = args[0].clone() {
-            let p = args[1].clone();
+        eval_text(&mut bel, "(def same (x y) (id x y))")?;
+        eval_text(&mut bel, "(def isd (x) (id x 'd))")?;
+        eval_text(&mut bel, "(set xs '(a b a c))")?;
 
-            let e = args[2].clone();
-            let body = object::from_vec(vec![symbol!("lit"), symbol!("clo"), nil!(), p, e])?;
-            Ok((name, body))
-        } else {
-            Err(anyhow!("invalid def name {:?}", args))
-        }
-    } else {
-        Err(anyhow!("invalid def {:?}", args))
-    }
-}
+        let obj = eval_text(&mut bel, "(pull 'a xs)")?;
+        assert_eq!(obj, parser::parse("(b c)")?);
+        eval_text(&mut bel, "(pull 'b xs same)")?;
+        assert_eq!(eval_text(&mut bel, "xs")?, parser::parse("(c)")?);
 
-fn merge_args_with_params(args: &Object, params: &Object) -> Result<ObjectMap, Error> {
-    debug!(
-        "merge_args_with_params: args = {}, params = {}",
-        args, params
-    );
-    let mut locals: ObjectMap = HashMap::new();
+        eval_text(&mut bel, "(pushnew 'c xs)")?;
+        eval_text(&mut bel, "(pushnew 'd xs)")?;
+        eval_text(&mut bel, "(pushnew 'd xs same)")?;
+        assert_eq!(eval_text(&mut bel, "xs")?, parser::parse("(d c)")?);
 
-    let args_v = args.to_vec()?;
-    let params_v = params.to_vec()?;
+        eval_text(&mut bel, "(clean isd xs)")?;
+        assert_eq!(eval_text(&mut bel, "xs")?, parser::parse("(c)")?);
 
-    if args_v.len() > params_v.len() {
-        return Err(anyhow!(
-            "too many args: {}; for {} params",
-            args_v.len(),
-            params_v.len()
-        ));
+        Ok(())
     }
 
-    for i in 0..args_v.len() {
-        let key = params_v[i].clone();
-        if key.t() == "symbol" {
-            debug!(
-                "merge_args_with_params: args_v[{}]={}, params_v[{}]={}",
-                i, args_v[i], i, params_v[i]
-            );
-            locals.insert(key, args_v[i].clone());
-        } else {
-            return Err(anyhow!("invalid param object: {:?}", params_v[i]));
-        }
-    }
+    #[test]
+    fn can_swap_and_wipe() -> Result<(), Error> {
+        let mut bel = Bel::new();
 
-    // if we have unmatched params, fill with nil
-    if args_v.len() < params_v.len() {
-        for param in &params_v[args_v.len()..] {
-            if param.t() == "symbol" {
-                locals.insert(param.clone(), nil!());
-            } else {
-                return Err(anyhow!("invalid param object: {:?}", param));
-            }
-        }
-    }
+        eval_text(&mut bel, "(set a 'x b 'y p '(l r))")?;
+        eval_text(&mut bel, "(swap a b)")?;
+        assert!(eval_text(&mut bel, "a")?.is_symbol("y"));
+        assert!(eval_text(&mut bel, "b")?.is_symbol("x"));
 
-    trace!("merge_args_with_params: locals {}", dump_object_map(&locals));
-    Ok(locals)
-}
+        eval_text(&mut bel, "(swap (car p) (car (cdr p)))")?;
+        assert_eq!(eval_text(&mut bel, "p")?, parser::parse("(r l)")?);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::parser;
+        eval_text(&mut bel, "(wipe a (car p))")?;
+        assert!(eval_text(&mut bel, "a")?.is_nil());
+        assert_eq!(eval_text(&mut bel, "p")?, parser::parse("(nil l)")?);
+
+        Ok(())
+    }
+
+    // the limit error, if the evaluation went over a limit
+    fn limit_exceeded(bel: &mut Bel, text: &str) -> Option<LimitExceeded> {
+        eval_text(bel, text)
+            .err()
+            .and_then(|err| err.downcast_ref::<LimitExceeded>().copied())
+    }
 
     #[test]
-    fn can_get_object() -> Result<(), Error> {
+    fn can_limit_steps() -> Result<(), Error> {
         let mut bel = Bel::new();
-        let exp = parser::parse("t")?;
-        let obj = bel.eval(&new_object_map(), &exp)?;
-        assert_eq!(exp, obj);
+        bel.limits.max_steps = Some(10_000);
+
+        eval_text(&mut bel, "(def spin (x) (spin x))")?;
+        assert_eq!(
+            limit_exceeded(&mut bel, "(spin 'a)"),
+            Some(LimitExceeded::Steps(10_000))
+        );
+
+        // an ordinary error is not a limit error
+        assert!(eval_text(&mut bel, "(car a)").is_err());
+        assert_eq!(limit_exceeded(&mut bel, "(car a)"), None);
+
+        // threads started by the evaluation are ended as well
+        assert_eq!(
+            limit_exceeded(&mut bel, "(atomic (thread (spin 'b)) (spin 'a))"),
+            Some(LimitExceeded::Steps(10_000))
+        );
+        assert!(bel.scheduler.threads.is_empty());
+
+        // still usable
+        assert!(eval_text(&mut bel, "(id 'a 'a)")?.is_true());
+
         Ok(())
     }
 
     #[test]
-    fn can_set_object() -> Result<(), Error> {
+    fn can_limit_time() -> Result<(), Error> {
         let mut bel = Bel::new();
-        let exp = parser::parse("(set a b)")?;
-        let obj = bel.eval(&new_object_map(), &exp)?;
-        assert!(obj.is_nil());
+        bel.limits.max_time = Some(Duration::from_millis(50));
+
+        eval_text(&mut bel, "(def spin (x) (spin x))")?;
+        assert_eq!(
+            limit_exceeded(&mut bel, "(spin 'a)"),
+            Some(LimitExceeded::Time(Duration::from_millis(50)))
+        );
+        assert!(eval_text(&mut bel, "(id 'a 'a)")?.is_true());
 
-        let exp = parser::parse("a")?;
-        let obj = bel.eval(&new_object_map(), &exp)?;
-        assert_eq!(obj, symbol!("b"));
         Ok(())
     }
 
     #[test]
-    fn can_set_multiple() -> Result<(), Error> {
+    fn can_limit_pairs() -> Result<(), Error> {
         let mut bel = Bel::new();
+        bel.limits.max_pairs = Some(1_000);
 
-        let parse_obj = parser::parse("(set a b c d e f)")?;
-        let obj = bel.eval(&new_object_map(), &parse_obj)?;
-        assert!(obj.is_nil());
+        eval_text(&mut bel, "(def grow (x) (grow (join x x)))")?;
+        assert_eq!(
+            limit_exceeded(&mut bel, "(grow 'a)"),
+            Some(LimitExceeded::Pairs(1_000))
+        );
 
-        for (key, val) in &[
-            ("a", "b".to_string()),
-            ("c", "d".to_string()),
-            ("e", "f".to_string()),
-        ] {
-            let parse_obj = parser::parse(key)?;
-            let obj = bel.eval(&new_object_map(), &parse_obj)?;
-            if let Object::Symbol(s) = obj {
-                assert_eq!(&s, val);
-            } else {
-                panic!("unexpected object {:?}", obj);
-            }
-        }
+        // the count starts again for each evaluation
+        assert!(eval_text(&mut bel, "(join 'a 'b)")?.is_pair(symbol!("a"), symbol!("b")));
 
         Ok(())
     }
 
     #[test]
-    fn can_set_multiple_with_default() -> Result<(), Error> {
+    fn can_interrupt_evaluation() -> Result<(), Error> {
         let mut bel = Bel::new();
 
-        let parse_obj = parser::parse("(set a b c d e)")?;
-        let obj = bel.eval(&new_object_map(), &parse_obj)?;
-        assert!(obj.is_nil());
+        eval_text(&mut bel, "(def spin (x) (spin x))")?;
+        let interrupt = bel.interrupt.clone();
+        let handle = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            interrupt.store(true, Ordering::SeqCst);
+        });
+        let err = eval_text(&mut bel, "(spin 'a)").unwrap_err();
+        handle.join().unwrap();
+        assert!(err.downcast_ref::<Interrupted>().is_some(), "{}", err);
 
-        for (key, val) in &[
-            ("a", "b".to_string()),
-            ("c", "d".to_string()),
-            ("e", "nil".to_string()),
-        ] {
-            let parse_obj = parser::parse(key)?;
-            let obj = bel.eval(&new_object_map(), &parse_obj)?;
-            if let Object::Symbol(s) = obj {
-                assert_eq!(&s, val);
-            } else {
-                panic!("unexpected object {:?}", obj);
-            }
-        }
+        // the flag is cleared once the evaluation has stopped
+        assert!(!bel.interrupt.load(Ordering::SeqCst));
+        assert!(eval_text(&mut bel, "(id 'a 'a)")?.is_true());
 
         Ok(())
     }
 
     #[test]
-    fn can_quote_object() -> Result<(), Error> {
+    fn caches_macro_expansions() -> Result<(), Error> {
         let mut bel = Bel::new();
+        eval_text(&mut bel, "(mac my-quote (x) (join 'quote (join x nil)))")?;
+        eval_text(&mut bel, "(def f nil (my-quote a))")?;
 
-        let parse_obj = parser::parse("(set a b)")?;
-        let obj = bel.eval(&new_object_map(), &parse_obj)?;
-        assert!(obj.is_nil());
-
-        let parse_obj = parser::parse("(quote a)")?;
-        let obj = bel.eval(&new_object_map(), &parse_obj)?;
-        if let Object::Symbol(s) = obj {
-            assert_eq!(s, "a");
-        } else {
-            panic!("unexpected object {:?}", obj);
+        // expanded the first time the body runs, then reused
+        for _ in 0..3 {
+            assert_eq!(eval_text(&mut bel, "(f)")?, symbol!("a"));
         }
+        assert_eq!(bel.stats.expansion_misses, 1);
+        assert_eq!(bel.stats.expansion_hits, 2);
+        assert!(bel.stats.to_string().contains("66.7% hit rate"));
 
-        let parse_obj = parser::parse("(quote ( x ))")?;
-        let obj = bel.eval(&new_object_map(), &parse_obj)?;
-        assert_eq!(obj, pair!(symbol!("x"), nil!()));
+        // a call typed in isn't kept once it has been dropped
+        eval_text(&mut bel, "(my-quote b)")?;
+        eval_text(&mut bel, "t")?;
+        assert_eq!(bel.expansions.len(), 1);
+
+        // redefining the macro throws away the expansion
+        eval_text(&mut bel, "(mac my-quote (x) (join 'quote (join 'b nil)))")?;
+        assert_eq!(eval_text(&mut bel, "(f)")?, symbol!("b"));
+        assert_eq!(bel.stats.expansion_misses, 3);
 
         Ok(())
     }
 
     #[test]
-    fn can_def_a_function() -> Result<(), Error> {
+    fn compiles_expressions_once() -> Result<(), Error> {
         let mut bel = Bel::new();
-
-        let parse_obj = parser::parse(
-            r#"(def xnox (x)
-                (id x nil))
-          "#,
+        bel.engine = Engine::Bytecode;
+        eval_text(
+            &mut bel,
+            "(mac my-if (x y) (join 'if (join x (join y nil))))",
         )?;
-        let obj = bel.eval(&new_object_map(), &parse_obj)?;
-        assert!(obj.is_nil());
+        eval_text(&mut bel, "(def f (x) (my-if x 'a))")?;
+
+        // the call, f's body, my-if's body and the expansion, the first
+        // time only
+        let exp = parser::parse("(f t)")?;
+        let compiles = bel.stats.compiles;
+        for _ in 0..3 {
+            assert!(bel.eval(&new_object_map(), &exp)?.is_symbol("a"));
+        }
+        assert_eq!(bel.stats.compiles, compiles + 4);
+
+        // once f is a macro, the macro's body and the expansion; a macro
+        // call compiles to the one op, which isn't worth keeping, so the
+        // mac and each call are compiled as they're evaluated
+        eval_text(&mut bel, "(mac f (x) ''b)")?;
+        for _ in 0..3 {
+            assert!(bel.eval(&new_object_map(), &exp)?.is_symbol("b"));
+        }
+        assert_eq!(bel.stats.compiles, compiles + 10);
 
-        let parse_obj = parser::parse("(xnox nil)")?;
-        let obj = bel.eval(&new_object_map(), &parse_obj)?;
-        assert!(obj.is_true());
+        Ok(())
+    }
 
-        let parse_obj = parser::parse("(xnox `a)")?;
-        let obj = bel.eval(&new_object_map(), &parse_obj)?;
-        assert!(obj.is_nil(), "{:?}", obj);
+    #[test]
+    fn can_collect_cycles() -> Result<(), Error> {
+        let mut bel = Bel::new();
+        eval_text(&mut bel, "(set xs nil)")?;
+        eval_text(&mut bel, "(push 'a xs)")?;
+        eval_text(&mut bel, "(xdr xs xs)")?;
+        eval_text(&mut bel, "(def self nil (car xs))")?;
+        bel.collect_garbage();
+
+        // a list that contains itself survives while it is a global
+        assert_eq!(eval_text(&mut bel, "(self)")?, symbol!("a"));
+        assert_eq!(eval_text(&mut bel, "(car (cdr xs))")?, symbol!("a"));
+
+        // and is freed once it isn't
+        eval_text(&mut bel, "(set xs nil)")?;
+        assert!(bel.collect_garbage().freed >= 1);
+        assert_eq!(bel.collect_garbage().freed, 0);
+        assert_eq!(eval_text(&mut bel, "(self)")?, nil!());
 
         Ok(())
     }
 
     #[test]
-    fn can_evaluate_if() -> Result<(), Error> {
+    fn closures_capture_variables() -> Result<(), Error> {
         let mut bel = Bel::new();
 
-        let parse_obj = parser::parse("(if t 'a 'b)")?;
-        let obj = bel.eval(&new_object_map(), &parse_obj)?;
-        assert!(obj.is_symbol("a"));
+        // a closure made by fn sees the variables where it was made
+        eval_text(&mut bel, "(def adder (x) (fn (y) (join x y)))")?;
+        eval_text(&mut bel, "(set add-a (adder 'a) add-b (adder 'b))")?;
+        assert_eq!(
+            eval_text(&mut bel, "(add-a 'c)")?,
+            pair!(symbol!("a"), symbol!("c"))
+        );
+        assert_eq!(
+            eval_text(&mut bel, "(add-b 'c)")?,
+            pair!(symbol!("b"), symbol!("c"))
+        );
 
-        let parse_obj = parser::parse("(if nil 'a 'b)")?;
-        let obj = bel.eval(&new_object_map(), &parse_obj)?;
-        assert!(obj.is_symbol("b"));
+        // the captured variables are shared, not copied
+        eval_text(
+            &mut bel,
+            "(def stack (xs) (join (fn (x) (push x xs)) (fn nil xs)))",
+        )?;
+        eval_text(&mut bel, "(set s (stack nil))")?;
+        eval_text(&mut bel, "(set put (car s) get (cdr s))")?;
+        assert_eq!(
+            eval_text(&mut bel, "((fn nil (put 'a) (put 'b) (get)))")?,
+            parser::parse("(b a)")?
+        );
 
-        let parse_obj = parser::parse("(if nil 'a)")?;
-        let obj = bel.eval(&new_object_map(), &parse_obj)?;
-        assert!(obj.is_nil());
+        // the env it captured goes with it through a collection, and is
+        // freed with it
+        bel.collect_garbage();
+        assert_eq!(
+            eval_text(&mut bel, "((fn nil (put 'c) (get)))")?,
+            parser::parse("(c b a)")?
+        );
+        eval_text(&mut bel, "(set s nil put nil get nil)")?;
+        assert!(bel.collect_garbage().freed > 0);
+        assert_eq!(bel.collect_garbage().freed, 0);
 
-        let parse_obj = parser::parse("(if nil 'a nil 'b 'c)")?;
-        let obj = bel.eval(&new_object_map(), &parse_obj)?;
-        assert!(obj.is_symbol("c"));
+        // a def'd function doesn't see its caller's variables
+        eval_text(&mut bel, "(def show nil x)")?;
+        assert!(eval_text(&mut bel, "((fn (x) (show)) 'a)").is_err());
 
-        let parse_obj = parser::parse("(if (id nil nil) 'a 'b)")?;
-        let obj = bel.eval(&new_object_map(), &parse_obj)?;
-        assert!(obj.is_symbol("a"));
+        Ok(())
+    }
+
+    #[test]
+    fn can_tell_what_names_are() -> Result<(), Error> {
+        let mut bel = Bel::new();
+        for name in SPECIAL_FORMS {
+            assert_eq!(
+                bel.name_kind(Symbol::new(name)),
+                NameKind::SpecialForm,
+                "{}",
+                name
+            );
+        }
+        eval_text(&mut bel, "(def second (xs) (car (cdr xs)))")?;
+        eval_text(
+            &mut bel,
+            "(mac when (test body) (join 'if (join test (join body nil))))",
+        )?;
+        eval_text(&mut bel, "(set a 'b)")?;
+        let kinds: Vec<NameKind> = ["car", "second", "when", "a", "t", "zz"]
+            .iter()
+            .map(|name| bel.name_kind(Symbol::new(name)))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                NameKind::Function,
+                NameKind::Function,
+                NameKind::Macro,
+                NameKind::Variable,
+                NameKind::Variable,
+                NameKind::Unbound
+            ]
+        );
 
         Ok(())
     }
 
     #[test]
-    fn can_recurse_function() -> Result<(), Error> {
+    fn special_forms_shadow_definitions() -> Result<(), Error> {
         let mut bel = Bel::new();
+        assert!(bel.shadowed().is_empty());
 
-        let parse_obj = parser::parse(
-            r#"(def rrr (xs)
-                       (if nil      t
-                           (cdr xs))
-          "#,
-        )?;
-        let obj = bel.eval(&new_object_map(), &parse_obj)?;
-        assert!(obj.is_nil());
+        // a macro named after a built in form is bound, but not called
+        eval_text(&mut bel, "(mac push (x place) ''mine)")?;
+        eval_text(&mut bel, "(set xs nil)")?;
+        assert_eq!(eval_text(&mut bel, "(push 'a xs)")?, parser::parse("(a)")?);
+        assert_eq!(bel.name_kind(Symbol::new("push")), NameKind::SpecialForm);
+        assert_eq!(bel.shadowed(), vec![Symbol::new("push")]);
 
-        let parse_obj = parser::parse("(rrr nil)")?;
-        let obj = bel.eval(&new_object_map(), &parse_obj)?;
-        assert!(obj.is_nil());
+        Ok(())
+    }
 
-        let parse_obj = parser::parse("(rrr ('a))")?;
-        let obj = bel.eval(&new_object_map(), &parse_obj)?;
-        assert!(obj.is_nil());
+    #[test]
+    fn calls_names_as_what_they_are_bound_to() -> Result<(), Error> {
+        for engine in [Engine::TreeWalk, Engine::Bytecode] {
+            let mut bel = Bel::new();
+            bel.engine = engine;
+
+            // a function redefined as a macro is called as one, by code
+            // compiled before and after
+            eval_text(&mut bel, "(def f (x) 'fn)")?;
+            eval_text(&mut bel, "(def call-f () (f 'a))")?;
+            assert!(eval_text(&mut bel, "(call-f)")?.is_symbol("fn"));
+            eval_text(&mut bel, "(mac f (x) ''mac)")?;
+            assert!(eval_text(&mut bel, "(f a)")?.is_symbol("mac"));
+            assert!(eval_text(&mut bel, "(call-f)")?.is_symbol("mac"));
+            assert_eq!(bel.name_kind(Symbol::new("f")), NameKind::Macro);
+
+            // and back again
+            eval_text(&mut bel, "(def f (x) x)")?;
+            assert!(eval_text(&mut bel, "(call-f)")?.is_symbol("a"));
+
+            // a function set to something else isn't called as one; like
+            // any call of what can't be applied, it's the list of values
+            eval_text(&mut bel, "(def g (x) x)")?;
+            eval_text(&mut bel, "(set g 'z)")?;
+            assert_eq!(eval_text(&mut bel, "(g 'a)")?, parser::parse("(z a)")?);
+            assert_eq!(bel.name_kind(Symbol::new("g")), NameKind::Variable);
+
+            // a macro made with set is called as one
+            eval_text(&mut bel, "(set m3 '(lit mac (lit clo nil (x) `',x)))")?;
+            assert!(eval_text(&mut bel, "(m3 foo)")?.is_symbol("foo"));
+            assert_eq!(bel.name_kind(Symbol::new("m3")), NameKind::Macro);
+        }
 
-        let parse_obj = parser::parse("(rrr ('a 'b 'c))")?;
-        let obj = bel.eval(&new_object_map(), &parse_obj)?;
-        assert!(obj.is_nil());
+        Ok(())
+    }
+
+    #[test]
+    fn can_expand_macros() -> Result<(), Error> {
+        let mut bel = Bel::new();
+        // there's no list without bel.bel
+        let when = "(mac when (test body) (join 'if (join test (join body nil))))";
+        let unless = "(mac unless (test body)
+                        (join 'when (join (join 'no (join test nil)) (join body nil))))";
+        eval_text(&mut bel, when)?;
+        eval_text(&mut bel, unless)?;
+        let expand = |bel: &mut Bel, f: fn(&mut Bel, &Object) -> Result<Object, Error>, text| {
+            let exp = parser::parse(text)?;
+            f(bel, &exp).map(|obj| obj.to_string())
+        };
+        let parse = |text| parser::parse(text).map(|obj| obj.to_string());
+
+        let text = "(unless a (unless b c))";
+        assert_eq!(
+            expand(&mut bel, Bel::macroexpand_1, text)?,
+            parse("(when (no a) (unless b c))")?
+        );
+        assert_eq!(
+            expand(&mut bel, Bel::macroexpand, text)?,
+            parse("(if (no a) (unless b c))")?
+        );
+        assert_eq!(
+            expand(&mut bel, Bel::expand_all, text)?,
+            parse("(if (no a) (if (no b) c))")?
+        );
+        // not macro calls
+        assert_eq!(
+            expand(&mut bel, Bel::macroexpand, "(car x)")?,
+            parse("(car x)")?
+        );
+        assert_eq!(expand(&mut bel, Bel::macroexpand_1, "a")?, parse("a")?);
+
+        // quoted data and parameters aren't expanded
+        let text = "(fn (when) (when '(when a b) (set x (when c d))))";
+        assert_eq!(
+            expand(&mut bel, Bel::expand_all, text)?,
+            parse("(fn (when) (if '(when a b) (set x (if c d))))")?
+        );
+        let text = "(def f (when) (when a b))";
+        assert_eq!(
+            expand(&mut bel, Bel::expand_all, text)?,
+            parse("(def f (when) (if a b))")?
+        );
+        // errors expanding are passed on
+        eval_text(&mut bel, "(mac broken (x) (car x))")?;
+        assert!(expand(&mut bel, Bel::macroexpand, "(broken a)").is_err());
 
         Ok(())
     }
@@ -519,9 +2857,9 @@ mod tests {
         let args = object::from_vec(args_v)?;
         let params_v = vec![symbol!("x")];
         let params = object::from_vec(params_v)?;
-        let locals = merge_args_with_params(&args, &params)?;
-        assert!(locals.contains_key(&symbol!("x")));
-        assert!(locals.get(&symbol!("x")) == Some(&symbol!("a")));
+        let (locals, _) = merge_args_with_params(&args, &params, &nil!(), &Env::new())?;
+        assert!(locals.contains(Symbol::new("x")));
+        assert!(locals.get(Symbol::new("x")) == Some(symbol!("a")));
 
         let l1_v = vec![symbol!("a")];
         let l1 = object::from_vec(l1_v)?;
@@ -529,12 +2867,110 @@ mod tests {
         let args = object::from_vec(args_v)?;
         let params_v = vec![symbol!("f"), symbol!("xs")];
         let params = object::from_vec(params_v)?;
-        let locals = merge_args_with_params(&args, &params)?;
-        assert!(locals.contains_key(&symbol!("f")));
-        assert!(locals.get(&symbol!("f")) == Some(&symbol!("no")));
-        assert!(locals.contains_key(&symbol!("xs")));
-        assert!(locals.get(&symbol!("xs")) == Some(&pair!(symbol!("a"), nil!())));
-        
+        let (locals, _) = merge_args_with_params(&args, &params, &nil!(), &locals)?;
+        assert!(locals.contains(Symbol::new("f")));
+        assert!(locals.get(Symbol::new("f")) == Some(symbol!("no")));
+        assert!(locals.contains(Symbol::new("xs")));
+        assert!(locals.get(Symbol::new("xs")) == Some(pair!(symbol!("a"), nil!())));
+        // the outer frame is still visible
+        assert!(locals.get(Symbol::new("x")) == Some(symbol!("a")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_call_continuations() -> Result<(), Error> {
+        for engine in [Engine::TreeWalk, Engine::Bytecode] {
+            let mut bel = Bel::new();
+            bel.engine = engine;
+
+            // calling k gives ccc the value, dropping what f was doing
+            assert_eq!(
+                eval_text(&mut bel, "(join 'a (ccc (fn (k) (join 'b (k 'c)))))")?,
+                parser::parse("(a . c)")?
+            );
+            // otherwise ccc gives what f returns
+            assert_eq!(
+                eval_text(&mut bel, "(join 'a (ccc (fn (k) 'b)))")?,
+                parser::parse("(a . b)")?
+            );
+
+            // a continuation can be called after ccc has returned, and
+            // carries on from there again
+            eval_text(&mut bel, "(set x (join (ccc (fn (k) (set k2 k) 'a)) nil))")?;
+            assert_eq!(eval_text(&mut bel, "x")?, parser::parse("(a)")?);
+            assert_eq!(eval_text(&mut bel, "(k2 'b)")?, parser::parse("(b)")?);
+            assert_eq!(eval_text(&mut bel, "x")?, parser::parse("(b)")?);
+
+            assert!(eval_text(&mut bel, "(ccc)").is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_bind_dynamically() -> Result<(), Error> {
+        for engine in [Engine::TreeWalk, Engine::Bytecode] {
+            let mut bel = Bel::new();
+            bel.engine = engine;
+
+            // the binding is seen by whatever is called meanwhile, before
+            // any lexical or global one
+            eval_text(&mut bel, "(set x 'global)")?;
+            eval_text(&mut bel, "(def f () x)")?;
+            eval_text(&mut bel, "(def g (x) x)")?;
+            assert!(eval_text(&mut bel, "(dyn x 'a (f))")?.is_symbol("a"));
+            assert!(eval_text(&mut bel, "(dyn x 'a (g 'b))")?.is_symbol("a"));
+            assert!(eval_text(&mut bel, "(f)")?.is_symbol("global"));
+
+            // and is dropped when a continuation jumps out of it
+            eval_text(&mut bel, "(set y (ccc (fn (k) (dyn x 'a (k 'out)))))")?;
+            assert!(eval_text(&mut bel, "(f)")?.is_symbol("global"));
+
+            assert!(eval_text(&mut bel, "(dyn (x) 'a x)").is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn calls_dynamically_bound_err() -> Result<(), Error> {
+        for engine in [Engine::TreeWalk, Engine::Bytecode] {
+            let mut bel = Bel::new();
+            bel.engine = engine;
+
+            // as bel.bel's eif does, escaping with what went wrong
+            let caught = "(ccc (fn (k) (dyn err (fn (x) (k (join 'caught x))) {})))";
+            assert_eq!(
+                eval_text(&mut bel, &caught.replace("{}", "(err 'oops)"))?,
+                parser::parse("(caught . oops)")?
+            );
+            // any other error is passed on as its message
+            let value = eval_text(&mut bel, &caught.replace("{}", "(car 'a)"))?;
+            assert_eq!(value.extract_pair()?.0, symbol!("caught"));
+            assert!(value.extract_pair()?.1.is_list());
+
+            // without a handler the error ends the evaluation
+            assert!(eval_text(&mut bel, "(err 'oops)").is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn lits_evaluate_to_themselves() -> Result<(), Error> {
+        for engine in [Engine::TreeWalk, Engine::Bytecode] {
+            let mut bel = Bel::new();
+            bel.engine = engine;
+
+            assert_eq!(
+                eval_text(&mut bel, "(lit a b)")?,
+                parser::parse("(lit a b)")?
+            );
+            assert!(eval_text(&mut bel, "((lit clo nil (x) x) 'a)")?.is_symbol("a"));
+            assert!(eval_text(&mut bel, "(type 'a)")?.is_symbol("symbol"));
+        }
+
         Ok(())
     }
 }
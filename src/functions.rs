@@ -1,32 +1,32 @@
 use crate::list::List;
 use crate::object::Object;
+use crate::symbol::Symbol;
 use anyhow::{anyhow, Error, Result};
 
 pub struct Function {
-    pub name: String,
+    /// the alist of variables the closure captured
+    pub env: Object,
     pub parameters: Object,
     pub body: Object,
 }
 
-pub fn expand_function(f_name: &Object, f_obj: &Object) -> Result<Function, Error> {
+pub fn expand_function(f_obj: &Object) -> Result<Function, Error> {
     let mut list = List::new(f_obj);
 
     // we expect the function to contain 5 items
-    // starting with the symbols lit clo nil
-    for &name in &["lit", "clo", "nil"] {
+    // starting with the symbols lit clo
+    for expected in [Symbol::LIT, Symbol::CLO] {
         match list.step()? {
+            Some(Object::Symbol(symbol)) if symbol == expected => {}
+            Some(obj @ Object::Symbol(_)) => {
+                return Err(anyhow!(
+                    "expand_function: unexpected symbol: {:?}; expected {}",
+                    obj,
+                    expected
+                ));
+            }
             Some(obj) => {
-                if let Object::Symbol(symbol_name) = obj.clone() {
-                    if symbol_name != name {
-                        return Err(anyhow!(
-                            "expand_function: unexpected symbol: {:?}; expected {}",
-                            obj,
-                            name
-                        ));
-                    }
-                } else {
-                    return Err(anyhow!("expand_function: unexpected object: {:?}", obj));
-                }
+                return Err(anyhow!("expand_function: unexpected object: {:?}", obj));
             }
             None => {
                 return Err(anyhow!("expand_function: unexpected end of list"));
@@ -34,6 +34,13 @@ pub fn expand_function(f_name: &Object, f_obj: &Object) -> Result<Function, Erro
         }
     }
 
+    // then the env, nil unless the closure was made by fn
+    let env = if let Some(obj) = list.step()? {
+        obj
+    } else {
+        return Err(anyhow!("expand_function: fn list terminates before env"));
+    };
+
     // function parameters should be next in the list fourth item, index 3
     let parameters = if let Some(obj) = list.step()? {
         obj
@@ -51,7 +58,7 @@ pub fn expand_function(f_name: &Object, f_obj: &Object) -> Result<Function, Erro
     };
 
     Ok(Function {
-        name: f_name.to_string(),
+        env,
         parameters,
         body,
     })
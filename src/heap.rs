@@ -0,0 +1,482 @@
+use crate::env::{Env, Frame};
+use crate::object::Object;
+use log::debug;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+
+/// A handle to a pair in the heap: the index of the slot holding it.
+///
+/// Handles are counted: cloning one adds to its slot's count and dropping
+/// one takes from it, and the slot is emptied and reused once the count
+/// gets to zero.
+pub struct PairRef(usize);
+
+impl PairRef {
+    pub fn new(car: Object, cdr: Object) -> Self {
+        HEAP.with(|heap| PairRef(heap.allocate((car, cdr))))
+    }
+
+    // the slot holding the pair, which identifies it while it's alive
+    pub fn index(&self) -> usize {
+        self.0
+    }
+
+    pub fn get(&self) -> (Object, Object) {
+        HEAP.with(|heap| heap.slots.borrow()[self.0].pair.clone())
+    }
+
+    // how many handles there are to the pair
+    pub fn handles(&self) -> usize {
+        HEAP.with(|heap| heap.slots.borrow()[self.0].handles.get())
+    }
+
+    pub fn set_car(&self, obj: Object) {
+        let old = HEAP.with(|heap| mem::replace(&mut heap.slots.borrow_mut()[self.0].pair.0, obj));
+        // dropped here, with the heap free to take back anything it held
+        drop(old);
+    }
+
+    pub fn set_cdr(&self, obj: Object) {
+        let old = HEAP.with(|heap| mem::replace(&mut heap.slots.borrow_mut()[self.0].pair.1, obj));
+        drop(old);
+    }
+
+    // the env the pair, an alist, was made from, if it was
+    pub fn env(&self) -> Option<Env> {
+        HEAP.with(|heap| heap.slots.borrow()[self.0].env.clone())
+    }
+
+    pub fn set_env(&self, env: Env) {
+        let old = HEAP.with(|heap| heap.slots.borrow_mut()[self.0].env.replace(env));
+        drop(old);
+    }
+}
+
+impl Clone for PairRef {
+    fn clone(&self) -> Self {
+        HEAP.with(|heap| {
+            let handles = &heap.slots.borrow()[self.0].handles;
+            handles.set(handles.get() + 1);
+        });
+        PairRef(self.0)
+    }
+}
+
+impl Drop for PairRef {
+    fn drop(&mut self) {
+        // the heap has gone if the (OS) thread is ending, along with
+        // everything in it
+        let _ = HEAP.try_with(|heap| heap.release(self.0));
+    }
+}
+
+// where a pair is kept
+struct Slot {
+    pair: (Object, Object),
+    // how many PairRefs there are to this slot; 0 if it's free
+    handles: Cell<usize>,
+    // the env a closure's env alist was made from, so calling the closure
+    // can go on with it; see env.rs
+    env: Option<Env>,
+}
+
+/// What a pair costs: its slot in the heap
+pub const PAIR_BYTES: usize = mem::size_of::<Slot>();
+
+// don't collect automatically until at least this many pairs have been made
+const MIN_COLLECT: u64 = 100_000;
+
+/// The heap: an arena of slots holding every pair made on this (OS) thread.
+///
+/// A pair is freed as soon as the last handle to it goes, so most are
+/// freed without the collector. Pairs that refer to each other (made by
+/// xdr, by closures capturing their own environment, by tables that
+/// contain themselves) keep each other's handles alive; the collector
+/// traces what can be reached and frees the rest. The frames of the env
+/// kept with an alist are traced as part of its slot.
+struct Heap {
+    slots: RefCell<Vec<Slot>>,
+    // the slots that can be reused
+    free: RefCell<Vec<usize>>,
+    // the slots whose last handle has gone, waiting to be emptied
+    released: RefCell<Vec<usize>>,
+    // whether released slots are being emptied
+    emptying: Cell<bool>,
+    // pairs made since the last collection
+    allocated: Cell<u64>,
+    // pairs alive after the last collection
+    live: Cell<usize>,
+}
+
+impl Heap {
+    fn new() -> Self {
+        Heap {
+            slots: RefCell::new(Vec::new()),
+            free: RefCell::new(Vec::new()),
+            released: RefCell::new(Vec::new()),
+            emptying: Cell::new(false),
+            allocated: Cell::new(0),
+            live: Cell::new(0),
+        }
+    }
+
+    // put pair in a free slot, or a new one
+    fn allocate(&self, pair: (Object, Object)) -> usize {
+        self.allocated.set(self.allocated.get() + 1);
+        let free = self.free.borrow_mut().pop();
+        let mut slots = self.slots.borrow_mut();
+        match free {
+            Some(index) => {
+                // free slots only hold nils, which don't need the heap to drop
+                slots[index].pair = pair;
+                slots[index].handles.set(1);
+                index
+            }
+            None => {
+                slots.push(Slot {
+                    pair,
+                    handles: Cell::new(1),
+                    env: None,
+                });
+                slots.len() - 1
+            }
+        }
+    }
+
+    fn release(&self, index: usize) {
+        let handles = {
+            let slots = self.slots.borrow();
+            let handles = &slots[index].handles;
+            handles.set(handles.get() - 1);
+            handles.get()
+        };
+        if handles == 0 {
+            self.released.borrow_mut().push(index);
+            self.empty_released();
+        }
+    }
+
+    // Empty the released slots and free them. Emptying a slot can release
+    // the slots it refers to, which are emptied in turn here, rather than
+    // by recursing, so freeing a long list doesn't run out of stack.
+    fn empty_released(&self) {
+        if self.emptying.replace(true) {
+            return;
+        }
+        loop {
+            let index = match self.released.borrow_mut().pop() {
+                Some(index) => index,
+                None => break,
+            };
+            let emptied = {
+                let mut slots = self.slots.borrow_mut();
+                let slot = &mut slots[index];
+                (
+                    mem::replace(&mut slot.pair, (nil!(), nil!())),
+                    slot.env.take(),
+                )
+            };
+            self.free.borrow_mut().push(index);
+            drop(emptied);
+        }
+        self.emptying.set(false);
+    }
+
+    fn live_pairs(&self) -> usize {
+        self.slots.borrow().len() - self.free.borrow().len()
+    }
+
+    // How many handles to each slot are held by other slots, and by the
+    // frames of the envs kept in them. Gives too the frames those envs
+    // hold that are held from outside the heap as well.
+    fn internal_handles(&self) -> (Vec<usize>, Vec<Env>) {
+        let slots = self.slots.borrow();
+        let mut internal = vec![0; slots.len()];
+        let mut count = |obj: &Object| {
+            if let Object::Pair(pair) = obj {
+                internal[pair.index()] += 1;
+            }
+        };
+        // the frames reachable from slots, and how many of the references
+        // to each are from slots or other frames
+        let mut frames: HashMap<*const Frame, (Env, usize)> = HashMap::new();
+        for slot in slots.iter() {
+            count(&slot.pair.0);
+            count(&slot.pair.1);
+            let mut env = slot.env.clone();
+            while let Some(frame) = env.as_ref().and_then(|env| env.frame()) {
+                let seen = frames.contains_key(&Rc::as_ptr(frame));
+                frames
+                    .entry(Rc::as_ptr(frame))
+                    .or_insert_with(|| (Env::from_frame(frame), 0))
+                    .1 += 1;
+                if seen {
+                    break;
+                }
+                frame.objects().for_each(&mut count);
+                env = Some(frame.parent().clone());
+            }
+        }
+        let held = frames
+            .into_values()
+            .filter(|(env, refs)| {
+                // less the reference in env itself
+                env.frame()
+                    .is_some_and(|frame| Rc::strong_count(frame) - 1 > *refs)
+            })
+            .map(|(env, _)| env)
+            .collect();
+        (internal, held)
+    }
+}
+
+thread_local! {
+    static HEAP: Heap = Heap::new();
+}
+
+// the number of pairs currently alive
+pub fn live_pairs() -> usize {
+    HEAP.with(|heap| heap.live_pairs())
+}
+
+// whether enough pairs have been made since the last collection to make
+// another worthwhile
+pub fn should_collect() -> bool {
+    HEAP.with(|heap| heap.allocated.get() >= MIN_COLLECT.max(heap.live.get() as u64))
+}
+
+/// What a collection found
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcStats {
+    /// pairs still alive
+    pub live: usize,
+    /// pairs freed by breaking cycles
+    pub freed: usize,
+    /// slots in the heap, in use or free
+    pub slots: usize,
+}
+
+impl GcStats {
+    pub fn live_bytes(&self) -> usize {
+        self.live * PAIR_BYTES
+    }
+}
+
+impl fmt::Display for GcStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "live: {} pairs ({} bytes); freed: {} pairs; heap: {} slots ({} bytes)",
+            self.live,
+            self.live_bytes(),
+            self.freed,
+            self.slots,
+            self.slots * PAIR_BYTES
+        )
+    }
+}
+
+/// Marks the pairs reachable from the objects it is given
+pub struct Tracer {
+    marked: Vec<bool>,
+    stack: Vec<usize>,
+    // the frames whose pairs have been marked
+    frames: HashSet<*const Frame>,
+}
+
+impl Tracer {
+    fn new(slots: usize) -> Self {
+        Tracer {
+            marked: vec![false; slots],
+            stack: Vec::new(),
+            frames: HashSet::new(),
+        }
+    }
+
+    pub fn mark(&mut self, obj: &Object) {
+        if let Object::Pair(pair) = obj {
+            self.stack.push(pair.index());
+            self.mark_stacked();
+        }
+    }
+
+    // mark the pairs held by the frames of env
+    pub fn mark_env(&mut self, env: &Env) {
+        self.stack_env(env);
+        self.mark_stacked();
+    }
+
+    fn stack_env(&mut self, env: &Env) {
+        let mut env = env;
+        while let Some(frame) = env.frame() {
+            if !self.frames.insert(Rc::as_ptr(frame)) {
+                break;
+            }
+            for obj in frame.objects() {
+                if let Object::Pair(pair) = obj {
+                    self.stack.push(pair.index());
+                }
+            }
+            env = frame.parent();
+        }
+    }
+
+    fn mark_stacked(&mut self) {
+        HEAP.with(|heap| {
+            let slots = heap.slots.borrow();
+            while let Some(index) = self.stack.pop() {
+                if !mem::replace(&mut self.marked[index], true) {
+                    let slot = &slots[index];
+                    for obj in [&slot.pair.0, &slot.pair.1] {
+                        if let Object::Pair(pair) = obj {
+                            self.stack.push(pair.index());
+                        }
+                    }
+                    if let Some(env) = &slot.env {
+                        self.stack_env(env);
+                    }
+                }
+            }
+        })
+    }
+}
+
+// Find the pairs that can't be reached and free them.
+//
+// roots marks everything the interpreter holds: globals, the threads'
+// stacks and so on. Pairs held by the host (by handles anywhere other
+// than in another pair) are roots too. They are found by counting: a slot
+// with more handles than there are in other slots is held from outside
+// the heap. What's left can only be reached from itself, and is freed by
+// emptying the slots, which releases the handles they held.
+pub fn collect(roots: impl FnOnce(&mut Tracer)) -> GcStats {
+    HEAP.with(|heap| {
+        let mut tracer = Tracer::new(heap.slots.borrow().len());
+        roots(&mut tracer);
+
+        let (internal, held_frames) = heap.internal_handles();
+        let held: Vec<usize> = heap
+            .slots
+            .borrow()
+            .iter()
+            .enumerate()
+            .filter(|(i, slot)| slot.handles.get() > internal[*i])
+            .map(|(i, _)| i)
+            .collect();
+        tracer.stack.extend(held);
+        tracer.mark_stacked();
+        for env in &held_frames {
+            tracer.mark_env(env);
+        }
+        drop(held_frames);
+
+        let garbage: Vec<((Object, Object), Option<Env>)> = heap
+            .slots
+            .borrow_mut()
+            .iter_mut()
+            .enumerate()
+            .filter(|(i, slot)| slot.handles.get() > 0 && !tracer.marked[*i])
+            .map(|(_, slot)| {
+                (
+                    mem::replace(&mut slot.pair, (nil!(), nil!())),
+                    slot.env.take(),
+                )
+            })
+            .collect();
+        let freed = garbage.len();
+        drop(garbage);
+
+        let stats = GcStats {
+            live: heap.live_pairs(),
+            freed,
+            slots: heap.slots.borrow().len(),
+        };
+        heap.allocated.set(0);
+        heap.live.set(stats.live);
+        debug!("collect: {}", stats);
+        stats
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbol::Symbol;
+    use anyhow::Error;
+
+    #[test]
+    fn can_collect_cycles() -> Result<(), Error> {
+        let held = pair!(symbol!("a"), nil!());
+        held.set_cdr(held.clone())?;
+
+        let rooted = pair!(symbol!("b"), nil!());
+        rooted.set_cdr(pair!(symbol!("c"), rooted.clone()))?;
+
+        let garbage = pair!(symbol!("d"), nil!());
+        garbage.set_cdr(pair!(symbol!("e"), garbage.clone()))?;
+        drop(garbage);
+        assert_eq!(live_pairs(), 5);
+
+        let stats = collect(|tracer| tracer.mark(&rooted));
+        assert_eq!(stats.freed, 2);
+        assert_eq!(stats.live, 3);
+        assert_eq!(live_pairs(), 3);
+
+        // held by us rather than a root, but still alive
+        assert_eq!(held.ref_count(), 2);
+        assert_eq!(held.extract_pair()?.0, symbol!("a"));
+        assert_eq!(rooted.extract_pair()?.0, symbol!("b"));
+
+        // freed slots are reused
+        let _reused = pair!(symbol!("f"), nil!());
+        let stats = collect(|_| {});
+        assert_eq!(
+            stats,
+            GcStats {
+                live: 4,
+                freed: 0,
+                slots: 5
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_collect_captured_envs() -> Result<(), Error> {
+        let env = Env::new().extend(vec![(Symbol::new("x"), symbol!("a"))]);
+        let alist = env.to_alist();
+        drop(alist);
+        assert_eq!(live_pairs(), 2);
+
+        // the env is held by us, and keeps its binding and alist alive
+        let stats = collect(|_| {});
+        assert_eq!(stats.freed, 0);
+        assert_eq!(env.get(Symbol::new("x")), Some(symbol!("a")));
+
+        // then only by the alist, which it holds in turn
+        drop(env);
+        assert_eq!(live_pairs(), 2);
+        let stats = collect(|_| {});
+        assert_eq!(stats.freed, 2);
+        assert_eq!(live_pairs(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_free_long_lists() -> Result<(), Error> {
+        let mut list = nil!();
+        for _ in 0..1_000_000 {
+            list = pair!(symbol!("a"), list);
+        }
+        assert_eq!(live_pairs(), 1_000_000);
+        drop(list);
+        assert_eq!(live_pairs(), 0);
+
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 #[macro_use]
 pub mod object;
+pub mod symbol;
 pub use object::Object;
 
 pub mod parser;
@@ -7,10 +8,35 @@ pub use parser::parse;
 
 pub mod primatives;
 
+pub mod places;
+
 pub mod functions;
 
+pub mod params;
+
+pub mod bquote;
+
 pub mod loader;
 
 pub mod list;
 
+pub mod env;
 pub mod eval;
+
+pub mod scheduler;
+
+pub mod limits;
+
+pub mod bytecode;
+
+pub mod heap;
+
+pub mod oracle;
+
+pub mod conformance;
+
+pub mod pretty;
+
+pub mod cli;
+
+pub mod repl;
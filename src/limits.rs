@@ -0,0 +1,118 @@
+use crate::object;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Limits on a single call to Bel::eval (or Bel::run_threads),
+/// for running code we don't trust. None means no limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// evaluation steps, counted over all threads
+    pub max_steps: Option<u64>,
+    /// wall clock time
+    pub max_time: Option<Duration>,
+    /// pairs allocated
+    pub max_pairs: Option<u64>,
+}
+
+/// The error returned when an evaluation goes over one of its Limits.
+/// Use anyhow's downcast_ref::<LimitExceeded>() to tell it apart from
+/// errors in the Bel code itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    Steps(u64),
+    Time(Duration),
+    Pairs(u64),
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LimitExceeded::Steps(n) => write!(f, "step limit exceeded: {} steps", n),
+            LimitExceeded::Time(d) => write!(f, "time limit exceeded: {:?}", d),
+            LimitExceeded::Pairs(n) => write!(f, "allocation limit exceeded: {} pairs", n),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// The error returned when an evaluation is stopped by setting
+/// Bel's interrupt flag (e.g. by Ctrl-C in the REPL)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interrupted;
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "interrupted")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Tracks the resources used since an evaluation started
+#[derive(Debug)]
+pub struct Meter {
+    limits: Limits,
+    steps: u64,
+    start: Instant,
+    start_pairs: u64,
+}
+
+impl Meter {
+    pub fn new(limits: Limits) -> Self {
+        Meter {
+            limits,
+            steps: 0,
+            start: Instant::now(),
+            start_pairs: object::pairs_allocated(),
+        }
+    }
+
+    // count a step, checking all the limits
+    pub fn step(&mut self) -> Result<(), LimitExceeded> {
+        self.steps += 1;
+        if let Some(max_steps) = self.limits.max_steps {
+            if self.steps > max_steps {
+                return Err(LimitExceeded::Steps(max_steps));
+            }
+        }
+        if let Some(max_time) = self.limits.max_time {
+            if self.start.elapsed() > max_time {
+                return Err(LimitExceeded::Time(max_time));
+            }
+        }
+        if let Some(max_pairs) = self.limits.max_pairs {
+            if object::pairs_allocated() - self.start_pairs > max_pairs {
+                return Err(LimitExceeded::Pairs(max_pairs));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::Object;
+
+    #[test]
+    fn can_meter_steps_and_pairs() {
+        let mut meter = Meter::new(Limits {
+            max_steps: Some(2),
+            ..Default::default()
+        });
+        assert!(meter.step().is_ok());
+        assert!(meter.step().is_ok());
+        assert_eq!(meter.step(), Err(LimitExceeded::Steps(2)));
+
+        let mut meter = Meter::new(Limits {
+            max_pairs: Some(1),
+            ..Default::default()
+        });
+        let _a = pair!(nil!(), nil!());
+        assert!(meter.step().is_ok());
+        let _b = pair!(nil!(), nil!());
+        assert_eq!(meter.step(), Err(LimitExceeded::Pairs(1)));
+    }
+}
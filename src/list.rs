@@ -16,13 +16,18 @@ impl List {
         if self.obj.is_nil() {
             Ok(None)
         } else if let Object::Pair(pair) = &self.obj {
-            let (car, cdr) = *pair.clone();
+            let (car, cdr) = pair.get();
             self.obj = cdr;
             Ok(Some(car))
         } else {
             Err(anyhow!("list: invalid Object: {:?}", self.obj))
         }
     }
+
+    // the part of the list that has not been stepped over yet
+    pub fn rest(&self) -> Object {
+        self.obj.clone()
+    }
 }
 
 /// convert a list to a string,
@@ -1,44 +1,29 @@
 use crate::eval::{new_object_map, Bel};
-use crate::parse;
+use crate::parser::Reader;
 use anyhow::{Context, Result};
-use log::{debug, trace, warn};
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use log::{debug, warn};
+use std::fs;
 
+// evaluate the objects in a source file in turn, stopping at the first
+// error, or after limit of them
 pub fn load_source(bel: &mut Bel, filepath: &str, limit: Option<usize>) -> Result<()> {
     debug!(":loading {} limit = {:?}", filepath, limit);
-    let file = File::open(filepath)?;
-    let reader = BufReader::new(file);
-
-    let mut expr_count: usize = 0;
-    let mut accum = String::new();
-    'line_loop: for line in reader.lines() {
-        let line = line?;
-        trace!("load_source: line = {:?}", line);
-        // https://sep.yimg.com/ty/cdn/paulgraham/bellanguage.txt has some weird
-        // unicode byte order mark
-        if !line.starts_with('\u{feff}') && !line.starts_with(';') {
-            if line.is_empty() && !accum.is_empty() {
-                debug!("expr = {:?}", accum);
-                let parsed_expr = parse(&accum)?;
-                if parsed_expr.is_nil() {
-                    warn!("skipping empty expression");
-                    continue 'line_loop;
-                }
-                bel.eval(&new_object_map(), &parsed_expr)
-                    .context(format!("\n\n{}\n", accum))?;
-                expr_count += 1;
-                if let Some(limit) = limit {
-                    if expr_count == limit {
-                        warn!("load_source: breaking after {} expression", expr_count);
-                        break;
-                    }
-                }
-                accum.clear();
-            }
+    let text = fs::read_to_string(filepath).context(filepath.to_string())?;
+    load_text(bel, &text, filepath, limit)
+}
 
-            accum.push_str(&format!("{}\n", &line));
+// load_source, with the text already read; name is where it came from
+pub fn load_text(bel: &mut Bel, text: &str, name: &str, limit: Option<usize>) -> Result<()> {
+    let reader = Reader::new(text).named(name).with_syntax(&bel.syntax);
+    for (expr_count, item) in reader.enumerate() {
+        if Some(expr_count) == limit {
+            warn!("load_source: breaking after {} expression", expr_count);
+            break;
         }
+        let (position, exp) = item?;
+        debug!("{}:{}: expr = {}", name, position, exp);
+        bel.eval(&new_object_map(), &exp)
+            .with_context(|| format!("{}:{}", name, position))?;
     }
 
     Ok(())
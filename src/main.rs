@@ -1,71 +1,169 @@
 use anyhow::{anyhow, Error, Result};
 use log::info;
 use rustyline::error::ReadlineError;
-use rustyline::Editor;
-use smolbel::eval;
+use rustyline::{CompletionType, Config, Editor};
+use signal_hook::consts::SIGINT;
+use smolbel::cli;
+use smolbel::conformance;
+use smolbel::eval::{self, NameKind};
+use smolbel::functions;
 use smolbel::list;
 use smolbel::loader;
 use smolbel::object;
 use smolbel::parser;
-use smolbel::functions;
+use smolbel::pretty;
+use smolbel::repl::ReplHelper;
+use smolbel::symbol::Symbol;
+use std::fs;
+use std::io::{self, IsTerminal, Read};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 struct State {
     text: String,
     bel: eval::Bel,
+    // CTRL-C does what it does by default, ending the program, while this
+    // is set: whenever interruptible isn't evaluating something
+    sigint_default: Arc<AtomicBool>,
 }
 
 fn main() -> Result<(), Error> {
     env_logger::init();
     info!("program starts");
 
-    let mut rl = Editor::<()>::new();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("fmt") {
+        process::exit(fmt_command(&args[1..]));
+    }
+
+    let options = match cli::Options::parse(&args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            eprintln!("{}", cli::USAGE);
+            process::exit(2);
+        }
+    };
+    if options.help {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
+
+    // an uncaught error exits with 1, unless we go on to the REPL
+    let mut bel = eval::Bel::new();
+    let mut code = 0;
+    if let Err(err) = cli::run(&mut bel, &options) {
+        report_error(&err);
+        code = 1;
+    }
+    if options.repl() {
+        repl(bel)?;
+        code = 0;
+    }
+    process::exit(code);
+}
+
+// the REPL commands, for completion
+const REPL_COMMANDS: &[&str] = &[
+    ":globals",
+    ":primatives",
+    ":functions",
+    ":load",
+    ":conformance",
+    ":get",
+    ":fn",
+    ":disasm",
+    ":engine",
+    ":gc",
+    ":stats",
+    ":parse",
+    ":eval",
+    ":macroexpand",
+    ":macroexpand-1",
+    ":expand-all",
+];
+
+fn repl(bel: eval::Bel) -> Result<(), Error> {
+    let config = Config::builder()
+        .completion_type(CompletionType::List)
+        .build();
+    let mut rl = Editor::<ReplHelper>::with_config(config);
+    rl.set_helper(Some(ReplHelper::new(
+        &bel,
+        REPL_COMMANDS,
+        io::stdin().is_terminal(),
+    )));
     if rl.load_history("history.txt").is_err() {
         println!("No previous history.");
     };
 
     let mut state = State {
         text: String::new(),
-        bel: eval::Bel::new(),
+        bel,
+        sigint_default: Arc::new(AtomicBool::new(true)),
     };
+    // signal-hook doesn't put back the default handler when the last action
+    // is unregistered, leaving CTRL-C ignored; this one stays, so that CTRL-C
+    // is back to the default when the REPL is done
+    signal_hook::flag::register_conditional_default(SIGINT, state.sigint_default.clone())?;
 
     'repl_loop: loop {
-        let readline = rl.readline(">> ");
+        let pending = rl.helper().is_some_and(|helper| !helper.pending.is_empty());
+        let readline = rl.readline(if pending { ".. " } else { ">> " });
         match readline {
             Ok(line) => {
-                rl.add_history_entry(line.as_str());
-
-                if line.starts_with(':') {
+                if !pending && line.starts_with(':') {
+                    rl.add_history_entry(line.as_str());
                     match process_repl_command(&mut state, &line) {
                         Ok(_) => {}
                         Err(err) => {
-                            eprintln!("error: {:?}", err);
+                            report_error(&err);
                         }
                     }
+                    if let Some(helper) = rl.helper_mut() {
+                        helper.refresh_names(&state.bel);
+                    }
+                    continue 'repl_loop;
+                }
+
+                // keep reading until the forms are finished
+                let helper = rl.helper_mut().ok_or_else(|| anyhow!("no REPL helper"))?;
+                let text = helper.text(&line);
+                let reader = parser::Reader::new(&text).with_syntax(&state.bel.syntax);
+                let error = reader.filter_map(|item| item.err()).next();
+                if error.as_ref().is_some_and(|err| err.incomplete) {
+                    helper.pending = text;
                     continue 'repl_loop;
                 }
+                helper.pending.clear();
+                rl.add_history_entry(text.trim_end());
 
-                match parser::parse(&line) {
-                    Ok(exp) => {
-                        println!("parsed exp = {}", exp);
-                        match state.bel.eval(&eval::new_object_map(), &exp) {
-                            Ok(obj) => {
-                                println!("eval output = {:?}", obj);
-                            }
-                            Err(err) => {
-                                eprintln!("error: {:?}", err);
-                            }
+                match error {
+                    Some(err) => report_error(&err.into()),
+                    None => {
+                        eval_forms(&mut state, &text);
+                        if let Some(helper) = rl.helper_mut() {
+                            helper.refresh_names(&state.bel);
                         }
                     }
-                    Err(err) => {
-                        eprintln!("error: {:?}", err);
-                    }
                 }
             }
             Err(ReadlineError::Interrupted) => {
-                println!("CTRL-C");
-                break;
+                // just clear the line, and any unfinished form; CTRL-D quits
+                if let Some(helper) = rl.helper_mut() {
+                    helper.pending.clear();
+                }
+                continue 'repl_loop;
             }
             Err(ReadlineError::Eof) => {
+                if let Some(helper) = rl.helper().filter(|helper| !helper.pending.is_empty()) {
+                    let reader =
+                        parser::Reader::new(&helper.pending).with_syntax(&state.bel.syntax);
+                    if let Some(err) = reader.filter_map(|item| item.err()).next() {
+                        report_error(&err.into());
+                    }
+                }
                 println!("CTRL-D");
                 break;
             }
@@ -79,6 +177,113 @@ fn main() -> Result<(), Error> {
     Ok(())
 }
 
+// evaluate each form in text in turn, stopping at the first error.
+// They're read one at a time, as one may add syntax the next uses
+fn eval_forms(state: &mut State, text: &str) {
+    let syntax = state.bel.syntax.clone();
+    for item in parser::Reader::new(text).with_syntax(&syntax) {
+        let exp = match item {
+            Ok((_, exp)) => exp,
+            Err(err) => {
+                report_error(&err.into());
+                return;
+            }
+        };
+        match interruptible(state, |bel| bel.eval(&eval::new_object_map(), &exp)) {
+            Ok(obj) => println!("{}", pretty::pretty(&obj, pretty::WIDTH)),
+            Err(err) => {
+                report_error(&err);
+                return;
+            }
+        }
+    }
+}
+
+// smolbel fmt [--check] [file...]
+// lays out the files in place, or with --check lists those that would
+// change, exiting with 1 if there are any. With no files, formats stdin
+// to stdout.
+fn fmt_command(args: &[String]) -> i32 {
+    let check = args.iter().any(|arg| arg == "--check");
+    let files: Vec<&String> = args.iter().filter(|arg| *arg != "--check").collect();
+    if let Some(arg) = files.iter().find(|arg| arg.starts_with('-')) {
+        eprintln!("error: unknown option {}", arg);
+        eprintln!("usage: smolbel fmt [--check] [file...]");
+        return 2;
+    }
+
+    if files.is_empty() {
+        let mut text = String::new();
+        let result = io::stdin()
+            .read_to_string(&mut text)
+            .map_err(Error::from)
+            .and_then(|_| pretty::format_text(&text, pretty::WIDTH));
+        return match result {
+            Ok(formatted) if check && formatted != text => 1,
+            Ok(_) if check => 0,
+            Ok(formatted) => {
+                print!("{}", formatted);
+                0
+            }
+            Err(err) => {
+                report_error(&err);
+                2
+            }
+        };
+    }
+
+    let mut code = 0;
+    for file in files {
+        let result = fs::read_to_string(file)
+            .map_err(Error::from)
+            .and_then(|text| Ok((pretty::format_text(&text, pretty::WIDTH)?, text)));
+        match result {
+            Ok((formatted, text)) if formatted == text => {}
+            Ok(_) if check => {
+                println!("would reformat {}", file);
+                code = code.max(1);
+            }
+            Ok((formatted, _)) => {
+                if let Err(err) = fs::write(file, formatted) {
+                    eprintln!("error: {}: {}", file, err);
+                    code = 2;
+                }
+            }
+            Err(err) => {
+                match err.downcast_ref::<parser::ParseError>() {
+                    Some(parse_error) => eprintln!("{}: {}", file, parse_error),
+                    None => eprintln!("error: {}: {:#}", file, err),
+                }
+                code = 2;
+            }
+        }
+    }
+    code
+}
+
+// parse errors show where they are, so they don't need the debug detail
+fn report_error(err: &Error) {
+    match err.downcast_ref::<parser::ParseError>() {
+        Some(parse_error) => eprintln!("{}", parse_error),
+        None => eprintln!("error: {:#}", err),
+    }
+}
+
+// run f with CTRL-C interrupting the evaluation rather than the REPL.
+// A CTRL-C from before is forgotten, so it doesn't stop this evaluation.
+fn interruptible<T>(
+    state: &mut State,
+    f: impl FnOnce(&mut eval::Bel) -> Result<T, Error>,
+) -> Result<T, Error> {
+    state.bel.interrupt.store(false, Ordering::SeqCst);
+    let sig_id = signal_hook::flag::register(SIGINT, state.bel.interrupt.clone())?;
+    state.sigint_default.store(false, Ordering::SeqCst);
+    let result = f(&mut state.bel);
+    state.sigint_default.store(true, Ordering::SeqCst);
+    signal_hook::low_level::unregister(sig_id);
+    result
+}
+
 fn process_repl_command(state: &mut State, line: &str) -> Result<(), Error> {
     let parts: Vec<&str> = line.splitn(2, ' ').collect();
     match parts[0] {
@@ -96,16 +301,32 @@ fn process_repl_command(state: &mut State, line: &str) -> Result<(), Error> {
         }
         ":function" | ":functions" => {
             println!("functions");
-            for key in &state.bel.function_names {
-                println!("{}", key);
+            for key in state.bel.globals.keys() {
+                if let object::Object::Symbol(symbol) = key {
+                    if state.bel.name_kind(*symbol) == NameKind::Function
+                        && !state.bel.primatives.contains_key(symbol)
+                    {
+                        println!("{}", key);
+                    }
+                }
             }
         }
         ":load" => {
-            // TODO: parse parts[3] for limit
+            let usage = || anyhow!("invalid command").context(":load <filepath> [limit]");
             if parts.len() != 2 {
-                return Err(anyhow!("invalid command").context(":load <filepah>"));
+                return Err(usage());
             };
-            loader::load_source(&mut state.bel, parts[1], Some(3))?;
+            let args: Vec<&str> = parts[1].split_whitespace().collect();
+            let limit = match args.as_slice() {
+                [_] => None,
+                [_, limit] => Some(limit.parse::<usize>().map_err(|_| usage())?),
+                _ => return Err(usage()),
+            };
+            interruptible(state, |bel| loader::load_source(bel, args[0], limit))?;
+        }
+        ":conformance" => {
+            let filepath = parts.get(1).copied().unwrap_or(conformance::SOURCE_PATH);
+            println!("{}", conformance::check_source(filepath)?);
         }
         ":get" => {
             if parts.len() != 2 {
@@ -113,8 +334,7 @@ fn process_repl_command(state: &mut State, line: &str) -> Result<(), Error> {
             }
             match state
                 .bel
-                .globals
-                .get(&object::Object::Symbol(parts[1].to_string()))
+                .global(&object::Object::Symbol(Symbol::new(parts[1])))
             {
                 Some(obj) => {
                     println!("{}", obj);
@@ -128,34 +348,68 @@ fn process_repl_command(state: &mut State, line: &str) -> Result<(), Error> {
             if parts.len() != 2 {
                 return Err(anyhow!("invalid command").context(":fn <name>"));
             }
-            let name = object::Object::Symbol(parts[1].to_string());
-            if !state.bel.function_names.contains(parts[1]) {
+            let symbol = Symbol::new(parts[1]);
+            let name = object::Object::Symbol(symbol);
+            if state.bel.name_kind(symbol) != NameKind::Function {
                 return Err(anyhow!("{} is not a function", name));
             }
             let obj = state
                 .bel
-                .globals
-                .get(&name)
+                .global(&name)
                 .ok_or_else(|| anyhow!("unknown name {}", name))?;
-            dump_list(obj, 0)?;
+            dump_list(&obj, 0)?;
+        }
+        ":disasm" => {
+            if parts.len() != 2 {
+                return Err(anyhow!("invalid command").context(":disasm <name>"));
+            }
+            print!("{}", state.bel.compile_function(parts[1])?);
+        }
+        ":engine" => {
+            if parts.len() == 2 {
+                state.bel.engine = match parts[1] {
+                    "tree" => eval::Engine::TreeWalk,
+                    "bytecode" => eval::Engine::Bytecode,
+                    _ => return Err(anyhow!("invalid command").context(":engine [tree|bytecode]")),
+                };
+            }
+            println!("{:?}", state.bel.engine);
+        }
+        ":gc" => {
+            println!("{}", state.bel.collect_garbage());
+        }
+        ":stats" => {
+            println!("{}", state.bel.stats);
         }
         ":parse" => {
             if parts.len() != 2 {
                 return Err(anyhow!("invalid command").context(":parse <code>"));
             }
             state.text = parts[1].to_string();
-            let obj = parser::parse(&state.text)?;
+            let obj = state.bel.parse(&state.text)?;
             println!("{}", obj);
         }
+        ":macroexpand" | ":macroexpand-1" | ":expand-all" => {
+            if parts.len() != 2 {
+                return Err(anyhow!("invalid command").context(format!("{} <code>", parts[0])));
+            }
+            let exp = state.bel.parse(parts[1])?;
+            let expansion = interruptible(state, |bel| match parts[0] {
+                ":macroexpand-1" => bel.macroexpand_1(&exp),
+                ":macroexpand" => bel.macroexpand(&exp),
+                _ => bel.expand_all(&exp),
+            })?;
+            println!("{}", pretty::pretty(&expansion, pretty::WIDTH));
+        }
         ":eval" => {
             if parts.len() != 2 {
                 return Err(anyhow!("invalid command").context(":eval <code>"));
             }
             state.text = parts[1].to_string();
-            let obj = parser::parse(&state.text)?;
+            let obj = state.bel.parse(&state.text)?;
             let (exp_name, _args) = obj.extract_pair()?;
-            let function = if let Some(f) = state.bel.globals.get(&exp_name) {
-                functions::expand_function(&exp_name, f)?
+            let function = if let Some(f) = state.bel.global(&exp_name) {
+                functions::expand_function(&f)?
             } else {
                 return Err(anyhow!("unknown function {}", exp_name));
             };
@@ -1,36 +1,109 @@
+use crate::heap::PairRef;
+use crate::symbol::Symbol;
 use anyhow::{anyhow, Error};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::hash::{Hash, Hasher};
 
 /// Bel has four fundamental data types:
 /// symbols, pairs, characters, and streams.
 /// Instances of the four fundamental types are called objects
 /// https://sep.yimg.com/ty/cdn/paulgraham/bellanguage.txt
-#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+///
+/// Pairs are shared: they live in the heap, see heap.rs, and cloning a
+/// pair object gives another handle to the same pair, so changes made with
+/// xar and xdr are seen by everyone holding it. Equality (==) compares structure; use is_same_pair for identity.
+/// As pairs can be changed, they can refer back to themselves: ==, hashing
+/// and printing all cope with that.
+///
+/// Symbols are interned, see Symbol.
+#[derive(Clone)]
 pub enum Object {
-    Symbol(String),
-    Pair(Box<(Object, Object)>),
+    Symbol(Symbol),
+    Pair(PairRef),
     Char(String),
     Stream,
 }
 
+// structure, as deep as it goes; pairs already being compared are taken to
+// be equal, which they are unless something else differs, so comparing
+// cyclic structures ends
+impl PartialEq for Object {
+    fn eq(&self, other: &Object) -> bool {
+        equal(self, other, &mut HashSet::new())
+    }
+}
+
+impl Eq for Object {}
+
+fn equal(a: &Object, b: &Object, comparing: &mut HashSet<(usize, usize)>) -> bool {
+    match (a, b) {
+        (Object::Pair(x), Object::Pair(y)) => {
+            if x.index() == y.index() || !comparing.insert((x.index(), y.index())) {
+                return true;
+            }
+            let (x, y) = (x.get(), y.get());
+            equal(&x.0, &y.0, comparing) && equal(&x.1, &y.1, comparing)
+        }
+        (Object::Symbol(x), Object::Symbol(y)) => x == y,
+        (Object::Char(x), Object::Char(y)) => x == y,
+        (Object::Stream, Object::Stream) => true,
+        _ => false,
+    }
+}
+
+// how many objects of a pair's contents are hashed
+const HASH_LIMIT: usize = 16;
+
+// hash pairs by their contents, consistent with ==.
+// Only the first few objects are hashed, so hashing cyclic structures ends;
+// structures that are == have the same first few objects.
+impl Hash for Object {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut limit = HASH_LIMIT;
+        hash_limited(self, state, &mut limit);
+    }
+}
+
+fn hash_limited<H: Hasher>(obj: &Object, state: &mut H, limit: &mut usize) {
+    if *limit == 0 {
+        return;
+    }
+    *limit -= 1;
+    std::mem::discriminant(obj).hash(state);
+    match obj {
+        Object::Symbol(symbol) => symbol.hash(state),
+        Object::Pair(pair) => {
+            let (car, cdr) = pair.get();
+            hash_limited(&car, state, limit);
+            hash_limited(&cdr, state, limit);
+        }
+        Object::Char(c) => c.hash(state),
+        Object::Stream => {}
+    }
+}
+
 /// nil object (aka 'false')
 macro_rules! nil {
     () => {
-        Object::Symbol("nil".to_string())
+        Object::Symbol($crate::symbol::Symbol::NIL)
     };
 }
 
 /// 'true' object (true is not a good name for a macro)
 macro_rules! t {
     () => {
-        Object::Symbol("t".to_string())
+        Object::Symbol($crate::symbol::Symbol::T)
     };
 }
 
 /// general symbol
 macro_rules! symbol {
     ($n:expr) => {
-        Object::Symbol($n.to_string())
+        Object::Symbol($crate::symbol::Symbol::new(
+            ::std::convert::AsRef::<str>::as_ref(&$n),
+        ))
     };
 }
 
@@ -38,7 +111,7 @@ macro_rules! symbol {
 #[allow(unused_macros)]
 macro_rules! pair {
     ($a:expr, $b:expr) => {
-        Object::Pair(Box::new(($a, $b)))
+        $crate::object::new_pair($a, $b)
     };
 }
 
@@ -58,43 +131,138 @@ macro_rules! stream {
     };
 }
 
-impl fmt::Display for Object {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match &self {
-            Object::Symbol(name) => {
-                write!(f, "{}", name)?;
-            }
-            Object::Pair(pair) => {
-                let p = *pair.clone();
-                let (h, t) = p;
-                write!(f, "({} . {})", h, t)?;
-            }
-            Object::Char(c) => {
-                write!(f, "c({})", c)?;
-            }
-            Object::Stream => {
-                write!(f, "stream")?;
+/// How a printer writes a pair, so that printing a structure that refers
+/// back to itself ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Label {
+    /// in full
+    None,
+    /// in full, labelled #n= as something in it refers back to it
+    Define(usize),
+    /// #n#, as it has been written already
+    Refer(usize),
+}
+
+/// The labels of the pairs in an object that are reached again from inside
+/// themselves, given out as they're printed
+pub struct Labels {
+    circular: HashSet<usize>,
+    labels: HashMap<usize, usize>,
+}
+
+impl Labels {
+    pub fn new(obj: &Object) -> Labels {
+        let mut circular = HashSet::new();
+        find_circular(obj, &mut HashSet::new(), &mut HashSet::new(), &mut circular);
+        Labels {
+            circular,
+            labels: HashMap::new(),
+        }
+    }
+
+    /// How to write obj, printing it next
+    pub fn label(&mut self, obj: &Object) -> Label {
+        let address = match obj.pair_address() {
+            Some(address) => address,
+            None => return Label::None,
+        };
+        if let Some(label) = self.labels.get(&address) {
+            Label::Refer(*label)
+        } else if self.circular.contains(&address) {
+            let label = self.labels.len();
+            self.labels.insert(address, label);
+            Label::Define(label)
+        } else {
+            Label::None
+        }
+    }
+
+    /// Whether obj would be written with a label
+    pub fn is_labelled(&self, obj: &Object) -> bool {
+        obj.pair_address().is_some_and(|address| {
+            self.circular.contains(&address) || self.labels.contains_key(&address)
+        })
+    }
+}
+
+// the pairs reached from a pair on the path to them, in the order a printer
+// would go through them
+fn find_circular(
+    obj: &Object,
+    path: &mut HashSet<usize>,
+    seen: &mut HashSet<usize>,
+    circular: &mut HashSet<usize>,
+) {
+    if let Object::Pair(pair) = obj {
+        let address = pair.index();
+        if path.contains(&address) {
+            circular.insert(address);
+            return;
+        }
+        if !seen.insert(address) {
+            return;
+        }
+        path.insert(address);
+        let (car, cdr) = pair.get();
+        find_circular(&car, path, seen, circular);
+        find_circular(&cdr, path, seen, circular);
+        path.remove(&address);
+    }
+}
+
+// write obj dotted, (a . (b . nil)), with Debug's names for things if debug
+fn write_object(
+    f: &mut fmt::Formatter,
+    obj: &Object,
+    labels: &mut Labels,
+    debug: bool,
+) -> fmt::Result {
+    match obj {
+        Object::Symbol(symbol) if debug => write!(f, "Symbol({:?})", symbol),
+        Object::Symbol(symbol) => write!(f, "{}", symbol),
+        Object::Pair(pair) => {
+            match labels.label(obj) {
+                Label::Refer(label) => return write!(f, "#{}#", label),
+                Label::Define(label) => write!(f, "#{}=", label)?,
+                Label::None => {}
             }
+            let (h, t) = pair.get();
+            write!(f, "{}(", if debug { "Pair(" } else { "" })?;
+            write_object(f, &h, labels, debug)?;
+            write!(f, " . ")?;
+            write_object(f, &t, labels, debug)?;
+            write!(f, "){}", if debug { ")" } else { "" })
         }
-        Ok(())
+        Object::Char(c) if debug => write!(f, "Char({:?})", c),
+        Object::Char(c) => write!(f, "c({})", c),
+        Object::Stream if debug => write!(f, "Stream"),
+        Object::Stream => write!(f, "stream"),
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_object(f, self, &mut Labels::new(self), false)
+    }
+}
+
+impl fmt::Debug for Object {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_object(f, self, &mut Labels::new(self), true)
     }
 }
 
 impl Object {
     pub fn is_symbol(&self, name: &str) -> bool {
-        if let Object::Symbol(n) = self {
-            n == name
+        if let Object::Symbol(symbol) = self {
+            symbol.name() == name
         } else {
             false
         }
     }
 
     pub fn is_nil(&self) -> bool {
-        if let Object::Symbol(name) = self {
-            name == "nil"
-        } else {
-            false
-        }
+        matches!(self, Object::Symbol(Symbol::NIL))
     }
 
     // anything other thn 'nil is true
@@ -104,16 +272,61 @@ impl Object {
 
     pub fn is_pair(&self, o1: Object, o2: Object) -> bool {
         if let Object::Pair(pair) = self {
-            let (car, cdr) = *pair.clone();
+            let (car, cdr) = pair.get();
             o1 == car && o2 == cdr
         } else {
             false
         }
     }
 
+    // identity, as opposed to ==, which compares structure
+    pub fn is_same_pair(&self, other: &Object) -> bool {
+        match (self, other) {
+            (Object::Pair(a), Object::Pair(b)) => a.index() == b.index(),
+            _ => false,
+        }
+    }
+
+    // the address of a pair, its slot in the heap, which identifies it
+    // while it's alive
+    pub fn pair_address(&self) -> Option<usize> {
+        match self {
+            Object::Pair(pair) => Some(pair.index()),
+            _ => None,
+        }
+    }
+
+    // how many references there are to a pair, 0 for anything else
+    pub fn ref_count(&self) -> usize {
+        match self {
+            Object::Pair(pair) => pair.handles(),
+            _ => 0,
+        }
+    }
+
+    // replace the car of a pair in place
+    pub fn set_car(&self, obj: Object) -> Result<(), Error> {
+        if let Object::Pair(pair) = self {
+            pair.set_car(obj);
+            Ok(())
+        } else {
+            Err(anyhow!("expecting pair found: {:?}", self))
+        }
+    }
+
+    // replace the cdr of a pair in place
+    pub fn set_cdr(&self, obj: Object) -> Result<(), Error> {
+        if let Object::Pair(pair) = self {
+            pair.set_cdr(obj);
+            Ok(())
+        } else {
+            Err(anyhow!("expecting pair found: {:?}", self))
+        }
+    }
+
     pub fn extract_pair(&self) -> Result<(Object, Object), Error> {
         if let Object::Pair(pair) = self {
-            Ok(*pair.clone())
+            Ok(pair.get())
         } else {
             Err(anyhow!("expecting pair found: {:?}", self))
         }
@@ -138,15 +351,24 @@ impl Object {
     pub fn to_vec(&self) -> Result<Vec<Object>, Error> {
         let mut accum: Vec<Object> = Vec::new();
         let mut list = self.clone();
+        // goes through the list at half speed: if the list comes back to a
+        // pair it has been through, list catches up with it
+        let mut behind = self.clone();
 
         while !list.is_nil() {
             if let Object::Pair(pair) = list {
-                let (car, cdr) = *pair.clone();
+                let (car, cdr) = pair.get();
                 accum.push(car);
                 list = cdr;
             } else {
                 return Err(anyhow!("expected Pair: {:?}", self));
             }
+            if accum.len().is_multiple_of(2) {
+                behind = behind.extract_pair()?.1;
+            }
+            if list.is_same_pair(&behind) {
+                return Err(anyhow!("circular list: {}", self));
+            }
         }
 
         Ok(accum)
@@ -162,13 +384,28 @@ impl Object {
     }
 }
 
+thread_local! {
+    static PAIRS_ALLOCATED: Cell<u64> = const { Cell::new(0) };
+}
+
+// every pair is made here, in the heap, so we can count them
+pub fn new_pair(car: Object, cdr: Object) -> Object {
+    PAIRS_ALLOCATED.with(|n| n.set(n.get() + 1));
+    Object::Pair(PairRef::new(car, cdr))
+}
+
+// the number of pairs made so far by this (OS) thread
+pub fn pairs_allocated() -> u64 {
+    PAIRS_ALLOCATED.with(|n| n.get())
+}
+
 // join puts a an object at the head of the list
 // see also cons, which takes multiple objects
 pub fn join(obj: Object, list: Object) -> Result<Object, Error> {
     if list.is_nil() {
-        Ok(Object::Pair(Box::new((obj, nil!()))))
+        Ok(pair!(obj, nil!()))
     } else if let Object::Pair(_) = list {
-        Ok(Object::Pair(Box::new((obj, list))))
+        Ok(pair!(obj, list))
     } else {
         Err(anyhow!("invalid list: {:?}", list))
     }
@@ -186,6 +423,23 @@ pub fn from_vec(v: Vec<Object>) -> Result<Object, Error> {
     Ok(obj_accum)
 }
 
+/// a Bel string: a list of chars, with whitespace named as the reader
+/// names it (\sp, \lf ...)
+pub fn from_string(text: &str) -> Object {
+    let mut obj_accum: Object = nil!();
+    for c in text.chars().rev() {
+        let name = match c {
+            ' ' => "sp".to_string(),
+            '\n' => "lf".to_string(),
+            '\t' => "tab".to_string(),
+            '\r' => "cr".to_string(),
+            _ => c.to_string(),
+        };
+        obj_accum = pair!(Object::Char(name), obj_accum);
+    }
+    obj_accum
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -231,4 +485,50 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn can_build_string() -> Result<(), Error> {
+        assert!(from_string("").is_nil());
+        let o = from_string("a b");
+        assert_eq!(o.to_vec()?, vec![char!("a"), char!("sp"), char!("b")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_handle_cycles() -> Result<(), Error> {
+        use std::collections::hash_map::DefaultHasher;
+
+        fn hash(obj: &Object) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            obj.hash(&mut hasher);
+            hasher.finish()
+        }
+
+        // xs is (a a a ...)
+        let xs = pair!(symbol!("a"), nil!());
+        xs.set_cdr(xs.clone())?;
+        assert_eq!(xs.to_string(), "#0=(a . #0#)");
+        assert_eq!(format!("{:?}", xs), "#0=Pair((Symbol(\"a\") . #0#))");
+        assert!(xs.to_vec().is_err());
+
+        // and so is ys, though it takes two pairs to come back round
+        let ys = pair!(symbol!("a"), pair!(symbol!("a"), nil!()));
+        ys.extract_pair()?.1.set_cdr(ys.clone())?;
+        assert_eq!(ys.to_string(), "#0=(a . (a . #0#))");
+        assert_eq!(xs, ys);
+        assert_eq!(hash(&xs), hash(&ys));
+
+        let zs = pair!(symbol!("a"), pair!(symbol!("b"), nil!()));
+        zs.extract_pair()?.1.set_cdr(zs.clone())?;
+        assert_ne!(xs, zs);
+
+        // a pair in its own car
+        let ws = pair!(nil!(), nil!());
+        ws.set_car(ws.clone())?;
+        assert_eq!(ws.to_string(), "#0=(#0# . nil)");
+        assert_eq!(ws.to_vec()?.len(), 1);
+
+        Ok(())
+    }
 }
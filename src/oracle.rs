@@ -0,0 +1,240 @@
+use crate::eval::{new_object_map, Bel};
+use crate::limits::Limits;
+use crate::object::Object;
+use crate::parser;
+use crate::parser::Reader;
+use anyhow::{anyhow, Context, Error, Result};
+use log::{debug, warn};
+use std::fmt;
+use std::fs;
+
+/// The definitions that make up the interpreter bel.bel writes in Bel
+pub const INTERPRETER: &[&str] = &[
+    "bel",
+    "mev",
+    "sched",
+    "ev",
+    "vref",
+    "inwhere",
+    "lookup",
+    "binding",
+    "sigerr",
+    "evmark",
+    "forms",
+    "formfn",
+    "parameters",
+    "evcall",
+    "evcall2",
+    "applym",
+    "applyf",
+    "applylit",
+];
+
+// (bel 'e) takes many steps for each of ours; stop it if it gets lost
+const MAX_STEPS: u64 = 50_000_000;
+
+/// How an expression's value compares with the value bel.bel's
+/// interpreter gives it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Comparison {
+    /// the same printed value, or both were errors
+    Agree(String),
+    Disagree {
+        native: String,
+        oracle: String,
+    },
+    /// the interpreter in bel.bel can't be run, and why
+    Unavailable(String),
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Comparison::Agree(value) => write!(f, "agree: {}", value),
+            Comparison::Disagree { native, oracle } => {
+                write!(f, "disagree: native {}; bel.bel {}", native, oracle)
+            }
+            Comparison::Unavailable(why) => write!(f, "unavailable: {}", why),
+        }
+    }
+}
+
+/// Uses the interpreter in bel.bel as a check on ours: an expression
+/// should have the same value evaluated directly as it does evaluated
+/// by (bel 'e), in the same globals.
+pub struct Oracle {
+    pub bel: Bel,
+    // why (bel 'e) can't be trusted, if it can't
+    unavailable: Option<String>,
+}
+
+impl Oracle {
+    // bel should already have bel.bel's definitions loaded
+    pub fn new(mut bel: Bel) -> Self {
+        bel.limits = Limits {
+            max_steps: Some(MAX_STEPS),
+            ..bel.limits
+        };
+        let mut oracle = Oracle {
+            bel,
+            unavailable: None,
+        };
+        oracle.unavailable = oracle.probe().err().map(|err| format!("{:#}", err));
+        if let Some(why) = &oracle.unavailable {
+            warn!("oracle: (bel 'e) can't be used: {}", why);
+        }
+        oracle
+    }
+
+    // load every form in the source file that we can, carrying on past the
+    // ones we can't
+    pub fn load(filepath: &str) -> Result<Self> {
+        let text = fs::read_to_string(filepath).context(filepath.to_string())?;
+        let mut bel = Bel::new();
+        for item in Reader::new(&text).named(filepath).with_syntax(&bel.syntax) {
+            let (position, exp) = item?;
+            if let Err(err) = bel.eval(&new_object_map(), &exp) {
+                debug!("oracle: skipping {}:{}: {}", filepath, position, err);
+            }
+        }
+        Ok(Oracle::new(bel))
+    }
+
+    // the parts of the interpreter that didn't load
+    pub fn missing(&self) -> Vec<&'static str> {
+        INTERPRETER
+            .iter()
+            .filter(|name| !self.bel.globals.contains_key(&symbol!(name)))
+            .copied()
+            .collect()
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.unavailable.is_none()
+    }
+
+    // a couple of expressions the interpreter must get right before its
+    // answers mean anything
+    fn probe(&mut self) -> Result<()> {
+        let missing = self.missing();
+        if !missing.is_empty() {
+            return Err(anyhow!("not loaded: {}", missing.join(" ")));
+        }
+        for (text, expected) in [("t", t!()), ("(car '(a b))", symbol!("a"))] {
+            let exp = parser::parse(text)?;
+            let value = self.oracle_eval(&exp).context(format!("(bel '{})", text))?;
+            if value != expected {
+                return Err(anyhow!(
+                    "(bel '{}) gave {}, expected {}",
+                    text,
+                    value,
+                    expected
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn oracle_eval(&mut self, exp: &Object) -> Result<Object, Error> {
+        let call = pair!(
+            symbol!("bel"),
+            pair!(pair!(symbol!("quote"), pair!(exp.clone(), nil!())), nil!())
+        );
+        let value = self.bel.eval(&new_object_map(), &call)?;
+        self.bel.run_threads()?;
+        Ok(value)
+    }
+
+    fn native_eval(&mut self, exp: &Object) -> Result<Object, Error> {
+        let value = self.bel.eval(&new_object_map(), exp)?;
+        self.bel.run_threads()?;
+        Ok(value)
+    }
+
+    pub fn compare(&mut self, exp: &Object) -> Comparison {
+        if let Some(why) = &self.unavailable {
+            return Comparison::Unavailable(why.clone());
+        }
+        // print the values, so shared structure and the pairs' identities
+        // don't matter
+        let native = show(self.native_eval(exp));
+        let oracle = show(self.oracle_eval(exp));
+        debug!("oracle: {} => native {}; bel.bel {}", exp, native, oracle);
+        if native == oracle {
+            Comparison::Agree(native)
+        } else {
+            Comparison::Disagree { native, oracle }
+        }
+    }
+}
+
+fn show(result: Result<Object, Error>) -> String {
+    match result {
+        Ok(obj) => obj.to_string(),
+        Err(_) => "error".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a Bel with bel defined as a macro, evaluating its argument with our
+    // own evaluator, and the names the probe checks for bound
+    fn stub(bel_mac: &str) -> Result<Bel, Error> {
+        let mut bel = Bel::new();
+        for name in INTERPRETER {
+            bel.set_global(symbol!(name), nil!());
+        }
+        bel.eval(&new_object_map(), &parser::parse(bel_mac)?)?;
+        Ok(bel)
+    }
+
+    #[test]
+    fn can_compare_with_oracle() -> Result<(), Error> {
+        // (bel 'e) expands to e
+        let mut oracle = Oracle::new(stub("(mac bel (e) (car (cdr e)))")?);
+        assert!(oracle.is_available());
+        let exp = parser::parse("(car '(a b))")?;
+        assert_eq!(oracle.compare(&exp), Comparison::Agree("a".to_string()));
+        let exp = parser::parse("(car 'a)")?;
+        assert_eq!(oracle.compare(&exp), Comparison::Agree("error".to_string()));
+
+        // (bel 'e) expands to ((fn (x) e) 'b), which is right for the probe
+        let mut oracle = Oracle::new(stub(
+            "(mac bel (e) (join (join 'fn (join (join 'x nil) (join (car (cdr e)) nil))) (join ''b nil)))",
+        )?);
+        oracle
+            .bel
+            .eval(&new_object_map(), &parser::parse("(set x nil)")?)?;
+        oracle
+            .bel
+            .eval(&new_object_map(), &parser::parse("(push 'a x)")?)?;
+        assert!(oracle.is_available());
+        assert_eq!(
+            oracle.compare(&parser::parse("x")?),
+            Comparison::Disagree {
+                native: "(a . nil)".to_string(),
+                oracle: "b".to_string()
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn reports_unavailable_oracle() -> Result<(), Error> {
+        let oracle = Oracle::new(Bel::new());
+        assert!(!oracle.is_available());
+        assert_eq!(oracle.missing(), INTERPRETER.to_vec());
+
+        let mut oracle = Oracle::new(stub("(def bel (e) 'a)")?);
+        assert!(oracle.missing().is_empty());
+        match oracle.compare(&t!()) {
+            Comparison::Unavailable(why) => assert_eq!(why, "(bel 't) gave a, expected t"),
+            other => panic!("expected unavailable: {}", other),
+        }
+
+        Ok(())
+    }
+}
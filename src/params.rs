@@ -0,0 +1,234 @@
+use crate::object::Object;
+use crate::symbol::Symbol;
+use anyhow::{anyhow, Error, Result};
+
+/// What a closure's parameters bind its arguments to
+///
+/// A parameter list is a tree: (x y) binds x and y to the first two
+/// arguments, (x . rest) binds rest to the arguments after the first,
+/// args on its own binds all of them, and ((x y) z) takes the first
+/// argument apart. As elements of the list, (o x default) is optional,
+/// with the value of default if there's no argument for it, and
+/// (t x type) is x; the type isn't checked. A uvar, a list whose car is
+/// vmark, is a variable like a symbol.
+#[derive(Debug, Default)]
+pub struct Bound {
+    /// the names in the order they are bound, the order of names(params)
+    pub bindings: Vec<(Symbol, Object)>,
+    /// the optional parameters without an argument, as the index of their
+    /// binding and the expression for its value, to be evaluated in turn
+    /// once the rest are bound
+    pub defaults: Vec<(usize, Object)>,
+}
+
+// (o x default) and (t x type), where they are elements of a list
+enum Marked {
+    Optional(Object, Object),
+    Typed(Object),
+}
+
+/// The name a uvar stands for, if obj is one. bel.bel makes a uvar with
+/// (list vmark), so each is a distinct pair, and is named after its address.
+pub fn uvar_name(obj: &Object, vmark: &Object) -> Option<Symbol> {
+    match obj.extract_pair() {
+        Ok((car, _)) if car.is_same_pair(vmark) => {
+            Some(Symbol::new(&format!("uvar:{}", obj.pair_address()?)))
+        }
+        _ => None,
+    }
+}
+
+fn marked(param: &Object) -> Option<Marked> {
+    let v = param.to_vec().ok()?;
+    match v.as_slice() {
+        [Object::Symbol(Symbol::O), var] => Some(Marked::Optional(var.clone(), nil!())),
+        [Object::Symbol(Symbol::O), var, default] => {
+            Some(Marked::Optional(var.clone(), default.clone()))
+        }
+        [Object::Symbol(Symbol::T), var, _] => Some(Marked::Typed(var.clone())),
+        _ => None,
+    }
+}
+
+/// The names params binds, in the order they are bound
+pub fn names(params: &Object, vmark: &Object) -> Vec<Symbol> {
+    let mut names = Vec::new();
+    add_names(params, vmark, &mut names);
+    names
+}
+
+fn add_names(params: &Object, vmark: &Object, names: &mut Vec<Symbol>) {
+    if let Some(name) = uvar_name(params, vmark) {
+        names.push(name);
+        return;
+    }
+    match params {
+        Object::Symbol(Symbol::NIL) => {}
+        Object::Symbol(name) => names.push(*name),
+        Object::Pair(_) => {
+            if let Ok((param, rest)) = params.extract_pair() {
+                match marked(&param) {
+                    Some(Marked::Optional(var, _)) | Some(Marked::Typed(var)) => {
+                        add_names(&var, vmark, names)
+                    }
+                    None => add_names(&param, vmark, names),
+                }
+                add_names(&rest, vmark, names);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Bind params to args. Missing arguments are nil, unless they have a
+/// default; too many are an error.
+pub fn bind(params: &Object, args: &Object, vmark: &Object) -> Result<Bound, Error> {
+    let mut bound = Bound::default();
+    bind_list(params, Some(args), vmark, &mut bound)?;
+    Ok(bound)
+}
+
+// bind the pattern params to arg, None if there's no argument for it
+fn bind_list(
+    params: &Object,
+    arg: Option<&Object>,
+    vmark: &Object,
+    bound: &mut Bound,
+) -> Result<(), Error> {
+    let name = match params {
+        Object::Symbol(Symbol::NIL) => {
+            return match arg {
+                Some(arg) if !arg.is_nil() => Err(anyhow!("too many args: {}", arg)),
+                _ => Ok(()),
+            }
+        }
+        Object::Symbol(name) => Some(*name),
+        _ => uvar_name(params, vmark),
+    };
+    if let Some(name) = name {
+        bound
+            .bindings
+            .push((name, arg.cloned().unwrap_or_else(|| nil!())));
+        return Ok(());
+    }
+    match params {
+        Object::Pair(_) => {
+            let (param, rest) = params.extract_pair()?;
+            let (first, more) = match arg {
+                Some(arg @ Object::Pair(_)) => {
+                    let (first, more) = arg.extract_pair()?;
+                    (Some(first), Some(more))
+                }
+                Some(Object::Symbol(Symbol::NIL)) | None => (None, None),
+                Some(arg) => {
+                    return Err(anyhow!("expected a list of args for {}: {}", params, arg))
+                }
+            };
+            bind_element(&param, first.as_ref(), vmark, bound)?;
+            bind_list(&rest, more.as_ref(), vmark, bound)
+        }
+        _ => Err(anyhow!("invalid param object: {:?}", params)),
+    }
+}
+
+fn bind_element(
+    param: &Object,
+    arg: Option<&Object>,
+    vmark: &Object,
+    bound: &mut Bound,
+) -> Result<(), Error> {
+    match marked(param) {
+        Some(Marked::Optional(var, default)) => {
+            if arg.is_none() && !default.is_nil() {
+                bound.defaults.push((bound.bindings.len(), default));
+            }
+            bind_list(&var, arg, vmark, bound)
+        }
+        Some(Marked::Typed(var)) => bind_list(&var, arg, vmark, bound),
+        None => bind_list(param, arg, vmark, bound),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object;
+    use crate::parser;
+
+    fn bound_names(bound: &Bound) -> Vec<String> {
+        bound
+            .bindings
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect()
+    }
+
+    #[test]
+    fn can_bind_params() -> Result<(), Error> {
+        let params = parser::parse("(a (b c) (o d) (o e 'x) . f)")?;
+        assert_eq!(
+            names(&params, &nil!())
+                .iter()
+                .map(|name| name.name())
+                .collect::<Vec<_>>(),
+            vec!["a", "b", "c", "d", "e", "f"]
+        );
+
+        let bound = bind(&params, &parser::parse("(p (q r) s u v w)")?, &nil!())?;
+        assert_eq!(
+            bound_names(&bound),
+            vec!["a=p", "b=q", "c=r", "d=s", "e=u", "f=(v . (w . nil))"]
+        );
+        assert!(bound.defaults.is_empty());
+
+        let bound = bind(&params, &parser::parse("(p (q))")?, &nil!())?;
+        assert_eq!(
+            bound_names(&bound),
+            vec!["a=p", "b=q", "c=nil", "d=nil", "e=nil", "f=nil"]
+        );
+        assert_eq!(bound.defaults.len(), 1);
+        assert_eq!(bound.defaults[0].0, 4);
+        assert_eq!(bound.defaults[0].1, parser::parse("'x")?);
+
+        let bound = bind(&parser::parse("args")?, &parser::parse("(p q)")?, &nil!())?;
+        assert_eq!(bound_names(&bound), vec!["args=(p . (q . nil))"]);
+
+        let bound = bind(
+            &parser::parse("(x|pair)")?,
+            &parser::parse("((a))")?,
+            &nil!(),
+        )?;
+        assert_eq!(bound_names(&bound), vec!["x=(a . nil)"]);
+
+        assert!(bind(&parser::parse("(a)")?, &parser::parse("(p q)")?, &nil!()).is_err());
+        assert!(bind(&parser::parse("((a b))")?, &parser::parse("(p)")?, &nil!()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_bind_uvars() -> Result<(), Error> {
+        let vmark = pair!(nil!(), nil!());
+        let v = pair!(vmark.clone(), nil!());
+        let w = pair!(vmark.clone(), nil!());
+        let params = object::from_vec(vec![v.clone(), symbol!("a"), w.clone()])?;
+
+        let v_name = uvar_name(&v, &vmark).unwrap();
+        let w_name = uvar_name(&w, &vmark).unwrap();
+        assert_ne!(v_name, w_name);
+        assert_eq!(
+            names(&params, &vmark),
+            vec![v_name, Symbol::new("a"), w_name]
+        );
+
+        let bound = bind(&params, &parser::parse("(p q r)")?, &vmark)?;
+        assert_eq!(bound.bindings[0], (v_name, symbol!("p")));
+        assert_eq!(bound.bindings[2], (w_name, symbol!("r")));
+
+        // a list like a uvar, whose car isn't vmark itself, is a pattern
+        let like = pair!(pair!(nil!(), nil!()), nil!());
+        assert_eq!(uvar_name(&like, &vmark), None);
+
+        Ok(())
+    }
+}
@@ -1,293 +1,1199 @@
-use crate::object;
 use crate::object::Object;
 use anyhow::{anyhow, Error};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
 
-struct ParseState {
-    remainder: String,
-    obj: Option<Object>,
+/// A syntax error at a byte offset into the text being read
+#[derive(Debug)]
+pub struct SyntaxError {
+    offset: usize,
+    message: String,
+    // the text ran out part way through an object
+    incomplete: bool,
 }
 
-pub fn parse(text: &str) -> Result<Object, Error> {
-    let mut obj_accum: Vec<Object> = Vec::new();
-
-    let mut data = text.to_string();
-    while !data.is_empty() {
-        let state = dispatch_char(&data)?;
-        data = state.remainder;
-        if let Some(obj) = state.obj {
-            obj_accum.push(obj);
-        }
-    }
-
-    match obj_accum.len() {
-        0 => Ok(nil!()),
-        1 => Ok(obj_accum[0].clone()),
-        _ => Err(anyhow!("multiple objects: {:?}", obj_accum)),
-    }
-}
-
-fn dispatch_char(text: &str) -> Result<ParseState, Error> {
-    let state = consume_whitespace(text)?;
-    if state.remainder.is_empty() {
-        Ok(ParseState {
-            remainder: "".to_string(),
-            obj: None,
-        })
-    } else if state.remainder.starts_with('(') {
-        consume_parens(&state.remainder)
-    } else if state.remainder.starts_with(')') {
-        Ok(ParseState {
-            remainder: text[1..].to_string(),
-            obj: state.obj,
-        })
-    // the  spec https://sep.yimg.com/ty/cdn/paulgraham/bellanguage.txt?t=1595850613&
-    // defines slightly different usages for backtick and single quote
-    // but I haven't figured that out
-    } else if state.remainder.starts_with('`') || state.remainder.starts_with('\'') {
-        consume_quote(&state.remainder)
-    } else if state.remainder.starts_with('\\') {
-        consume_char(&state.remainder)
-    } else {
-        consume_symbol(&state.remainder)
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
     }
 }
 
-fn consume_whitespace(text: &str) -> Result<ParseState, Error> {
-    for (i, c) in text.chars().enumerate() {
-        if !c.is_whitespace() {
-            return Ok(ParseState {
-                remainder: text[i..].to_string(),
-                obj: None,
-            });
+impl std::error::Error for SyntaxError {}
+
+/// An error reading source text, with where it is.
+/// It displays as
+///     file.bel:12:5: unexpected ')'
+///         (id x))
+///               ^
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// the file the text came from, if it did
+    pub name: Option<String>,
+    pub position: Position,
+    pub message: String,
+    /// the line of the text the error is on
+    pub source_line: String,
+    /// the text ran out part way through an object, so more text could
+    /// finish it, e.g. an unclosed '('
+    pub incomplete: bool,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(name) = &self.name {
+            write!(f, "{}:", name)?;
         }
+        writeln!(f, "{}: {}", self.position, self.message)?;
+        writeln!(f, "{}", self.source_line)?;
+        write!(f, "{:>width$}", "^", width = self.position.column)
     }
+}
+
+impl std::error::Error for ParseError {}
 
-    Ok(ParseState {
-        remainder: "".to_string(),
-        obj: None,
-    })
+/// Where something was read from: the byte offset into the text, and the
+/// line and column, counting from 1, for people
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
 }
 
-fn consume_parens(text: &str) -> Result<ParseState, Error> {
-    if text.is_empty() {
-        return Err(anyhow!("consume_parens called with empty text"));
+impl Default for Position {
+    fn default() -> Self {
+        Position {
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
     }
+}
 
-    if !text.starts_with('(') {
-        return Err(anyhow!(
-            "consume_parens text does not start with '(' '{}'",
-            text
-        ));
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
     }
-    let mut data = text[1..].to_string();
+}
 
-    let mut vec_accum: Vec<Object> = Vec::new();
+/// What a stretch of the text read as, for laying the text out again:
+/// see Reader::tokens
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// ( or [
+    Open,
+    /// ) or ]
+    Close,
+    /// the dot in a dotted list
+    Dot,
+    /// ' ` , ,@ or #;, written in front of an object
+    Prefix,
+    /// a symbol, a char, or whatever a reader macro read
+    Atom,
+    /// ; to the end of the line, or a #| block |#
+    Comment,
+}
 
-    'dispatch_loop: while !data.is_empty() {
-        let state = dispatch_char(&data)?;
-        if let Some(obj) = state.obj {
-            vec_accum.push(obj);
-        }
-        if state.remainder.starts_with(')') {
-            data = state.remainder[1..].to_string();
-            break 'dispatch_loop;
+/// Where a token is in the text, as byte offsets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Reads what follows a char with syntax of its own; the char has been
+/// read
+pub type ReadMacro = Rc<dyn Fn(&mut Source) -> Result<Object, SyntaxError>>;
+
+/// The reader macros: chars with syntax of their own, and what reads what
+/// follows them, e.g. #{a b} read as (set-of a b).
+/// Clones share the table, so a Reader sees macros added while it reads,
+/// e.g. by a syn earlier in the file.
+#[derive(Clone, Default)]
+pub struct Syntax {
+    table: Rc<RefCell<HashMap<char, ReadMacro>>>,
+}
+
+impl Syntax {
+    // chars the reader handles itself can't be given other syntax
+    pub fn insert(
+        &self,
+        c: char,
+        f: impl Fn(&mut Source) -> Result<Object, SyntaxError> + 'static,
+    ) -> Result<(), Error> {
+        if is_builtin_char(c) {
+            return Err(anyhow!("{:?} is built into the reader", c));
         }
-        data = state.remainder;
+        self.table.borrow_mut().insert(c, Rc::new(f));
+        Ok(())
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        self.table.borrow().contains_key(&c)
+    }
+
+    fn get(&self, c: char) -> Option<ReadMacro> {
+        self.table.borrow().get(&c).cloned()
+    }
+}
+
+impl fmt::Debug for Syntax {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut chars: Vec<char> = self.table.borrow().keys().copied().collect();
+        chars.sort_unstable();
+        f.debug_tuple("Syntax").field(&chars).finish()
+    }
+}
+
+/// The text after a reader macro's char, for it to read from
+pub struct Source<'c, 'a> {
+    cursor: &'c mut Cursor<'a>,
+    // where the macro's char is
+    start: usize,
+}
+
+impl Source<'_, '_> {
+    pub fn peek(&self) -> Option<char> {
+        self.cursor.peek()
+    }
+
+    pub fn next_char(&mut self) -> Option<char> {
+        self.cursor.bump()
+    }
+
+    // the next object, as it would be read anywhere else
+    pub fn read(&mut self) -> Result<Object, SyntaxError> {
+        self.cursor.read()
     }
 
-    let obj_accum = object::from_vec(vec_accum)?;
+    // the objects up to close, which is read too
+    pub fn read_until(&mut self, close: char) -> Result<Vec<Object>, SyntaxError> {
+        let unclosed = format!("no '{}' to end this", close);
+        self.cursor.read_until(self.start, &unclosed, close)
+    }
 
-    Ok(ParseState {
-        remainder: data,
-        obj: Some(obj_accum),
-    })
+    // an error at the macro's char
+    pub fn error(&self, message: &str) -> SyntaxError {
+        self.cursor.error(self.start, message)
+    }
 }
 
-fn consume_quote(text: &str) -> Result<ParseState, Error> {
-    if text.is_empty() {
-        return Err(anyhow!("consume_quote called with empty text"));
+/// Reads the objects in a text one after another, with where each
+/// starts; e.g. the definitions in a source file.
+/// Comments between objects are skipped.
+pub struct Reader<'a> {
+    cursor: Cursor<'a>,
+    // where the last object started; lines and columns are counted as
+    // the reader goes, so they are only counted once
+    position: Position,
+    // where the text came from, for errors
+    name: Option<String>,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(text: &'a str) -> Self {
+        Reader {
+            cursor: Cursor::new(text),
+            position: Position::default(),
+            name: None,
+        }
     }
 
-    if !(text.starts_with('`') || text.starts_with('\'')) {
-        return Err(anyhow!(
-            "consume_quote text does not start with '`' or '\'' '{}'",
-            text
-        ));
+    // name errors with where the text came from, e.g. its file name
+    pub fn named(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
     }
-    let state = dispatch_char(&text[1..].to_string())?;
-    match state.obj {
-        Some(obj) => {
-            let mut obj_accum: Object = nil!();
-            obj_accum = object::join(obj, obj_accum)?;
-            obj_accum = object::join(symbol!("quote"), obj_accum)?;
 
-            Ok(ParseState {
-                remainder: state.remainder,
-                obj: Some(obj_accum),
-            })
+    // read with these reader macros as well as the built in syntax
+    pub fn with_syntax(mut self, syntax: &Syntax) -> Self {
+        self.cursor.syntax = syntax.clone();
+        self
+    }
+
+    /// Read the rest of the text, giving the tokens it's made of, in
+    /// order, comments included
+    pub fn tokens(mut self) -> Result<Vec<Token>, ParseError> {
+        self.cursor.tokens = Some(Vec::new());
+        for item in self.by_ref() {
+            item?;
         }
-        None => Err(anyhow!("consume_quote: quoted nil object")),
+        Ok(self.cursor.tokens.take().unwrap_or_default())
+    }
+
+    fn error(&self, message: String) -> ParseError {
+        let text = self.cursor.text;
+        let line_start = text[..self.position.offset]
+            .rfind('\n')
+            .map_or(0, |i| i + 1);
+        let source_line = text[line_start..].lines().next().unwrap_or("");
+        ParseError {
+            name: self.name.clone(),
+            position: self.position,
+            message,
+            source_line: source_line.to_string(),
+            incomplete: false,
+        }
+    }
+
+    // move the position on to offset, counting lines and columns on the way
+    fn advance(&mut self, offset: usize) {
+        for c in self.cursor.text[self.position.offset..offset].chars() {
+            if c == '\n' {
+                self.position.line += 1;
+                self.position.column = 1;
+            } else {
+                self.position.column += 1;
+            }
+        }
+        self.position.offset = offset;
     }
 }
 
-fn consume_char(text: &str) -> Result<ParseState, Error> {
-    let mut accum = String::new();
-    let mut i: usize = 0;
+impl Iterator for Reader<'_> {
+    type Item = Result<(Position, Object), ParseError>;
 
-    'char_loop: for c in text.chars() {
-        if is_boundaray_char(c) {
-            break 'char_loop;
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(err) = self.cursor.skip_space() {
+            return Some(Err(self.fail(err)));
+        }
+        if self.cursor.is_at_end() {
+            return None;
+        }
+        self.advance(self.cursor.offset);
+        let start = self.position;
+        match self.cursor.read() {
+            Ok(obj) => Some(Ok((start, obj))),
+            Err(err) => Some(Err(self.fail(err))),
         }
-        i += 1;
-        accum.push(c);
     }
+}
 
-    let obj = if accum.is_empty() {
-        None
-    } else {
-        Some(symbol!(&accum))
+impl Reader<'_> {
+    fn fail(&mut self, err: SyntaxError) -> ParseError {
+        self.advance(err.offset);
+        // don't carry on from the middle of something
+        self.cursor.offset = self.cursor.text.len();
+        ParseError {
+            incomplete: err.incomplete,
+            ..self.error(err.message)
+        }
+    }
+}
+
+// read the single object in text; nil if there isn't one
+pub fn parse(text: &str) -> Result<Object, Error> {
+    parse_with(text, &Syntax::default())
+}
+
+// parse, with reader macros
+pub fn parse_with(text: &str, syntax: &Syntax) -> Result<Object, Error> {
+    let mut reader = Reader::new(text).with_syntax(syntax);
+    let obj = match reader.next() {
+        None => return Ok(nil!()),
+        Some(item) => item?.1,
     };
+    if let Some(item) = reader.next() {
+        let (position, _) = item?;
+        reader.position = position;
+        return Err(reader
+            .error("expected one object; found more".to_string())
+            .into());
+    }
 
-    Ok(ParseState {
-        remainder: text[i..].to_string(),
-        obj,
-    })
+    Ok(obj)
 }
 
-fn consume_symbol(text: &str) -> Result<ParseState, Error> {
-    let mut accum = String::new();
-    let mut i: usize = 0;
+// Where we are in the text being read.
+// Reading moves the cursor forward through the text, once, and objects
+// are made from slices of it, so nothing is copied but their names.
+// Offsets are in bytes, and always on a char boundary.
+struct Cursor<'a> {
+    text: &'a str,
+    offset: usize,
+    syntax: Syntax,
+    // the tokens read, if they're wanted
+    tokens: Option<Vec<Token>>,
+}
 
-    'char_loop: for c in text.chars() {
-        if c.is_whitespace() || c == '(' || c == ')' {
-            break 'char_loop;
+impl<'a> Cursor<'a> {
+    fn new(text: &'a str) -> Self {
+        Cursor {
+            text,
+            offset: 0,
+            syntax: Syntax::default(),
+            tokens: None,
         }
-        i += 1;
-        accum.push(c);
     }
 
-    let obj = if accum.is_empty() {
-        None
-    } else {
-        Some(symbol!(&accum))
+    // note a token from start to where the cursor is, if they're wanted
+    fn token(&mut self, kind: TokenKind, start: usize) {
+        let end = self.offset;
+        if let Some(tokens) = &mut self.tokens {
+            tokens.push(Token { kind, start, end });
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.offset..]
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.offset == self.text.len()
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.offset += c.len_utf8();
+        Some(c)
+    }
+
+    // move past the chars for which f is true, returning them
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let start = self.offset;
+        while let Some(c) = self.peek() {
+            if !f(c) {
+                break;
+            }
+            self.offset += c.len_utf8();
+        }
+        &self.text[start..self.offset]
+    }
+
+    fn error(&self, offset: usize, message: &str) -> SyntaxError {
+        SyntaxError {
+            offset,
+            message: message.to_string(),
+            incomplete: self.is_at_end(),
+        }
+    }
+
+    // move past whitespace and comments:
+    //     ; to the end of the line
+    //     #| a block |#, which can have blocks inside
+    //     #; in front of an object, which is skipped too
+    fn skip_space(&mut self) -> Result<(), SyntaxError> {
+        loop {
+            // some sources start with a byte order mark
+            self.take_while(|c| c.is_whitespace() || c == '\u{feff}');
+            let rest = self.rest();
+            let start = self.offset;
+            if rest.starts_with(';') {
+                self.take_while(|c| c != '\n');
+                self.token(TokenKind::Comment, start);
+            } else if rest.starts_with("#|") {
+                self.skip_block_comment()?;
+                self.token(TokenKind::Comment, start);
+            } else if rest.starts_with("#;") {
+                self.offset += 2;
+                self.token(TokenKind::Prefix, start);
+                // #; #; a b skips both
+                self.skip_space()?;
+                if matches!(self.peek(), None | Some(')') | Some(']')) {
+                    return Err(self.error(start, "nothing to comment out after '#;'"));
+                }
+                self.read()?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn skip_block_comment(&mut self) -> Result<(), SyntaxError> {
+        let start = self.offset;
+        let mut depth = 0;
+        loop {
+            let rest = self.rest();
+            if rest.starts_with("#|") {
+                depth += 1;
+                self.offset += 2;
+            } else if rest.starts_with("|#") {
+                depth -= 1;
+                self.offset += 2;
+                if depth == 0 {
+                    return Ok(());
+                }
+            } else if self.bump().is_none() {
+                return Err(self.error(start, "unclosed '#|'"));
+            }
+        }
+    }
+
+    // read the object that starts after any whitespace and comments
+    fn read(&mut self) -> Result<Object, SyntaxError> {
+        self.skip_space()?;
+        match self.peek() {
+            None => Err(self.error(self.offset, "unexpected end of text")),
+            Some('(') => self.read_list(),
+            Some('[') => self.read_brackets(),
+            Some(')') => Err(self.error(self.offset, "unexpected ')'")),
+            Some(']') => Err(self.error(self.offset, "unexpected ']'")),
+            Some('\'') => self.read_prefixed("quote"),
+            Some('`') => self.read_prefixed("bquote"),
+            Some(',') => self.read_comma(),
+            Some('\\') => self.read_char(),
+            Some('|') => self.read_quoted_symbol(),
+            Some(c) => match self.syntax.get(c) {
+                Some(f) => self.read_macro(f),
+                None => self.read_symbol(),
+            },
+        }
+    }
+
+    fn read_macro(&mut self, f: ReadMacro) -> Result<Object, SyntaxError> {
+        let start = self.offset;
+        let count = self.tokens.as_ref().map_or(0, Vec::len);
+        self.bump();
+        let obj = f(&mut Source {
+            cursor: self,
+            start,
+        })?;
+        // what the macro read is laid out as it was written
+        if let Some(tokens) = &mut self.tokens {
+            tokens.truncate(count);
+        }
+        self.token(TokenKind::Atom, start);
+        Ok(obj)
+    }
+
+    // (a b c), or (a b . c), where the dot on its own comes before the
+    // last object and that object is the end of the list
+    fn read_list(&mut self) -> Result<Object, SyntaxError> {
+        let start = self.offset;
+        self.bump();
+        self.token(TokenKind::Open, start);
+        let mut items: Vec<Object> = Vec::new();
+        let mut tail = nil!();
+        loop {
+            self.skip_space()?;
+            match self.peek() {
+                None => return Err(self.error(start, "unclosed '('")),
+                Some(')') => {
+                    self.close();
+                    break;
+                }
+                Some('.') if self.is_at_dot() => {
+                    let dot = self.offset;
+                    self.bump();
+                    self.token(TokenKind::Dot, dot);
+                    self.skip_space()?;
+                    if items.is_empty() {
+                        return Err(self.error(dot, "nothing before '.'"));
+                    }
+                    match self.peek() {
+                        None => return Err(self.error(start, "unclosed '('")),
+                        Some(')') => return Err(self.error(dot, "nothing after '.'")),
+                        Some(_) => tail = self.read()?,
+                    }
+                    self.skip_space()?;
+                    match self.peek() {
+                        None => return Err(self.error(start, "unclosed '('")),
+                        Some(')') => {
+                            self.close();
+                            break;
+                        }
+                        Some(_) => return Err(self.error(dot, "more than one object after '.'")),
+                    }
+                }
+                Some(_) => items.push(self.read()?),
+            }
+        }
+
+        Ok(items
+            .into_iter()
+            .rev()
+            .fold(tail, |list, item| pair!(item, list)))
+    }
+
+    // move past the char that closes a list
+    fn close(&mut self) {
+        let start = self.offset;
+        self.bump();
+        self.token(TokenKind::Close, start);
+    }
+
+    // whether the next char is a dot on its own, rather than the start of
+    // a symbol such as .a
+    fn is_at_dot(&self) -> bool {
+        let mut chars = self.rest().chars();
+        chars.next() == Some('.') && chars.next().is_none_or(is_delimiter)
+    }
+
+    // [f _ x] is short for (fn (_) (f _ x)). Brackets inside have their
+    // own _, as they are fns of their own
+    fn read_brackets(&mut self) -> Result<Object, SyntaxError> {
+        let body = self.read_items('[', ']')?;
+        let parms = pair!(symbol!("_"), nil!());
+
+        Ok(pair!(symbol!("fn"), pair!(parms, pair!(body, nil!()))))
+    }
+
+    // the objects between open and close, as a list
+    fn read_items(&mut self, open: char, close: char) -> Result<Object, SyntaxError> {
+        let start = self.offset;
+        self.bump();
+        self.token(TokenKind::Open, start);
+        let items = self.read_until(start, &format!("unclosed '{}'", open), close)?;
+
+        Ok(items
+            .into_iter()
+            .rev()
+            .fold(nil!(), |list, item| pair!(item, list)))
+    }
+
+    // the objects up to close, and close; unclosed is the error, at start,
+    // if there isn't one
+    fn read_until(
+        &mut self,
+        start: usize,
+        unclosed: &str,
+        close: char,
+    ) -> Result<Vec<Object>, SyntaxError> {
+        let mut items: Vec<Object> = Vec::new();
+        loop {
+            // check for the closing paren first, or () would be read as the
+            // start of a list
+            self.skip_space()?;
+            match self.peek() {
+                None => return Err(self.error(start, unclosed)),
+                Some(c) if c == close => {
+                    self.close();
+                    return Ok(items);
+                }
+                Some(_) => items.push(self.read()?),
+            }
+        }
+    }
+
+    // 'a is (quote a), `a (bquote a)
+    fn read_prefixed(&mut self, name: &str) -> Result<Object, SyntaxError> {
+        let start = self.offset;
+        self.bump();
+        self.token(TokenKind::Prefix, start);
+        self.read_after(start, name)
+    }
+
+    // ,a is (comma a), ,@a (comma-at a)
+    fn read_comma(&mut self) -> Result<Object, SyntaxError> {
+        let start = self.offset;
+        self.bump();
+        if self.peek() == Some('@') {
+            self.bump();
+            self.token(TokenKind::Prefix, start);
+            self.read_after(start, "comma-at")
+        } else {
+            self.token(TokenKind::Prefix, start);
+            self.read_after(start, "comma")
+        }
+    }
+
+    // (name obj), where obj is what's next
+    fn read_after(&mut self, start: usize, name: &str) -> Result<Object, SyntaxError> {
+        self.skip_space()?;
+        if self.is_at_end() {
+            return Err(self.error(start, "nothing to quote"));
+        }
+        let obj = self.read()?;
+
+        Ok(pair!(symbol!(name), pair!(obj, nil!())))
+    }
+
+    // \a, \sp, \( and so on; the character after the backslash is part of
+    // the name, even if it would otherwise end it
+    fn read_char(&mut self) -> Result<Object, SyntaxError> {
+        let start = self.offset;
+        self.bump();
+        if self.bump().is_none() {
+            return Err(self.error(start, "expected a character after \\"));
+        }
+        self.take_while(|c| !is_boundaray_char(c));
+        self.token(TokenKind::Atom, start);
+
+        Ok(Object::Char(self.text[start + 1..self.offset].to_string()))
+    }
+
+    // |a b| is the symbol named "a b"; \ escapes the char after it, so
+    // |a\|b| is named "a|b"
+    fn read_quoted_symbol(&mut self) -> Result<Object, SyntaxError> {
+        let start = self.offset;
+        self.bump();
+        let mut name = String::new();
+        loop {
+            match self.bump() {
+                Some('|') => break,
+                Some('\\') => match self.bump() {
+                    Some(c) => name.push(c),
+                    None => return Err(self.error(start, "unclosed '|'")),
+                },
+                Some(c) => name.push(c),
+                None => return Err(self.error(start, "unclosed '|'")),
+            }
+        }
+        self.token(TokenKind::Atom, start);
+        // it ends where a symbol would, e.g. before a reader macro's }
+        match self.peek() {
+            Some(c) if !(is_delimiter(c) || self.syntax.contains(c)) => Err(self.error(
+                self.offset,
+                "expected a space or a paren after a quoted symbol",
+            )),
+            _ => Ok(symbol!(name)),
+        }
+    }
+
+    fn read_symbol(&mut self) -> Result<Object, SyntaxError> {
+        let start = self.offset;
+        let syntax = self.syntax.clone();
+        let word = self.take_while(|c| !(is_delimiter(c) || syntax.contains(c)));
+        self.token(TokenKind::Atom, start);
+        parse_word(word).map_err(|message| self.error(start, message))
+    }
+}
+
+/// Whether a symbol's name, printed as it is, reads back as that symbol.
+/// If not it's printed as |name|. Names starting with a digit are quoted
+/// too, as they will be read as numbers. Reader macros added to a Syntax
+/// aren't taken into account.
+pub fn reads_as_symbol(name: &str) -> bool {
+    let first = match name.chars().next() {
+        Some(c) => c,
+        None => return false,
     };
+    !(first.is_ascii_digit()
+        || matches!(first, '\'' | '`' | ',' | '\\' | '~' | '\u{feff}')
+        || name.contains(|c| is_delimiter(c) || matches!(c, '|' | ':' | '.' | '!')))
+}
+
+// A word is a symbol, or intrafix shorthand for a list of them:
+//     a|b    (t a b)
+//     a.b    (a b)
+//     a!b    (a 'b)
+//     .a     (upon a)
+//     a:b    (compose a b)
+//     ~a     (compose no a)
+// in that order of precedence, so a|b.c is (t a (b c)).
+fn parse_word(word: &str) -> Result<Object, &'static str> {
+    if word == "." {
+        // a dot on its own outside a list
+        Ok(symbol!(word))
+    } else if word.contains('|') {
+        parse_typed(word)
+    } else if word.contains(is_intrafix_char) {
+        parse_access(word)
+    } else {
+        Ok(parse_compose(word))
+    }
+}
 
-    Ok(ParseState {
-        remainder: text[i..].to_string(),
-        obj,
-    })
+fn is_intrafix_char(c: char) -> bool {
+    c == '.' || c == '!'
 }
 
-fn is_boundaray_char(c: char) -> bool {
-    c.is_whitespace() || c == '(' || c == ')' || c == '\\' || c == '`' || c == '\''
+// var|type, as in a typed parameter
+fn parse_typed(word: &str) -> Result<Object, &'static str> {
+    if word.matches('|').count() > 1 {
+        return Err("more than one '|'");
+    }
+    let parts: Vec<&str> = word.split('|').filter(|part| !part.is_empty()).collect();
+    match parts.as_slice() {
+        [var, typ] => Ok(pair!(
+            symbol!("t"),
+            pair!(parse_word(var)?, pair!(parse_word(typ)?, nil!()))
+        )),
+        _ => Err("expected something either side of '|'"),
+    }
+}
+
+// a.b.c is (a b c); a word after ! is quoted
+fn parse_access(word: &str) -> Result<Object, &'static str> {
+    let mut items: Vec<Object> = Vec::new();
+    let mut cursor = Cursor::new(word);
+    let first = cursor.take_while(|c| !is_intrafix_char(c));
+    items.push(if first.is_empty() {
+        symbol!("upon")
+    } else {
+        parse_compose(first)
+    });
+    while !cursor.is_at_end() {
+        let sep = cursor.take_while(is_intrafix_char);
+        let part = cursor.take_while(|c| !is_intrafix_char(c));
+        if sep.len() > 1 {
+            return Err("more than one '.' or '!' together");
+        }
+        if part.is_empty() {
+            return Err("nothing after '.' or '!'");
+        }
+        let item = parse_compose(part);
+        items.push(if sep == "!" {
+            pair!(symbol!("quote"), pair!(item, nil!()))
+        } else {
+            item
+        });
+    }
+
+    Ok(items
+        .into_iter()
+        .rev()
+        .fold(nil!(), |list, item| pair!(item, list)))
+}
+
+fn parse_compose(word: &str) -> Object {
+    if !word.contains(':') {
+        return parse_no(word);
+    }
+    let parts: Vec<&str> = word.split(':').filter(|part| !part.is_empty()).collect();
+    let fns = parts
+        .into_iter()
+        .rev()
+        .fold(nil!(), |list, part| pair!(parse_no(part), list));
+    pair!(symbol!("compose"), fns)
+}
+
+// ~ on its own is no
+fn parse_no(word: &str) -> Object {
+    match word.strip_prefix('~') {
+        Some("") => symbol!("no"),
+        Some(rest) => pair!(
+            symbol!("compose"),
+            pair!(symbol!("no"), pair!(parse_no(rest), nil!()))
+        ),
+        None => symbol!(word),
+    }
+}
+
+// what the reader does with these is fixed
+fn is_builtin_char(c: char) -> bool {
+    is_boundaray_char(c) || c == '|' || is_intrafix_char(c) || c == ':' || c == '~' || c == ','
+}
+
+fn is_bracket(c: char) -> bool {
+    c == '(' || c == ')' || c == '[' || c == ']'
+}
+
+/// Whether c ends a symbol
+pub fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || is_bracket(c) || c == ';'
+}
+
+/// Whether c ends a character's name, e.g. \sp
+pub fn is_boundaray_char(c: char) -> bool {
+    is_delimiter(c) || c == '\\' || c == '`' || c == '\''
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::object;
 
     #[test]
-    fn can_consume_symbol() -> Result<(), Error> {
-        let parse_state = consume_symbol("")?;
-        assert!(parse_state.remainder.is_empty());
-        assert!(parse_state.obj.is_none());
+    fn can_read_symbol() -> Result<(), Error> {
+        let mut cursor = Cursor::new("a");
+        assert!(cursor.read()?.is_symbol("a"));
+        assert!(cursor.rest().is_empty());
 
-        let parse_state = consume_symbol("a")?;
-        assert!(parse_state.remainder.is_empty());
-        assert!(parse_state.obj.unwrap().is_symbol("a"));
+        let mut cursor = Cursor::new("ab(c");
+        assert!(cursor.read()?.is_symbol("ab"));
+        assert_eq!(cursor.rest(), "(c");
 
-        let parse_state = consume_whitespace("(")?;
-        assert_eq!(parse_state.remainder, "(");
-        assert!(parse_state.obj.is_none());
+        let mut cursor = Cursor::new("λx é");
+        assert!(cursor.read()?.is_symbol("λx"));
+        assert_eq!(cursor.rest(), " é");
+        assert!(cursor.read()?.is_symbol("é"));
+
+        assert!(Cursor::new("").read().is_err());
 
         Ok(())
     }
 
     #[test]
-    fn can_consume_whitespacel() -> Result<(), Error> {
-        let parse_state = consume_whitespace("")?;
-        assert!(parse_state.remainder.is_empty());
-        assert!(parse_state.obj.is_none());
+    fn can_skip_whitespace() -> Result<(), Error> {
+        let mut cursor = Cursor::new("");
+        cursor.skip_space()?;
+        assert!(cursor.rest().is_empty());
+
+        let mut cursor = Cursor::new("a");
+        cursor.skip_space()?;
+        assert_eq!(cursor.rest(), "a");
 
-        let parse_state = consume_whitespace("a")?;
-        assert_eq!(parse_state.remainder, "a");
-        assert!(parse_state.obj.is_none());
+        let mut cursor = Cursor::new("    (");
+        cursor.skip_space()?;
+        assert_eq!(cursor.rest(), "(");
 
-        let parse_state = consume_whitespace("    (")?;
-        assert_eq!(parse_state.remainder, "(");
-        assert!(parse_state.obj.is_none());
+        let mut cursor = Cursor::new(" ; comment\n ; more\n(");
+        cursor.skip_space()?;
+        assert_eq!(cursor.rest(), "(");
+
+        let mut cursor = Cursor::new("#| a #| nested |# block |# #; (a b) #;#;c d (");
+        cursor.skip_space()?;
+        assert_eq!(cursor.rest(), "(");
 
         Ok(())
     }
 
     #[test]
-    fn can_consume_parens() -> Result<(), Error> {
-        let parse_state = consume_parens("()")?;
-        assert!(parse_state.remainder.is_empty());
-        assert!(parse_state.obj.unwrap().is_nil());
-
-        let parse_state = consume_parens("( a )")?;
-        assert!(
-            parse_state.remainder.is_empty(),
-            "remainder.is_empty() {:?}",
-            parse_state.remainder
+    fn can_read_comments() -> Result<(), Error> {
+        assert_eq!(
+            parse("(def f (x) ; f of x\n  (id x ; x\n nil));done")?,
+            parse("(def f (x) (id x nil))")?
         );
-        assert_eq!(parse_state.obj.unwrap().to_vec()?, vec![symbol!("a")]);
+        assert_eq!(parse("(a;b\nc)")?, parse("(a c)")?);
+        assert_eq!(parse("(a #| b |# c #|d|#)")?, parse("(a c)")?);
+        assert_eq!(parse("(a #;(b c) d #; e)")?, parse("(a d)")?);
+        assert_eq!(parse("'#;a b")?, parse("'b")?);
+        assert_eq!(parse("[f #;_ x]")?, parse("[f x]")?);
+        assert!(parse("; nothing but a comment\n#| and a block |#")?.is_nil());
+        assert_eq!(parse("\\; ; the char ;")?, Object::Char(";".to_string()));
+        assert_eq!(parse("(\\a;b\n)")?, parse("(\\a)")?);
 
-        let parse_state = consume_parens("( a b )")?;
+        let err = parse("(a #;)").unwrap_err();
         assert!(
-            parse_state.remainder.is_empty(),
-            "remainder.is_empty() {:?}",
-            parse_state.remainder
+            err.to_string().starts_with("1:4: nothing to comment out"),
+            "{}",
+            err
         );
+        let err = parse("(a #| b)").unwrap_err();
+        assert!(err.to_string().starts_with("1:4: unclosed '#|'"), "{}", err);
+        let err = parse("a\n#|").unwrap_err();
+        assert!(err.to_string().starts_with("2:1: unclosed '#|'"), "{}", err);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_give_tokens() -> Result<(), Error> {
+        use TokenKind::*;
+        let text = "(a . ,@b) ; c\n'#;[x] #|d|# \\e";
+        let tokens: Vec<(TokenKind, &str)> = Reader::new(text)
+            .tokens()?
+            .into_iter()
+            .map(|token| (token.kind, &text[token.start..token.end]))
+            .collect();
         assert_eq!(
-            parse_state.obj.unwrap().to_vec()?,
-            vec![symbol!("a"), symbol!("b")]
+            tokens,
+            vec![
+                (Open, "("),
+                (Atom, "a"),
+                (Dot, "."),
+                (Prefix, ",@"),
+                (Atom, "b"),
+                (Close, ")"),
+                (Comment, "; c"),
+                (Prefix, "'"),
+                (Prefix, "#;"),
+                (Open, "["),
+                (Atom, "x"),
+                (Close, "]"),
+                (Comment, "#|d|#"),
+                (Atom, "\\e"),
+            ]
         );
 
-        let parse_state = consume_parens("( a b (c d))")?;
-        assert!(
-            parse_state.remainder.is_empty(),
-            "remainder.is_empty() {:?}",
-            parse_state.remainder
-        );
+        // what a reader macro reads is one token
+        let syntax = Syntax::default();
+        syntax.insert('$', |source| source.read())?;
+        let text = "(f $(a b))";
+        let tokens = Reader::new(text).with_syntax(&syntax).tokens()?;
+        assert_eq!(tokens.len(), 4);
+        assert_eq!(&text[tokens[2].start..tokens[2].end], "$(a b)");
+        assert!(Reader::new("(a").tokens().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_read_list() -> Result<(), Error> {
+        let mut cursor = Cursor::new("()");
+        assert!(cursor.read()?.is_nil());
+        assert!(cursor.rest().is_empty());
+
+        let mut cursor = Cursor::new("( a )");
+        assert_eq!(cursor.read()?.to_vec()?, vec![symbol!("a")]);
+        assert!(cursor.rest().is_empty());
+
+        let mut cursor = Cursor::new("( a b )");
+        assert_eq!(cursor.read()?.to_vec()?, vec![symbol!("a"), symbol!("b")]);
+        assert!(cursor.rest().is_empty());
+
+        let mut cursor = Cursor::new("( a b (c d))");
         assert_eq!(
-            parse_state.obj.unwrap().to_vec()?,
+            cursor.read()?.to_vec()?,
             vec![
                 symbol!("a"),
                 symbol!("b"),
                 pair!(symbol!("c"), pair!(symbol!("d"), nil!()))
             ]
         );
+        assert!(cursor.rest().is_empty());
 
-        let parse_state = consume_parens("( a nil )")?;
-        assert!(
-            parse_state.remainder.is_empty(),
-            "remainder.is_empty() {:?}",
-            parse_state.remainder
+        let mut cursor = Cursor::new("( a nil )");
+        assert_eq!(cursor.read()?, pair!(symbol!("a"), pair!(nil!(), nil!())));
+        assert!(cursor.rest().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_read_quote() -> Result<(), Error> {
+        let mut cursor = Cursor::new("'a");
+        assert_eq!(
+            cursor.read()?.to_vec()?,
+            vec![symbol!("quote"), symbol!("a")]
         );
+        assert!(cursor.rest().is_empty());
+
+        let mut cursor = Cursor::new("`(a ,b ,@c)");
+        let comma = |name: &str, arg: &str| pair!(symbol!(name), pair!(symbol!(arg), nil!()));
         assert_eq!(
-            parse_state.obj.unwrap(),
-            pair!(symbol!("a"), pair!(nil!(), nil!()))
+            cursor.read()?.to_vec()?,
+            vec![
+                symbol!("bquote"),
+                object::from_vec(vec![
+                    symbol!("a"),
+                    comma("comma", "b"),
+                    comma("comma-at", "c")
+                ])?
+            ]
         );
+        assert!(cursor.rest().is_empty());
 
         Ok(())
     }
 
     #[test]
-    fn can_consume_quote() -> Result<(), Error> {
-        let parse_state = consume_quote("`a")?;
-        assert!(parse_state.remainder.is_empty());
+    fn can_read_dotted_list() -> Result<(), Error> {
+        let mut cursor = Cursor::new("(a . b)");
+        assert_eq!(cursor.read()?, pair!(symbol!("a"), symbol!("b")));
+
+        let mut cursor = Cursor::new("(a b . (c))");
         assert_eq!(
-            parse_state.obj.unwrap().to_vec()?,
-            vec![symbol!("quote"), symbol!("a")]
+            cursor.read()?.to_vec()?,
+            vec![symbol!("a"), symbol!("b"), symbol!("c")]
+        );
+
+        // a dot in a word is intrafix
+        let mut cursor = Cursor::new("(a .b)");
+        assert_eq!(
+            cursor.read()?.to_vec()?,
+            vec![
+                symbol!("a"),
+                pair!(symbol!("upon"), pair!(symbol!("b"), nil!()))
+            ]
+        );
+
+        for text in ["(. a)", "(a .)", "(a . b c)"] {
+            assert!(Cursor::new(text).read().is_err(), "{}", text);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_read_char() -> Result<(), Error> {
+        let mut cursor = Cursor::new("\\a b");
+        assert_eq!(cursor.read()?, char!("a"));
+        assert_eq!(cursor.rest(), " b");
+
+        let mut cursor = Cursor::new("\\sp)");
+        assert_eq!(cursor.read()?, char!("sp"));
+        assert_eq!(cursor.rest(), ")");
+
+        let mut cursor = Cursor::new("\\()");
+        assert_eq!(cursor.read()?, char!("("));
+        assert_eq!(cursor.rest(), ")");
+
+        let mut cursor = Cursor::new("\\λ\\é");
+        assert_eq!(cursor.read()?, char!("λ"));
+        assert_eq!(cursor.read()?, char!("é"));
+
+        assert!(Cursor::new("\\").read().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_read_brackets() -> Result<(), Error> {
+        assert_eq!(parse("[f _ x]")?, parse("(fn (_) (f _ x))")?);
+        assert_eq!(parse("[]")?, parse("(fn (_) nil)")?);
+        assert_eq!(
+            parse("(all [id _ (car args)] (cdr args))")?,
+            parse("(all (fn (_) (id _ (car args))) (cdr args))")?
+        );
+        // the inner _ is the inner fn's parameter
+        assert_eq!(
+            parse("[map [cons _ x] _]")?,
+            parse("(fn (_) (map (fn (_) (cons _ x)) _))")?
+        );
+        assert_eq!(parse("[is \\;]")?, parse("(fn (_) (is \\;))")?);
+
+        let err = parse("[f _)").unwrap_err();
+        assert!(
+            err.to_string().starts_with("1:5: unexpected ')'"),
+            "{}",
+            err
+        );
+        let err = parse("(f _]").unwrap_err();
+        assert!(
+            err.to_string().starts_with("1:5: unexpected ']'"),
+            "{}",
+            err
         );
+        let err = parse("[f _").unwrap_err();
+        assert!(err.to_string().starts_with("1:1: unclosed '['"), "{}", err);
 
-        let parse_state = consume_quote("`(a)")?;
-        assert!(parse_state.remainder.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn can_read_quoted_symbol() -> Result<(), Error> {
+        assert!(parse("|a b|")?.is_symbol("a b"));
+        assert!(parse("||")?.is_symbol(""));
+        assert!(parse("|(x)|")?.is_symbol("(x)"));
+        assert!(parse("|1st|")?.is_symbol("1st"));
+        assert!(parse("|a\\|b\\\\c|")?.is_symbol("a|b\\c"));
+        assert!(parse("|a:b.c|")?.is_symbol("a:b.c"));
+        assert!(parse("|; not a comment|")?.is_symbol("; not a comment"));
+        assert_eq!(parse("(|x y| |z|)")?, parse("(|x y| z)")?);
+        assert_eq!(parse("'|x y|")?.to_vec()?[1], symbol!("x y"));
+
+        let err = parse("(|a b)").unwrap_err();
+        assert!(err.to_string().starts_with("1:2: unclosed '|'"), "{}", err);
+        let err = parse("|a|b").unwrap_err();
+        assert!(
+            err.to_string().starts_with("1:4: expected a space"),
+            "{}",
+            err
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn prints_symbols_so_they_read_back() {
+        for name in ["a", "car", "+", "fn-1-λ", "_", "nil", "a#b", "x'y"] {
+            assert_eq!(symbol!(name).to_string(), name);
+        }
+        for (name, printed) in [
+            ("", "||"),
+            ("a b", "|a b|"),
+            ("(", "|(|"),
+            ("1st", "|1st|"),
+            ("car:cdr", "|car:cdr|"),
+            ("~no", "|~no|"),
+            ("a|b", "|a\\|b|"),
+            ("\\", "|\\\\|"),
+            ("'a", "|'a|"),
+            (".", "|.|"),
+            (",a", "|,a|"),
+            ("#|", "|#\\||"),
+        ] {
+            assert_eq!(symbol!(name).to_string(), printed);
+        }
+    }
+
+    #[test]
+    fn can_read_intrafix() -> Result<(), Error> {
+        // every form bel.bel uses
+        assert_eq!(parse("car:cddr")?, parse("(compose car cddr)")?);
+        assert_eq!(parse("rev:cdr:rev")?, parse("(compose rev cdr rev)")?);
+        assert_eq!(parse("-:floor:-")?, parse("(compose - floor -)")?);
+        assert_eq!(parse("~even")?, parse("(compose no even)")?);
+        assert_eq!(parse("~=")?, parse("(compose no =)")?);
+        assert_eq!(parse("~f:car")?, parse("(compose (compose no f) car)")?);
+        assert_eq!(parse("~")?, parse("no")?);
+        assert_eq!(parse("~~x")?, parse("(compose no (compose no x))")?);
+        assert_eq!(parse("s|source")?, parse("(t s source)")?);
+        assert_eq!(parse("a|isa!arr")?, parse("(t a (isa (quote arr)))")?);
+        assert_eq!(parse("isa!tab")?, parse("(isa (quote tab))")?);
+        assert_eq!(parse("templates.name")?, parse("(templates name)")?);
+        assert_eq!(parse("a.b!c.d")?, parse("(a b (quote c) d)")?);
+        assert_eq!(parse(".a")?, parse("(upon a)")?);
+        assert_eq!(parse("!a")?, parse("(upon (quote a))")?);
+        assert_eq!(parse("f.x:y")?, parse("(f (compose x y))")?);
+        assert_eq!(parse("(x . y)")?, pair!(symbol!("x"), symbol!("y")));
+        assert_eq!(parse("\\.")?, Object::Char(".".to_string()));
+
+        let err = parse("(a b.)").unwrap_err();
+        assert!(err.to_string().starts_with("1:4: nothing after"), "{}", err);
+        let err = parse("a..b").unwrap_err();
+        assert!(
+            err.to_string().starts_with("1:1: more than one '.'"),
+            "{}",
+            err
+        );
+        let err = parse("a|b|c").unwrap_err();
+        assert!(
+            err.to_string().starts_with("1:1: more than one '|'"),
+            "{}",
+            err
+        );
+        assert!(parse("a|").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_read_objects_in_turn() -> Result<(), Error> {
+        let text = "\u{feff}; comment\n(def no (x)\n\n  (id x nil)) ; no\na (b) ; c\n\n'é";
+        let objs: Vec<(Position, Object)> = Reader::new(text).collect::<Result<_, _>>()?;
+        let positions: Vec<String> = objs
+            .iter()
+            .map(|(position, _)| position.to_string())
+            .collect();
+        assert_eq!(positions, vec!["2:1", "5:1", "5:3", "7:1"]);
+        assert_eq!(objs[0].0.offset, "\u{feff}; comment\n".len());
+        assert_eq!(objs[0].1, parse("(def no (x) (id x nil))")?);
+        assert_eq!(objs[3].1, parse("(quote é)")?);
+
+        Ok(())
+    }
+
+    fn parse_error(text: &str) -> ParseError {
+        match Reader::new(text).named("t.bel").find_map(|item| item.err()) {
+            Some(err) => err,
+            None => panic!("expected an error reading {:?}", text),
+        }
+    }
+
+    #[test]
+    fn knows_when_text_stops_part_way() {
+        for text in ["(def f (x)\n", "'", "[a (b", "#| a", "|a b", "#;", "\\"] {
+            assert!(parse_error(text).incomplete, "{:?}", text);
+        }
+        for text in ["(a))", "(a b]", "#;)", "a.\n", "|a|b\n"] {
+            assert!(!parse_error(text).incomplete, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn reports_where_errors_are() -> Result<(), Error> {
+        let err = parse_error("(def f (x)\n  (id x nil)))\n");
+        assert_eq!(err.position.to_string(), "2:14");
+        assert_eq!(
+            err.to_string(),
+            "t.bel:2:14: unexpected ')'\n  (id x nil)))\n             ^"
+        );
+
+        let err = parse_error("a\n (def f (x)\n  (id x nil)\n");
+        assert_eq!(err.position.to_string(), "2:2");
+        assert_eq!(err.message, "unclosed '('");
+
+        let err = parse_error("(a ')");
+        assert_eq!(err.position.to_string(), "1:5");
+        assert_eq!(err.message, "unexpected ')'");
+
+        let err = parse_error("'");
+        assert_eq!(err.message, "nothing to quote");
+
+        let err = parse("a\n(b c)").unwrap_err();
         assert_eq!(
-            parse_state.obj.unwrap().to_vec()?,
-            vec![symbol!("quote"), pair!(symbol!("a"), nil!())]
+            err.to_string(),
+            "2:1: expected one object; found more\n(b c)\n^"
         );
 
         Ok(())
@@ -301,6 +1207,9 @@ mod tests {
         let obj = parse("(nil)")?;
         assert_eq!(obj, pair!(nil!(), nil!()));
 
+        let obj = parse("(a () b)")?;
+        assert_eq!(obj.to_vec()?, vec![symbol!("a"), nil!(), symbol!("b")]);
+
         Ok(())
     }
 }
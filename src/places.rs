@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+
+use crate::list::List;
+use crate::object;
+use crate::object::Object;
+use crate::symbol::Symbol;
+use anyhow::{anyhow, Error, Result};
+use log::debug;
+
+/// A place is something that can be assigned to:
+/// a variable, or the car or cdr of a pair.
+/// (where e) finds the place designated by the expression e.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Place {
+    /// a local variable if one is bound, otherwise a global
+    Var(Symbol),
+    /// the car of a pair
+    Car(Object),
+    /// the cdr of a pair
+    Cdr(Object),
+}
+
+impl Place {
+    // the place a (cell loc) list, as bel.bel's where returns, designates
+    pub fn from_where(obj: &Object) -> Result<Place, Error> {
+        match obj.to_vec()?.as_slice() {
+            [cell @ Object::Pair(_), loc] if loc.is_symbol("a") => Ok(Place::Car(cell.clone())),
+            [cell @ Object::Pair(_), loc] if loc.is_symbol("d") => Ok(Place::Cdr(cell.clone())),
+            _ => Err(anyhow!("invalid place: {}", obj)),
+        }
+    }
+}
+
+/// The shape of a place expression, recorded while its subexpressions
+/// are evaluated, so the place can be found from their values.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlaceExp {
+    /// a symbol; nothing needs evaluating
+    Var(Symbol),
+    /// (f a1 ... an) where f names a locfn; a1 ... an are evaluated
+    Loc(Symbol, usize),
+    /// (f a1 ... an) where f names a place defined with loc; its function
+    /// is called with a1 ... an, giving (cell loc)
+    Where,
+    /// (e a1 ... an) where e should evaluate to a table;
+    /// e and a1 ... an are evaluated
+    Table(usize),
+}
+
+/// Operators that modify places.
+/// bel.bel defines these as macros on top of where, we implement
+/// them directly. They run atomically.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Modify {
+    /// (where place)
+    Where,
+    /// (set place value), one of the assignments a set makes
+    Set,
+    /// (zap op place . args)
+    Zap,
+    /// (push x place)
+    Push,
+    /// (pull x place (o f =))
+    Pull,
+    /// (pop place)
+    Pop,
+    /// (swap . places)
+    Swap,
+    /// (wipe . places)
+    Wipe,
+    /// (clean f place)
+    Clean,
+    /// (pushnew x place (o f =))
+    PushNew,
+}
+
+impl Modify {
+    pub fn from_name(name: &str) -> Option<Modify> {
+        match name {
+            "where" => Some(Modify::Where),
+            "zap" => Some(Modify::Zap),
+            "push" => Some(Modify::Push),
+            "pull" => Some(Modify::Pull),
+            "pop" => Some(Modify::Pop),
+            "swap" => Some(Modify::Swap),
+            "wipe" => Some(Modify::Wipe),
+            "clean" => Some(Modify::Clean),
+            "pushnew" => Some(Modify::PushNew),
+            _ => None,
+        }
+    }
+}
+
+// split the expression of a place into its shape and the subexpressions
+// that need to be evaluated. The places defined with loc, an alist of
+// (name . fn), come before the built in locfns.
+pub fn place_exp(
+    exp: &Object,
+    defined: &Object,
+    locfns: &HashMap<Symbol, LocFunc>,
+) -> Result<(PlaceExp, Vec<Object>), Error> {
+    match exp {
+        Object::Symbol(name) => {
+            if exp.is_nil() || matches!(exp, Object::Symbol(Symbol::T)) {
+                Err(anyhow!("cannot assign to {}", name))
+            } else {
+                Ok((PlaceExp::Var(*name), Vec::new()))
+            }
+        }
+        Object::Pair(_) => {
+            let (head, args) = exp.extract_pair()?;
+            let mut subexps = args.to_vec()?;
+            if let Some(f) = defined_place(&head, defined)? {
+                // ('f a1 ... an)
+                let f = pair!(symbol!("quote"), pair!(f, nil!()));
+                return Ok((PlaceExp::Where, vec![pair!(f, args)]));
+            }
+            match head {
+                Object::Symbol(name) if locfns.contains_key(&name) => {
+                    Ok((PlaceExp::Loc(name, subexps.len()), subexps))
+                }
+                _ => {
+                    let place = PlaceExp::Table(subexps.len());
+                    subexps.insert(0, head);
+                    Ok((place, subexps))
+                }
+            }
+        }
+        _ => Err(anyhow!("unfindable place: {}", exp)),
+    }
+}
+
+// the function loc defined for the place name, if it did
+fn defined_place(name: &Object, defined: &Object) -> Result<Option<Object>, Error> {
+    if !matches!(name, Object::Symbol(_)) {
+        return Ok(None);
+    }
+    let mut list = List::new(defined);
+    while let Some(entry) = list.step()? {
+        let (key, f) = entry.extract_pair()?;
+        if &key == name {
+            return Ok(Some(f));
+        }
+    }
+    Ok(None)
+}
+
+// find the place given the values of its subexpressions,
+// taking as many values as it needs
+pub fn resolve(
+    place_exp: &PlaceExp,
+    values: &mut impl Iterator<Item = Object>,
+    locfns: &HashMap<Symbol, LocFunc>,
+) -> Result<Place, Error> {
+    debug!("resolve: {:?}", place_exp);
+    match place_exp {
+        PlaceExp::Var(name) => Ok(Place::Var(*name)),
+        PlaceExp::Loc(name, n) => {
+            let args = object::from_vec(values.take(*n).collect())?;
+            match locfns.get(name) {
+                Some(locfn) => locfn(&args),
+                None => Err(anyhow!("unknown locfn {}", name)),
+            }
+        }
+        PlaceExp::Where => {
+            let value = values
+                .next()
+                .ok_or_else(|| anyhow!("resolve: missing value"))?;
+            Place::from_where(&value)
+        }
+        PlaceExp::Table(n) => {
+            let head = values
+                .next()
+                .ok_or_else(|| anyhow!("resolve: missing value"))?;
+            let args: Vec<Object> = values.take(*n).collect();
+            if !is_table(&head) {
+                return Err(anyhow!("unfindable place: {}", head));
+            }
+            if args.len() != 1 {
+                return Err(anyhow!("table place: expecting a single key"));
+            }
+            Ok(Place::Cdr(tabloc(&head, &args[0])?))
+        }
+    }
+}
+
+/// finds the place designated by an expression (f a1 ... an)
+/// given the evaluated arguments a1 ... an
+pub type LocFunc = fn(&Object) -> Result<Place, Error>;
+
+pub fn load_locfns() -> HashMap<Symbol, LocFunc> {
+    HashMap::from([
+        (Symbol::new("car"), car as LocFunc),
+        (Symbol::new("cdr"), cdr as LocFunc),
+        (Symbol::new("tabref"), tabref as LocFunc),
+    ])
+}
+
+fn single_pair(name: &str, args: &Object) -> Result<Object, Error> {
+    let a_v = args.to_vec()?;
+    if a_v.len() != 1 {
+        return Err(anyhow!("{}: expecting a single argument: {}", name, args));
+    }
+    if let Object::Pair(_) = a_v[0] {
+        Ok(a_v[0].clone())
+    } else {
+        Err(anyhow!("{}: place is not a pair: {}", name, a_v[0]))
+    }
+}
+
+fn car(args: &Object) -> Result<Place, Error> {
+    Ok(Place::Car(single_pair("car", args)?))
+}
+
+fn cdr(args: &Object) -> Result<Place, Error> {
+    Ok(Place::Cdr(single_pair("cdr", args)?))
+}
+
+// (tabref tab key) designates the cdr of the table entry for key,
+// which is created if it doesn't exist yet
+fn tabref(args: &Object) -> Result<Place, Error> {
+    let a_v = args.to_vec()?;
+    if a_v.len() < 2 {
+        return Err(anyhow!("tabref: expecting table and key: {}", args));
+    }
+    Ok(Place::Cdr(tabloc(&a_v[0], &a_v[1])?))
+}
+
+pub fn is_table(obj: &Object) -> bool {
+    match obj.extract_pair() {
+        Ok((lit, rest)) => {
+            matches!(lit, Object::Symbol(Symbol::LIT))
+                && matches!(rest.extract_pair(), Ok((tag, _)) if tag.is_symbol("tab"))
+        }
+        Err(_) => false,
+    }
+}
+
+// the (key . value) pair for key in tab, if there is one
+pub fn table_entry(tab: &Object, key: &Object) -> Result<Option<Object>, Error> {
+    if !is_table(tab) {
+        return Err(anyhow!("expecting table found: {}", tab));
+    }
+    let (_, rest) = tab.extract_pair()?;
+    let (_, kvs) = rest.extract_pair()?;
+    let mut list = List::new(&kvs);
+    while let Some(kv) = list.step()? {
+        let (k, _) = kv.extract_pair()?;
+        if &k == key {
+            return Ok(Some(kv));
+        }
+    }
+
+    Ok(None)
+}
+
+// the (key . value) pair for key in tab, added to the table with
+// a value of nil if it isn't there already
+pub fn tabloc(tab: &Object, key: &Object) -> Result<Object, Error> {
+    if let Some(kv) = table_entry(tab, key)? {
+        return Ok(kv);
+    }
+
+    debug!("tabloc: adding {} to {}", key, tab);
+    let kv = pair!(key.clone(), nil!());
+    let (_, rest) = tab.extract_pair()?;
+    let (_, kvs) = rest.extract_pair()?;
+    rest.set_cdr(pair!(kv.clone(), kvs))?;
+    Ok(kv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    #[test]
+    fn can_resolve_place() -> Result<(), Error> {
+        let locfns = load_locfns();
+        let defined = nil!();
+
+        let (place, subexps) = place_exp(&symbol!("x"), &defined, &locfns)?;
+        assert_eq!(place, PlaceExp::Var(Symbol::new("x")));
+        assert!(subexps.is_empty());
+        assert!(place_exp(&nil!(), &defined, &locfns).is_err());
+
+        let (place, subexps) = place_exp(&parser::parse("(cdr (f x))")?, &defined, &locfns)?;
+        assert_eq!(place, PlaceExp::Loc(Symbol::new("cdr"), 1));
+        assert_eq!(subexps, vec![parser::parse("(f x)")?]);
+        let cell = parser::parse("(a b)")?;
+        let resolved = resolve(&place, &mut vec![cell.clone()].into_iter(), &locfns)?;
+        assert_eq!(resolved, Place::Cdr(cell));
+        assert!(resolve(&place, &mut vec![nil!()].into_iter(), &locfns).is_err());
+
+        let (place, subexps) = place_exp(&parser::parse("(tab 'k)")?, &defined, &locfns)?;
+        assert_eq!(place, PlaceExp::Table(1));
+        assert_eq!(subexps.len(), 2);
+        let tab = parser::parse("(lit tab)")?;
+        let resolved = resolve(&place, &mut vec![tab, symbol!("k")].into_iter(), &locfns)?;
+        assert_eq!(resolved, Place::Cdr(parser::parse("(k)")?));
+
+        // places defined with loc come first, and give (cell loc)
+        let defined = pair!(pair!(symbol!("cdr"), symbol!("f")), nil!());
+        let (place, subexps) = place_exp(&parser::parse("(cdr x)")?, &defined, &locfns)?;
+        assert_eq!(place, PlaceExp::Where);
+        assert_eq!(subexps, vec![parser::parse("('f x)")?]);
+        let resolved = resolve(
+            &place,
+            &mut vec![parser::parse("((a) d)")?].into_iter(),
+            &locfns,
+        )?;
+        assert_eq!(resolved, Place::Cdr(parser::parse("(a)")?));
+        assert!(resolve(
+            &place,
+            &mut vec![parser::parse("(a d)")?].into_iter(),
+            &locfns
+        )
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_find_table_entry() -> Result<(), Error> {
+        let tab = parser::parse("(lit tab)")?;
+        assert!(is_table(&tab));
+        assert!(table_entry(&tab, &symbol!("a"))?.is_none());
+
+        // tabloc adds the entry, and it is shared with the table
+        let kv = tabloc(&tab, &symbol!("a"))?;
+        kv.set_cdr(symbol!("b"))?;
+        let found = table_entry(&tab, &symbol!("a"))?.unwrap();
+        assert!(found.is_same_pair(&kv));
+        assert!(tabloc(&tab, &symbol!("a"))?.is_same_pair(&kv));
+        assert_eq!(tab.to_vec()?.len(), 3);
+
+        assert!(table_entry(&symbol!("a"), &symbol!("a")).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,889 @@
+use crate::object::{Label, Labels, Object};
+use crate::parser::{Reader, Token, TokenKind};
+use anyhow::{anyhow, Error};
+
+/// The width lines are kept to, where they can be
+pub const WIDTH: usize = 80;
+
+// Forms whose first few arguments go on the first line, with the rest,
+// the body, on lines of their own indented by two:
+//     (def no (x)
+//       (id x nil))
+const BODY_FORMS: &[(&str, usize)] = &[
+    ("def", 2),
+    ("mac", 2),
+    ("form", 2),
+    ("syn", 2),
+    ("vir", 2),
+    ("com", 2),
+    ("loc", 2),
+    ("let", 2),
+    ("rfn", 2),
+    ("whilet", 2),
+    ("each", 2),
+    ("bind", 2),
+    ("fn", 1),
+    ("fu", 1),
+    ("afn", 1),
+    ("letu", 1),
+    ("with", 1),
+    ("withs", 1),
+];
+
+// Forms made of test and then pairs, laid out in two columns when there
+// are enough of them to be worth it:
+//     (if (no x)   (err 'x)
+//         (atom x) x
+//                  (car x))
+// with this many arguments before the pairs
+const CLAUSE_FORMS: &[(&str, usize)] = &[("if", 0), ("aif", 0), ("case", 1), ("pcase", 1)];
+
+// Forms whose first argument is a list of variable and value pairs
+const BINDING_FORMS: &[&str] = &["with", "withs"];
+
+// A list with more than one list in it is broken up if it's longer than
+// this, even if it fits, as they're hard to read on one line
+const MAX_FLAT: usize = 40;
+
+// Forms that define things, whose bodies always go on lines of their own
+// when an object is printed
+const DEFINING_FORMS: &[&str] = &["def", "mac", "form", "syn", "vir", "com", "loc"];
+
+// Blank lines in a row are kept, up to this many
+const MAX_BLANK_LINES: usize = 2;
+
+// What the pretty printer lays out: the text as it was read, down to the
+// atoms, with the comments kept, and where things were in it. The layout of
+// the text is kept where it can be: a list written on one line stays on one
+// line if it fits, and one broken over lines is broken in the same places.
+// Objects have no text, and are laid out from scratch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    // a symbol or a character, as it was written
+    Atom(String),
+    List {
+        open: char,
+        close: char,
+        items: Vec<Item>,
+        // the column it opened at in the text, if it was read from text
+        column: Option<usize>,
+    },
+    // ' or ` or #; or anything else written right before a node, e.g. ,@
+    Prefix(String, Box<Node>),
+    // ; to the end of the line, or a #| block |#; trailing if it came after
+    // something else on its line
+    Comment {
+        text: String,
+        trailing: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Item {
+    node: Node,
+    // how many blank lines there were before it
+    blank_lines: usize,
+    // where it was in the text, if it was read from text
+    placement: Option<Placement>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Placement {
+    // it was the first thing on its line
+    starts_line: bool,
+    column: usize,
+    // the spaces between it and the item before, if it didn't start a line
+    spaces: usize,
+}
+
+impl Item {
+    fn starts_line(&self) -> bool {
+        self.placement
+            .is_some_and(|placement| placement.starts_line)
+    }
+}
+
+impl Node {
+    fn is_comment(&self) -> bool {
+        matches!(self, Node::Comment { .. })
+    }
+
+    // a list, perhaps quoted
+    fn is_list(&self) -> bool {
+        match self {
+            Node::List { .. } => true,
+            Node::Prefix(_, node) => node.is_list(),
+            _ => false,
+        }
+    }
+
+    fn atom(&self) -> Option<&str> {
+        match self {
+            Node::Atom(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    // the node on one line, if it can be: comments and blank lines have to
+    // stay on lines of their own. Items are spaced as they were in the
+    // text, as they may have been lined up with something.
+    fn flat(&self) -> Option<String> {
+        match self {
+            Node::Atom(text) if text.contains('\n') => None,
+            Node::Atom(text) => Some(text.clone()),
+            Node::Prefix(prefix, node) => Some(format!("{}{}", prefix, node.flat()?)),
+            Node::List {
+                open, close, items, ..
+            } => {
+                let mut flat = open.to_string();
+                for (i, item) in items.iter().enumerate() {
+                    if item.blank_lines > 0 {
+                        return None;
+                    }
+                    if i > 0 {
+                        flat.extend(std::iter::repeat_n(' ', spaces_before(item)));
+                    }
+                    flat.push_str(&item.node.flat()?);
+                }
+                flat.push(*close);
+                Some(flat)
+            }
+            Node::Comment { .. } => None,
+        }
+    }
+
+    // it was read from text, and written on one line there
+    fn is_written_flat(&self) -> bool {
+        match self {
+            Node::List {
+                items,
+                column: Some(_),
+                ..
+            } => items
+                .iter()
+                .all(|item| !item.starts_line() && item.node.is_written_flat()),
+            Node::List { column: None, .. } => false,
+            Node::Prefix(_, node) => node.is_written_flat(),
+            _ => true,
+        }
+    }
+}
+
+// the spaces between an item and the one before it on its line: as many as
+// there were in the text, or one
+fn spaces_before(item: &Item) -> usize {
+    item.placement
+        .map_or(1, |placement| placement.spaces.max(1))
+}
+
+// the width of text on screen, near enough
+fn width(text: &str) -> usize {
+    text.chars().count()
+}
+
+// Columns tabs are taken to be at multiples of
+const TAB_WIDTH: usize = 8;
+
+// the column at the end of text, a line, counting from 0
+fn column_after(text: &str) -> usize {
+    text.chars().fold(0, |column, c| match c {
+        '\t' => (column / TAB_WIDTH + 1) * TAB_WIDTH,
+        _ => column + 1,
+    })
+}
+
+// Source text made into Nodes from the tokens the reader found in it, and
+// the space between them
+struct Layout<'a> {
+    text: &'a str,
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+    // where the last token ended
+    offset: usize,
+}
+
+impl<'a> Layout<'a> {
+    fn new(text: &'a str) -> Result<Self, Error> {
+        Ok(Layout {
+            text,
+            tokens: Reader::new(text).tokens()?.into_iter().peekable(),
+            offset: 0,
+        })
+    }
+
+    // the column of offset, counting from 0
+    fn column(&self, offset: usize) -> usize {
+        let line_start = self.text[..offset].rfind('\n').map_or(0, |i| i + 1);
+        column_after(&self.text[line_start..offset])
+    }
+
+    fn next(&mut self) -> Result<(Token, &'a str), Error> {
+        let token = self
+            .tokens
+            .next()
+            .ok_or_else(|| anyhow!("unexpected end of text"))?;
+        self.offset = token.end;
+        Ok((token, &self.text[token.start..token.end]))
+    }
+
+    // the items up to the end of the list, or the text if not in a list
+    fn items(&mut self, in_list: bool) -> Result<Vec<Item>, Error> {
+        let mut items: Vec<Item> = Vec::new();
+        loop {
+            let start = match self.tokens.peek() {
+                None if in_list => return Err(anyhow!("unclosed list")),
+                None => return Ok(items),
+                Some(token) if token.kind == TokenKind::Close => {
+                    self.next()?;
+                    return Ok(items);
+                }
+                Some(token) => token.start,
+            };
+            let newlines = self.text[self.offset..start].matches('\n').count();
+            let placement = Placement {
+                starts_line: newlines > 0,
+                column: self.column(start),
+                spaces: match newlines {
+                    0 => self.column(start) - self.column(self.offset),
+                    _ => 0,
+                },
+            };
+            let blank_lines = if items.is_empty() {
+                0
+            } else {
+                newlines.saturating_sub(1).min(MAX_BLANK_LINES)
+            };
+            let trailing = newlines == 0 && !items.is_empty();
+            // comments between a prefix and what it's in front of go
+            // before it
+            let mut comments: Vec<String> = Vec::new();
+            let mut node = self.node(&mut comments)?;
+            if let Node::Comment { trailing: t, .. } = &mut node {
+                *t = trailing;
+            }
+            let hoisted = !comments.is_empty();
+            for (i, text) in comments.into_iter().enumerate() {
+                items.push(Item {
+                    node: Node::Comment {
+                        text,
+                        trailing: i == 0 && trailing,
+                    },
+                    blank_lines: if i == 0 { blank_lines } else { 0 },
+                    placement: Some(placement),
+                });
+            }
+            items.push(Item {
+                node,
+                blank_lines: if hoisted { 0 } else { blank_lines },
+                placement: Some(Placement {
+                    starts_line: placement.starts_line || hoisted,
+                    ..placement
+                }),
+            });
+        }
+    }
+
+    fn node(&mut self, comments: &mut Vec<String>) -> Result<Node, Error> {
+        let (token, text) = self.next()?;
+        Ok(match token.kind {
+            TokenKind::Open => {
+                let open = text.chars().next().unwrap_or('(');
+                Node::List {
+                    open,
+                    close: if open == '[' { ']' } else { ')' },
+                    column: Some(self.column(token.start)),
+                    items: self.items(true)?,
+                }
+            }
+            TokenKind::Close => return Err(anyhow!("unexpected '{}'", text)),
+            TokenKind::Comment => Node::Comment {
+                text: text.trim_end().to_string(),
+                trailing: false,
+            },
+            TokenKind::Prefix => {
+                while let Some(Token {
+                    kind: TokenKind::Comment,
+                    ..
+                }) = self.tokens.peek()
+                {
+                    let (_, comment) = self.next()?;
+                    comments.push(comment.trim_end().to_string());
+                }
+                Node::Prefix(text.to_string(), Box::new(self.node(comments)?))
+            }
+            TokenKind::Atom | TokenKind::Dot => Node::Atom(text.to_string()),
+        })
+    }
+}
+
+// Lays out Nodes, keeping lines to width where it can
+struct Printer {
+    out: String,
+    width: usize,
+}
+
+impl Printer {
+    fn column(&self) -> usize {
+        let line_start = self.out.rfind('\n').map_or(0, |i| i + 1);
+        width(&self.out[line_start..])
+    }
+
+    fn newline(&mut self, indent: usize, blank_lines: usize) {
+        let trimmed = self.out.trim_end_matches(' ').len();
+        self.out.truncate(trimmed);
+        self.out.extend(std::iter::repeat_n('\n', blank_lines + 1));
+        self.out.extend(std::iter::repeat_n(' ', indent));
+    }
+
+    fn fits(&self, text: &str) -> bool {
+        self.column() + width(text) <= self.width
+    }
+
+    fn top_level(&mut self, items: &[Item]) {
+        for (i, item) in items.iter().enumerate() {
+            match &item.node {
+                Node::Comment {
+                    text,
+                    trailing: true,
+                } => {
+                    self.out.push(' ');
+                    self.out.push_str(text);
+                }
+                node => {
+                    if i > 0 {
+                        self.newline(0, item.blank_lines);
+                    }
+                    self.node(node);
+                }
+            }
+        }
+        if !items.is_empty() {
+            self.out.push('\n');
+        }
+    }
+
+    fn node(&mut self, node: &Node) {
+        match node {
+            Node::Atom(text) | Node::Comment { text, .. } => self.out.push_str(text),
+            Node::Prefix(prefix, node) => {
+                self.out.push_str(prefix);
+                self.node(node);
+            }
+            Node::List {
+                open,
+                close,
+                items,
+                column,
+            } => {
+                let written_flat = node.is_written_flat();
+                match (node.flat(), column) {
+                    (Some(flat), Some(_)) if written_flat && self.fits(&flat) => {
+                        self.out.push_str(&flat)
+                    }
+                    (Some(flat), None) if self.fits(&flat) && !always_breaks(items, &flat) => {
+                        self.out.push_str(&flat)
+                    }
+                    (_, Some(column)) if !written_flat => {
+                        self.as_written(*open, *close, items, *column)
+                    }
+                    _ => self.list(*open, *close, items),
+                }
+            }
+        }
+    }
+
+    // A list broken over lines in the text, broken in the same places. An
+    // item that started a line is lined up with what it was lined up with
+    // there: an item before it in the list, or the list's body. If it
+    // wasn't lined up with anything, it goes where list would put it.
+    fn as_written(&mut self, open: char, close: char, items: &[Item], column: usize) {
+        let col = self.column();
+        let head = items.first().and_then(|item| item.node.atom());
+        let body = head.and_then(|head| BODY_FORMS.iter().find(|(name, _)| *name == head));
+        let indent = |i: usize| match (head, body) {
+            (Some(_), Some((_, count))) if i > *count => col + 2,
+            (Some(head), _) => col + 1 + width(head) + 1,
+            (None, _) => col + 1,
+        };
+        // the columns things started at in the text, and where they are now
+        let mut columns = vec![(column + 1, col + 1), (column + 2, col + 2)];
+
+        self.out.push(open);
+        let mut after_comment = false;
+        for (i, item) in items.iter().enumerate() {
+            let placement = item.placement.unwrap_or_default();
+            match &item.node {
+                Node::Comment {
+                    text,
+                    trailing: true,
+                } => {
+                    self.out.push(' ');
+                    self.out.push_str(text);
+                }
+                node => {
+                    if i > 0 && (placement.starts_line || after_comment) {
+                        let lined_up = columns
+                            .iter()
+                            .find(|(source, _)| *source == placement.column)
+                            .map(|(_, now)| *now);
+                        self.newline(lined_up.unwrap_or_else(|| indent(i)), item.blank_lines);
+                    } else if i > 0 {
+                        self.out
+                            .extend(std::iter::repeat_n(' ', spaces_before(item)));
+                    }
+                    columns.push((placement.column, self.column()));
+                    self.node(node);
+                }
+            }
+            after_comment = item.node.is_comment();
+        }
+        // a close paren after a comment would be commented out
+        if after_comment {
+            self.newline(indent(items.len()), 0);
+        }
+        self.out.push(close);
+    }
+
+    fn list(&mut self, open: char, close: char, items: &[Item]) {
+        let col = self.column();
+        let head = match items.first().and_then(|item| item.node.atom()) {
+            Some(head) => head,
+            None => {
+                // data: everything lined up under the first item
+                self.out.push(open);
+                return self.items(close, items, 0, 1, col + 1);
+            }
+        };
+        if let Some((_, count)) = CLAUSE_FORMS.iter().find(|(name, _)| *name == head) {
+            if self.clauses(open, close, items, 1 + count) {
+                return;
+            }
+        }
+        self.out.push(open);
+        match BODY_FORMS.iter().find(|(name, _)| *name == head) {
+            Some((_, count)) if BINDING_FORMS.contains(&head) => {
+                self.out.push_str(head);
+                match items.get(1).filter(|item| !item.node.is_comment()) {
+                    Some(bindings) => {
+                        self.out.push(' ');
+                        self.bindings(&bindings.node);
+                        self.items(close, items, 2, 1 + count, col + 2)
+                    }
+                    None => self.items(close, items, 1, 1 + count, col + 2),
+                }
+            }
+            Some((_, count)) => self.items(close, items, 0, 1 + count, col + 2),
+            // a call: the arguments lined up under the first
+            None => self.items(close, items, 0, 2, col + 1 + width(head) + 1),
+        }
+    }
+
+    // the items of a list from start, those before first_line on the line
+    // the list starts on, the rest each on a line of its own at indent;
+    // the list is open, and the items before start written
+    fn items(
+        &mut self,
+        close: char,
+        items: &[Item],
+        start: usize,
+        first_line: usize,
+        indent: usize,
+    ) {
+        let mut after_comment = false;
+        for (i, item) in items.iter().enumerate().skip(start) {
+            match &item.node {
+                Node::Comment {
+                    text,
+                    trailing: true,
+                } => {
+                    self.out.push(' ');
+                    self.out.push_str(text);
+                }
+                node => {
+                    if i > 0 && (i >= first_line || after_comment) {
+                        self.newline(indent, item.blank_lines);
+                    } else if i > 0 {
+                        self.out.push(' ');
+                    }
+                    self.node(node);
+                }
+            }
+            after_comment = item.node.is_comment();
+        }
+        // a close paren after a comment would be commented out
+        if after_comment {
+            self.newline(indent, 0);
+        }
+        self.out.push(close);
+    }
+
+    // Test and then pairs in two columns, after lead items on the first
+    // line; false, having written nothing, if they don't suit it
+    fn clauses(&mut self, open: char, close: char, items: &[Item], lead: usize) -> bool {
+        if items.len() < lead + 4
+            || items
+                .iter()
+                .any(|item| item.blank_lines > 0 || item.node.is_comment())
+        {
+            return false;
+        }
+        let col = self.column();
+        let lead_flat: Option<Vec<String>> =
+            items[..lead].iter().map(|item| item.node.flat()).collect();
+        let lead_flat = match lead_flat {
+            Some(lead_flat) => lead_flat,
+            None => return false,
+        };
+        let clauses = &items[lead..];
+        let tests: Option<Vec<String>> = clauses
+            .chunks(2)
+            .filter(|pair| pair.len() == 2)
+            .map(|pair| pair[0].node.flat())
+            .collect();
+        let tests = match tests {
+            Some(tests) => tests,
+            None => return false,
+        };
+        // if's clauses start on its first line, case's on the next
+        let test_col = match lead {
+            0 => col + 1,
+            1 => col + 1 + width(&lead_flat[0]) + 1,
+            _ => col + 2,
+        };
+        let then_col = test_col + tests.iter().map(|test| width(test)).max().unwrap_or(0) + 1;
+        let thens_fit = clauses.chunks(2).all(|pair| {
+            let then = &pair[pair.len() - 1].node;
+            then.flat()
+                .is_some_and(|flat| then_col + width(&flat) <= self.width)
+        });
+        if !thens_fit && then_col > self.width / 2 {
+            return false;
+        }
+
+        self.out.push(open);
+        self.out.push_str(&lead_flat.join(" "));
+        for (i, pair) in clauses.chunks(2).enumerate() {
+            if pair.len() == 1 {
+                self.newline(then_col, 0);
+                self.node(&pair[0].node);
+                break;
+            }
+            if i == 0 && lead == 1 {
+                self.out.push(' ');
+            } else if i > 0 || lead > 1 {
+                self.newline(test_col, 0);
+            }
+            self.out.push_str(&tests[i]);
+            let padding = then_col - self.column();
+            self.out.extend(std::iter::repeat_n(' ', padding));
+            self.node(&pair[1].node);
+        }
+        self.out.push(close);
+        true
+    }
+
+    // (v1 e1 v2 e2 ...) as pairs, as in with
+    fn bindings(&mut self, node: &Node) {
+        match node {
+            Node::List {
+                open, close, items, ..
+            } if node.flat().is_none_or(|flat| !self.fits(&flat)) => {
+                if !self.clauses(*open, *close, items, 0) {
+                    self.node(node);
+                }
+            }
+            _ => self.node(node),
+        }
+    }
+}
+
+// definitions with bodies, ifs with more than one test and long lists of
+// lists are never written on one line
+fn always_breaks(items: &[Item], flat: &str) -> bool {
+    let lists = items.iter().filter(|item| item.node.is_list()).count();
+    match items.first().and_then(|item| item.node.atom()) {
+        Some(head) if DEFINING_FORMS.contains(&head) => items.len() > 3,
+        Some("if") | Some("aif") if items.len() > 5 => true,
+        _ => lists > 1 && width(flat) > MAX_FLAT,
+    }
+}
+
+// Lay out the text of a Bel source file, keeping its comments. Fails if
+// the text can't be read, or if the result wouldn't read as the same
+// objects, which would be a bug here.
+pub fn format_text(text: &str, width: usize) -> Result<String, Error> {
+    let before = read_all(text)?;
+    let items = Layout::new(text)?.items(false)?;
+    let mut printer = Printer {
+        out: String::new(),
+        width,
+    };
+    // keep a byte order mark
+    if text.starts_with('\u{feff}') {
+        printer.out.push('\u{feff}');
+    }
+    printer.top_level(&items);
+
+    let after = read_all(&printer.out)
+        .map_err(|err| anyhow!("formatting made text that can't be read: {}", err))?;
+    if after != before {
+        return Err(anyhow!("formatting would change what the text reads as"));
+    }
+    Ok(printer.out)
+}
+
+fn read_all(text: &str) -> Result<Vec<Object>, Error> {
+    Reader::new(text)
+        .map(|item| item.map(|(_, obj)| obj).map_err(Error::from))
+        .collect()
+}
+
+// Lay out obj as format_text would its source
+pub fn pretty(obj: &Object, width: usize) -> String {
+    let mut printer = Printer {
+        out: String::new(),
+        width,
+    };
+    printer.node(&to_node(obj, &mut Labels::new(obj)));
+    printer.out
+}
+
+/// obj on one line, written as pretty writes it when it fits
+pub fn flat(obj: &Object) -> String {
+    let node = to_node(obj, &mut Labels::new(obj));
+    // only comments and blank lines keep a node off one line, and objects
+    // have neither
+    node.flat().unwrap_or_else(|| pretty(obj, usize::MAX))
+}
+
+// pairs that something in them refers back to are labelled #n=, and
+// written #n# after that, as printing them in full would never end
+fn to_node(obj: &Object, labels: &mut Labels) -> Node {
+    match obj {
+        Object::Pair(_) => match labels.label(obj) {
+            Label::Refer(label) => Node::Atom(format!("#{}#", label)),
+            Label::Define(label) => {
+                Node::Prefix(format!("#{}=", label), Box::new(list_node(obj, labels)))
+            }
+            Label::None => list_node(obj, labels),
+        },
+        Object::Char(name) => Node::Atom(format!("\\{}", name)),
+        _ => Node::Atom(obj.to_string()),
+    }
+}
+
+fn list_node(obj: &Object, labels: &mut Labels) -> Node {
+    // (quote x) is written 'x, as it would be in source, and so on
+    if let Some((prefix, quoted)) = quoted(obj) {
+        return Node::Prefix(prefix.to_string(), Box::new(to_node(&quoted, labels)));
+    }
+    let mut items: Vec<Item> = Vec::new();
+    let mut rest = obj.clone();
+    while let Object::Pair(pair) = &rest {
+        // a labelled pair has to be written on its own, after a dot
+        if !items.is_empty() && labels.is_labelled(&rest) {
+            break;
+        }
+        let (car, cdr) = pair.get();
+        items.push(Item {
+            node: to_node(&car, labels),
+            blank_lines: 0,
+            placement: None,
+        });
+        rest = cdr;
+    }
+    if !rest.is_nil() {
+        for node in [Node::Atom(".".to_string()), to_node(&rest, labels)] {
+            items.push(Item {
+                node,
+                blank_lines: 0,
+                placement: None,
+            });
+        }
+    }
+    Node::List {
+        open: '(',
+        close: ')',
+        items,
+        column: None,
+    }
+}
+
+// The forms the reader makes of prefixes, and the prefixes
+const PREFIXES: &[(&str, &str)] = &[
+    ("quote", "'"),
+    ("bquote", "`"),
+    ("comma", ","),
+    ("comma-at", ",@"),
+];
+
+// the prefix and x, if obj is (quote x) or another of PREFIXES
+fn quoted(obj: &Object) -> Option<(&'static str, Object)> {
+    let v = obj.to_vec().ok().filter(|v| v.len() == 2)?;
+    let name = match &v[0] {
+        Object::Symbol(name) => name.name(),
+        _ => return None,
+    };
+    PREFIXES
+        .iter()
+        .find(|(form, _)| *form == name)
+        .map(|(_, prefix)| (*prefix, v[1].clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn check(text: &str, expected: &str, width: usize) -> Result<(), Error> {
+        let formatted = format_text(text, width)?;
+        assert_eq!(formatted, expected, "\n{}", formatted);
+        // and formatting again changes nothing
+        assert_eq!(format_text(&formatted, width)?, formatted);
+        Ok(())
+    }
+
+    #[test]
+    fn can_lay_out_calls() -> Result<(), Error> {
+        // the text's line breaks and spacing are kept, where they fit
+        check("(f   a\n  b)", "(f   a\n  b)\n", WIDTH)?;
+        check(
+            "(if p\n    (f a)\n    b)",
+            "(if p\n    (f a)\n    b)\n",
+            WIDTH,
+        )?;
+        check(
+            "(def pair   (x) (id x))",
+            "(def pair   (x) (id x))\n",
+            WIDTH,
+        )?;
+        // an item not lined up with anything goes under the first argument
+        check("(f a\n       b)", "(f a\n   b)\n", WIDTH)?;
+        check("(f (g a\n b))", "(f (g a\n      b))\n", WIDTH)?;
+        check(
+            "(cons (list s r) (snoc p (list s r)) g)",
+            "(cons (list s r)\n      (snoc p (list s r))\n      g)\n",
+            30,
+        )?;
+        // data, and calls of calls
+        check(
+            "((f x) (aa bb) (cc dd))",
+            "((f x)\n (aa bb)\n (cc dd))\n",
+            12,
+        )?;
+        check("'(a b c d)", "'(a b\n    c\n    d)\n", 6)?;
+        Ok(())
+    }
+
+    #[test]
+    fn can_lay_out_bodies() -> Result<(), Error> {
+        check(
+            "(def no (x) (id x nil))",
+            "(def no (x)\n  (id x nil))\n",
+            16,
+        )?;
+        check(
+            "(mac letu (v . body) `(let ,v (uvar) ,@body))",
+            "(mac letu (v . body)\n  `(let ,v (uvar)\n     ,@body))\n",
+            24,
+        )?;
+        check(
+            "(with (v (uvar) ps (parameters (car parms))) (f v ps))",
+            "(with (v  (uvar)\n       ps (parameters (car parms)))\n  (f v ps))\n",
+            40,
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn can_lay_out_clauses() -> Result<(), Error> {
+        check(
+            "(if (no (cdr args)) (car args) (no (car args)) (apply append (cdr args)) (cons (car (car args)) (apply append (cons (cdr (car args)) (cdr args)))))",
+            "(if (no (cdr args)) (car args)\n    (no (car args)) (apply append (cdr args))\n                    (cons (car (car args))\n                          (apply append (cons (cdr (car args)) (cdr args)))))\n",
+            WIDTH,
+        )?;
+        // one test isn't worth two columns
+        check(
+            "(if (no s) (car r) (sched (snoc p (list s r)) g))",
+            "(if (no s)\n    (car r)\n    (sched (snoc p (list s r)) g))\n",
+            40,
+        )?;
+        check(
+            "(case e scope (cons e a) globe (cons e g))",
+            "(case e\n  scope (cons e a)\n  globe (cons e g))\n",
+            30,
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_comments() -> Result<(), Error> {
+        check(
+            "; no\n\n\n(def no (x) ; x is anything\n  ; is it nil?\n  (id x nil))  ; done\n#| block\n  comment |#\n(a #;(b c) d)",
+            "; no\n\n\n(def no (x) ; x is anything\n  ; is it nil?\n  (id x nil)) ; done\n#| block\n  comment |#\n(a #;(b c) d)\n",
+            WIDTH,
+        )?;
+        check("a\n\n\n\n\nb\n\n", "a\n\n\nb\n", WIDTH)?;
+        // a comment before the close paren
+        check("(a b ; c\n)", "(a b ; c\n   )\n", WIDTH)?;
+        check("(f ; first\n   a b)", "(f ; first\n   a b)\n", WIDTH)?;
+        check("(f a b ; c\n   d)", "(f a b ; c\n   d)\n", WIDTH)?;
+        // one between a prefix and what it's in front of goes before them
+        check(
+            "(list '; why\n      (a b) c)",
+            "(list ; why\n      '(a b) c)\n",
+            WIDTH,
+        )?;
+        check("(f #; ; gone\n   a b)", "(f ; gone\n   #;a b)\n", WIDTH)?;
+        Ok(())
+    }
+
+    #[test]
+    fn expands_tabs() -> Result<(), Error> {
+        // tabs are to the next multiple of 8 columns, so a body indented
+        // with one is indented as a body
+        check("(def f (x)\n\t(car x))", "(def f (x)\n  (car x))\n", WIDTH)?;
+        // and spacing with them is kept as it looked
+        check(
+            "(if (no x)\t(f a)\n\t(g b))",
+            "(if (no x)      (f a)\n    (g b))\n",
+            WIDTH,
+        )?;
+        assert_eq!(column_after("\t"), 8);
+        assert_eq!(column_after("ab\tc\t"), 16);
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_what_text_reads_as() -> Result<(), Error> {
+        check("(f ,@(cdr args) x)", "(f ,@(cdr args) x)\n", WIDTH)?;
+        check("(\\a \\sp |a b| [_ x])", "(\\a \\sp |a b| [_ x])\n", WIDTH)?;
+        check("", "", WIDTH)?;
+        assert!(format_text("(a b", WIDTH).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn can_pretty_print_objects() -> Result<(), Error> {
+        let obj = parser::parse("(def no (x) (id x '\\a))")?;
+        assert_eq!(pretty(&obj, WIDTH), "(def no (x)\n  (id x '\\a))");
+        let obj = parser::parse("(id x '(quote a b) '(c d e f))")?;
+        assert_eq!(pretty(&obj, WIDTH), "(id x '(quote a b) '(c d e f))");
+        assert_eq!(pretty(&obj, 16), "(id x\n    '(quote a b)\n    '(c d e f))");
+        let obj = pair!(symbol!("a"), symbol!("b"));
+        assert_eq!(pretty(&obj, WIDTH), "(a . b)");
+        let obj = parser::parse("`(a ,b ,@(c d) (comma))")?;
+        assert_eq!(pretty(&obj, WIDTH), "`(a ,b ,@(c d) (comma))");
+
+        // structures that refer back to themselves
+        let obj = pair!(symbol!("a"), pair!(symbol!("b"), nil!()));
+        obj.extract_pair()?.1.set_cdr(obj.clone())?;
+        assert_eq!(pretty(&obj, WIDTH), "#0=(a b . #0#)");
+        let obj = pair!(symbol!("a"), pair!(symbol!("b"), nil!()));
+        let rest = obj.extract_pair()?.1;
+        rest.set_cdr(rest.clone())?;
+        assert_eq!(pretty(&obj, WIDTH), "(a . #0=(b . #0#))");
+        obj.set_car(obj.clone())?;
+        assert_eq!(pretty(&obj, WIDTH), "#0=(#0# . #1=(b . #1#))");
+        Ok(())
+    }
+}
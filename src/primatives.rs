@@ -1,19 +1,41 @@
 use std::collections::HashMap;
 
-use crate::object::Object;
+use crate::object::{self, Object};
+use crate::places;
+use crate::symbol::Symbol;
 use anyhow::{anyhow, Error, Result};
 use log::debug;
+use std::fmt;
 
 pub type PrimFunc = fn(&Object) -> Result<Object, Error>;
 
-pub fn load_primatives() -> HashMap<String, PrimFunc> {
+pub fn load_primatives() -> HashMap<Symbol, PrimFunc> {
     HashMap::from([
-        ("id".to_string(), id as PrimFunc),
-        ("car".to_string(), car as PrimFunc),
-        ("cdr".to_string(), cdr as PrimFunc),
+        (Symbol::new("id"), id as PrimFunc),
+        (Symbol::new("car"), car as PrimFunc),
+        (Symbol::new("cdr"), cdr as PrimFunc),
+        (Symbol::new("join"), join as PrimFunc),
+        (Symbol::new("xar"), xar as PrimFunc),
+        (Symbol::new("xdr"), xdr as PrimFunc),
+        (Symbol::new("table"), table as PrimFunc),
+        (Symbol::new("tabref"), tabref as PrimFunc),
+        (Symbol::new("err"), err as PrimFunc),
+        (Symbol::new("nom"), nom as PrimFunc),
+        (Symbol::new("type"), r#type as PrimFunc),
     ])
 }
 
+// the arguments a primative was called with, padded with nil
+// up to n, which is the most it accepts
+fn prim_args(name: &str, params: &Object, n: usize) -> Result<Vec<Object>, Error> {
+    let mut p_v = params.to_vec()?;
+    if p_v.len() > n {
+        return Err(anyhow!("{}: too many params: {}", name, params));
+    }
+    p_v.resize(n, nil!());
+    Ok(p_v)
+}
+
 fn id(params: &Object) -> Result<Object, Error> {
     // id is true if
     // * there are two arguments
@@ -33,22 +55,111 @@ fn id(params: &Object) -> Result<Object, Error> {
                 result = t!();
             }
         }
+    } else if p_v[0].is_same_pair(&p_v[1]) {
+        result = t!();
     };
 
     Ok(result)
 }
 
+// car and cdr of nil are nil
 fn car(params: &Object) -> Result<Object, Error> {
     debug!("car: params = {}", params);
-    let (car, _) = params.extract_pair()?;
+    let p_v = prim_args("car", params, 1)?;
+    if p_v[0].is_nil() {
+        return Ok(nil!());
+    }
+    let (car, _) = p_v[0].extract_pair()?;
     Ok(car)
 }
 
 fn cdr(params: &Object) -> Result<Object, Error> {
     debug!("cdr: params = {}", params);
-    let (_, cdr) = params.extract_pair()?;
+    let p_v = prim_args("cdr", params, 1)?;
+    if p_v[0].is_nil() {
+        return Ok(nil!());
+    }
+    let (_, cdr) = p_v[0].extract_pair()?;
     Ok(cdr)
 }
+
+// (join a b) is a new pair (a . b)
+fn join(params: &Object) -> Result<Object, Error> {
+    debug!("join: params = {}", params);
+    let p_v = prim_args("join", params, 2)?;
+    Ok(pair!(p_v[0].clone(), p_v[1].clone()))
+}
+
+// (xar x y) replaces the car of x with y, returning y
+fn xar(params: &Object) -> Result<Object, Error> {
+    debug!("xar: params = {}", params);
+    let p_v = prim_args("xar", params, 2)?;
+    p_v[0].set_car(p_v[1].clone())?;
+    Ok(p_v[1].clone())
+}
+
+// (xdr x y) replaces the cdr of x with y, returning y
+fn xdr(params: &Object) -> Result<Object, Error> {
+    debug!("xdr: params = {}", params);
+    let p_v = prim_args("xdr", params, 2)?;
+    p_v[0].set_cdr(p_v[1].clone())?;
+    Ok(p_v[1].clone())
+}
+
+// a table is (lit tab . kvs) where kvs is a list of (key . value) pairs
+fn table(params: &Object) -> Result<Object, Error> {
+    debug!("table: params = {}", params);
+    let p_v = prim_args("table", params, 1)?;
+    Ok(pair!(symbol!("lit"), pair!(symbol!("tab"), p_v[0].clone())))
+}
+
+// (tabref tab key default)
+fn tabref(params: &Object) -> Result<Object, Error> {
+    debug!("tabref: params = {}", params);
+    let p_v = prim_args("tabref", params, 3)?;
+    match places::table_entry(&p_v[0], &p_v[1])? {
+        Some(kv) => {
+            let (_, value) = kv.extract_pair()?;
+            Ok(value)
+        }
+        None => Ok(p_v[2].clone()),
+    }
+}
+
+/// The error (err x) gives, so a dynamically bound err can be called
+/// with x
+#[derive(Debug)]
+pub struct Signal(pub Object);
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "err: {}", self.0)
+    }
+}
+
+impl std::error::Error for Signal {}
+
+// (err x) signals an error, x saying what it is
+fn err(params: &Object) -> Result<Object, Error> {
+    let p_v = prim_args("err", params, 1)?;
+    Err(Signal(p_v[0].clone()).into())
+}
+
+// (nom x) is the name of the symbol x, as a string
+fn nom(params: &Object) -> Result<Object, Error> {
+    let p_v = prim_args("nom", params, 1)?;
+    match &p_v[0] {
+        Object::Symbol(name) => Ok(object::from_string(name.name())),
+        x => Err(anyhow!("nom: not a symbol: {}", x)),
+    }
+}
+
+// (type x) is the type of x: symbol, pair, char or stream
+fn r#type(params: &Object) -> Result<Object, Error> {
+    let p_v = prim_args("type", params, 1)?;
+    Ok(Object::Symbol(Symbol::new(&p_v[0].t())))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -64,6 +175,58 @@ mod tests {
         let ans = id(&params)?;
         assert!(!ans.is_true());
 
+        // pairs are identical only if they are the same pair
+        let x = parser::parse("(a)")?;
+        let params = object::from_vec(vec![x.clone(), x.clone()])?;
+        assert!(id(&params)?.is_true());
+        let params = object::from_vec(vec![x, parser::parse("(a)")?])?;
+        assert!(!id(&params)?.is_true());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_take_car_and_cdr() -> Result<(), Error> {
+        let params = parser::parse("((a b))")?;
+        assert!(car(&params)?.is_symbol("a"));
+        assert_eq!(cdr(&params)?, parser::parse("(b)")?);
+
+        let params = parser::parse("(nil)")?;
+        assert!(car(&params)?.is_nil());
+        assert!(cdr(&params)?.is_nil());
+
+        let params = parser::parse("(a)")?;
+        assert!(car(&params).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_signal_errors() -> Result<(), Error> {
+        let result = err(&parser::parse("(bad-thing)")?);
+        assert_eq!(result.unwrap_err().to_string(), "err: bad-thing");
+        Ok(())
+    }
+
+    #[test]
+    fn can_name_symbols() -> Result<(), Error> {
+        let params = parser::parse("(ab)")?;
+        assert_eq!(nom(&params)?, object::from_string("ab"));
+        assert!(nom(&parser::parse("((a))")?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn can_modify_pair() -> Result<(), Error> {
+        let x = join(&parser::parse("(a b)")?)?;
+        assert!(x.is_pair(symbol!("a"), symbol!("b")));
+
+        let params = object::from_vec(vec![x.clone(), symbol!("c")])?;
+        assert!(xar(&params)?.is_symbol("c"));
+        let params = object::from_vec(vec![x.clone(), nil!()])?;
+        assert!(xdr(&params)?.is_nil());
+        assert_eq!(x, parser::parse("(c)")?);
+
         Ok(())
     }
 }
@@ -0,0 +1,442 @@
+use crate::eval::{Bel, NameKind, SPECIAL_FORMS};
+use crate::object::Object;
+use crate::parser::{self, Syntax};
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Context, Helper};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+// ANSI colours for the kinds of names, comments and matching brackets
+const SPECIAL_FORM_COLOUR: &str = "\x1b[1;35m";
+const MACRO_COLOUR: &str = "\x1b[36m";
+const FUNCTION_COLOUR: &str = "\x1b[34m";
+const UNBOUND_COLOUR: &str = "\x1b[31m";
+const COMMENT_COLOUR: &str = "\x1b[2m";
+const BRACKET_COLOUR: &str = "\x1b[1;33m";
+const RESET: &str = "\x1b[0m";
+
+/// Reads REPL input: checks forms are finished, completes names and REPL
+/// commands, and colours names by what they are, and the bracket that
+/// matches the one at the cursor.
+///
+/// rustyline 9 has no prompt for continuation lines, so rather than
+/// saying a form is incomplete, which would keep the line being edited,
+/// the validator takes it, and the REPL asks for the rest with a ".."
+/// prompt. A line that can't be read whatever follows, like one with an
+/// extra ')', has to be fixed before it's taken; except when reading
+/// from a pipe, where rustyline would join it to the next line.
+pub struct ReplHelper {
+    /// the lines of an unfinished form
+    pub pending: String,
+    syntax: Syntax,
+    interactive: bool,
+    commands: Vec<String>,
+    // the global names and what they are, as of the last update
+    names: HashMap<String, NameKind>,
+}
+
+impl ReplHelper {
+    pub fn new(bel: &Bel, commands: &[&str], interactive: bool) -> Self {
+        let mut helper = ReplHelper {
+            pending: String::new(),
+            syntax: bel.syntax.clone(),
+            interactive,
+            commands: commands.iter().map(|command| command.to_string()).collect(),
+            names: HashMap::new(),
+        };
+        helper.refresh_names(bel);
+        helper
+    }
+
+    /// Take the names from bel again, after it's evaluated something
+    pub fn refresh_names(&mut self, bel: &Bel) {
+        self.names = bel
+            .globals
+            .keys()
+            .filter_map(|key| match key {
+                Object::Symbol(name) => Some((name.to_string(), bel.name_kind(*name))),
+                _ => None,
+            })
+            .chain(
+                SPECIAL_FORMS
+                    .iter()
+                    .map(|name| (name.to_string(), NameKind::SpecialForm)),
+            )
+            .collect();
+    }
+
+    /// The text typed so far, with line
+    pub fn text(&self, line: &str) -> String {
+        format!("{}{}\n", self.pending, line)
+    }
+
+    fn colour(&self, name: &str) -> Option<&'static str> {
+        match self.names.get(name) {
+            Some(NameKind::SpecialForm) => Some(SPECIAL_FORM_COLOUR),
+            Some(NameKind::Macro) => Some(MACRO_COLOUR),
+            Some(NameKind::Function) => Some(FUNCTION_COLOUR),
+            Some(NameKind::Variable) => None,
+            Some(NameKind::Unbound) | None => Some(UNBOUND_COLOUR),
+        }
+    }
+}
+
+impl Validator for ReplHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        let text = self.text(ctx.input());
+        let reader = parser::Reader::new(&text).with_syntax(&self.syntax);
+        match reader.filter_map(|item| item.err()).next() {
+            Some(err) if self.interactive && !err.incomplete => {
+                Ok(ValidationResult::Invalid(Some(format!("\n{}", err))))
+            }
+            _ => Ok(ValidationResult::Valid(None)),
+        }
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    // REPL commands at the start of a line, otherwise the names that
+    // start with the part of a symbol before the cursor
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let before = &line[..pos];
+        if self.pending.is_empty() && before.starts_with(':') && !before.contains(' ') {
+            let mut commands = self
+                .commands
+                .iter()
+                .filter(|command| command.starts_with(before))
+                .cloned()
+                .collect::<Vec<String>>();
+            commands.sort();
+            return Ok((0, commands));
+        }
+
+        let start = before
+            .char_indices()
+            .rev()
+            .find(|(_, c)| ends_name(*c))
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        let prefix = &before[start..];
+        let mut names: Vec<String> = self
+            .names
+            .keys()
+            .filter(|name| name.starts_with(prefix))
+            .cloned()
+            .collect();
+        names.sort();
+        Ok((start, names))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        // REPL commands aren't Bel
+        if self.pending.is_empty() && line.starts_with(':') {
+            return Cow::Borrowed(line);
+        }
+        let tokens = tokens(line);
+        let brackets = matching_brackets(line, &tokens, pos);
+        let mut out = String::with_capacity(line.len() * 2);
+        for token in &tokens {
+            let text = &line[token.start..token.end];
+            let colour = match token.kind {
+                TokenKind::Name => self.colour(text),
+                TokenKind::Comment => Some(COMMENT_COLOUR),
+                TokenKind::Bracket if brackets.contains(&token.start) => Some(BRACKET_COLOUR),
+                _ => None,
+            };
+            match colour {
+                Some(colour) => out.push_str(&format!("{}{}{}", colour, text, RESET)),
+                None => out.push_str(text),
+            }
+        }
+        Cow::Owned(out)
+    }
+
+    // the colours can change whenever the line or the cursor does
+    fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
+        true
+    }
+}
+
+impl Helper for ReplHelper {}
+
+// the chars that end the part of a symbol being completed
+fn ends_name(c: char) -> bool {
+    parser::is_delimiter(c) || "'`,@|:.!~".contains(c)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TokenKind {
+    Name,
+    Comment,
+    Bracket,
+    // chars, quoted symbols, quotes and whitespace, left as they are
+    Other,
+}
+
+// A piece of a line, from start to end. Every char of the line is in one.
+#[derive(Debug, Clone, Copy)]
+struct Token {
+    start: usize,
+    end: usize,
+    kind: TokenKind,
+}
+
+// Split a line into tokens, closely enough to colour it. It has to cope
+// with lines that can't be read (yet), so it doesn't use the reader
+fn tokens(line: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let rest = &line[start..];
+        let (end, kind) = if c == ';' {
+            (
+                rest.find('\n').map_or(line.len(), |i| start + i),
+                TokenKind::Comment,
+            )
+        } else if rest.starts_with("#|") {
+            (
+                rest.find("|#").map_or(line.len(), |i| start + i + 2),
+                TokenKind::Comment,
+            )
+        } else if rest.starts_with("#;") {
+            (start + 2, TokenKind::Comment)
+        } else if "()[]".contains(c) {
+            (start + 1, TokenKind::Bracket)
+        } else if c == '\\' {
+            // the char after the backslash is part of its name, whatever
+            // it is
+            let name = rest.chars().nth(1).map_or(0, char::len_utf8);
+            let end = rest[1 + name..]
+                .find(parser::is_boundaray_char)
+                .map_or(line.len(), |i| start + 1 + name + i);
+            (end, TokenKind::Other)
+        } else if c == '|'
+            && !tokens
+                .last()
+                .is_some_and(|token: &Token| token.kind == TokenKind::Name && token.end == start)
+        {
+            // a|b is a typed name, not a quoted symbol
+            (quoted_symbol_end(line, start), TokenKind::Other)
+        } else if ends_name(c) {
+            (start + c.len_utf8(), TokenKind::Other)
+        } else {
+            let end = rest.find(ends_name).map_or(line.len(), |i| start + i);
+            (end, TokenKind::Name)
+        };
+        tokens.push(Token { start, end, kind });
+        while chars.peek().is_some_and(|(i, _)| *i < end) {
+            chars.next();
+        }
+    }
+    mark_quoted(line, &mut tokens);
+    tokens
+}
+
+// names in quoted data aren't looked up, so they aren't coloured either
+fn mark_quoted(line: &str, tokens: &mut [Token]) {
+    let mut depth = 0;
+    // just after a quote
+    let mut quoting = false;
+    // the depth the quoted list being read started at
+    let mut quoted: Option<usize> = None;
+    for token in tokens {
+        let text = &line[token.start..token.end];
+        match token.kind {
+            TokenKind::Bracket if text == "(" || text == "[" => {
+                if quoting && quoted.is_none() {
+                    quoted = Some(depth);
+                }
+                quoting = false;
+                depth += 1;
+            }
+            TokenKind::Bracket => {
+                depth = depth.saturating_sub(1);
+                if quoted == Some(depth) {
+                    quoted = None;
+                }
+            }
+            TokenKind::Name => {
+                if quoting || quoted.is_some() {
+                    token.kind = TokenKind::Other;
+                }
+                // the rest of 'a:b is quoted too
+                quoting = quoting && line[token.end..].starts_with(is_intrafix_char);
+            }
+            TokenKind::Other if text == "'" || text == "`" => quoting = true,
+            TokenKind::Other if text.trim().is_empty() || text.chars().all(is_intrafix_char) => {}
+            _ => quoting = false,
+        }
+    }
+}
+
+// the chars between the parts of a:b, a.b, a!b and a|b
+fn is_intrafix_char(c: char) -> bool {
+    ":.!|".contains(c)
+}
+
+// the end of the |quoted symbol| starting at start, or of the line
+fn quoted_symbol_end(line: &str, start: usize) -> usize {
+    let mut escaped = false;
+    for (i, c) in line[start + 1..].char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '|' => return start + 1 + i + 1,
+            _ => {}
+        }
+    }
+    line.len()
+}
+
+// The offsets of the bracket just before the cursor, or failing that the
+// one at it, and of the bracket that matches it, if it's the right kind
+fn matching_brackets(line: &str, tokens: &[Token], pos: usize) -> Vec<usize> {
+    let brackets: Vec<(usize, char)> = tokens
+        .iter()
+        .filter(|token| token.kind == TokenKind::Bracket)
+        .map(|token| {
+            (
+                token.start,
+                line[token.start..].chars().next().unwrap_or(' '),
+            )
+        })
+        .collect();
+    let at = match brackets
+        .iter()
+        .position(|(offset, _)| offset + 1 == pos)
+        .or_else(|| brackets.iter().position(|(offset, _)| *offset == pos))
+    {
+        Some(at) => at,
+        None => return Vec::new(),
+    };
+
+    let (offset, c) = brackets[at];
+    let opens = |c: char| c == '(' || c == '[';
+    // look forwards from an open bracket, or back from a close one
+    let others: Vec<&(usize, char)> = if opens(c) {
+        brackets[at..].iter().collect()
+    } else {
+        brackets[..=at].iter().rev().collect()
+    };
+    let mut depth = 0;
+    for &(other, b) in others {
+        depth += if opens(b) == opens(c) { 1 } else { -1 };
+        if depth == 0 {
+            let pair = if opens(c) { (c, b) } else { (b, c) };
+            return match pair {
+                ('(', ')') | ('[', ']') => vec![offset, other],
+                _ => Vec::new(),
+            };
+        }
+    }
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Error;
+    use rustyline::history::History;
+
+    fn helper() -> Result<ReplHelper, Error> {
+        let mut bel = Bel::new();
+        let exp = bel.parse("(mac when (test body) (list 'if test body))")?;
+        bel.eval(&crate::eval::new_object_map(), &exp)?;
+        Ok(ReplHelper::new(&bel, &[":get", ":gc", ":load"], true))
+    }
+
+    fn complete(helper: &ReplHelper, line: &str) -> Result<(usize, Vec<String>), Error> {
+        let history = History::new();
+        let ctx = Context::new(&history);
+        Ok(helper.complete(line, line.len(), &ctx)?)
+    }
+
+    #[test]
+    fn can_complete_names() -> Result<(), Error> {
+        let helper = helper()?;
+        assert_eq!(
+            complete(&helper, ":g")?,
+            (0, vec![":gc".to_string(), ":get".to_string()])
+        );
+        let (start, names) = complete(&helper, "(wh")?;
+        assert_eq!(
+            (start, names),
+            (1, vec!["when".to_string(), "where".to_string()])
+        );
+        let (start, names) = complete(&helper, ":get ca")?;
+        assert_eq!(start, 5);
+        assert!(names.contains(&"car".to_string()));
+        let (start, names) = complete(&helper, "(map car:cd")?;
+        assert_eq!((start, names), (9, vec!["cdr".to_string()]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_colour_names() -> Result<(), Error> {
+        let helper = helper()?;
+        let line = "(when (car x) 'a) ; car";
+        assert_eq!(
+            helper.highlight(line, 3),
+            format!(
+                "({}when{} ({}car{} {}x{}) 'a) {}; car{}",
+                MACRO_COLOUR,
+                RESET,
+                FUNCTION_COLOUR,
+                RESET,
+                UNBOUND_COLOUR,
+                RESET,
+                COMMENT_COLOUR,
+                RESET
+            )
+        );
+        // chars and quoted symbols aren't names
+        assert_eq!(helper.highlight("\\a |car| t", 0), "\\a |car| t");
+        assert_eq!(helper.highlight(":get car", 0), ":get car");
+        // nor is quoted data
+        assert_eq!(helper.highlight("'(car (x)) 'a:b", 0), "'(car (x)) 'a:b");
+        assert_eq!(
+            helper.highlight("x|car", 0),
+            format!(
+                "{}x{}|{}car{}",
+                UNBOUND_COLOUR, RESET, FUNCTION_COLOUR, RESET
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_match_brackets() {
+        let line = "(a [b] (c)";
+        let found = tokens(line);
+        // just after the ']', and at the '['
+        assert_eq!(matching_brackets(line, &found, 6), vec![5, 3]);
+        assert_eq!(matching_brackets(line, &found, 3), vec![3, 5]);
+        assert_eq!(matching_brackets(line, &found, 10), vec![9, 7]);
+        // the first '(' isn't closed
+        assert_eq!(matching_brackets(line, &found, 0), Vec::<usize>::new());
+        assert_eq!(
+            matching_brackets("(a]", &tokens("(a]"), 3),
+            Vec::<usize>::new()
+        );
+        // brackets in comments and chars don't count
+        let line = "(\\( ; )\n)";
+        assert_eq!(matching_brackets(line, &tokens(line), 0), vec![0, 8]);
+    }
+}
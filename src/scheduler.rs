@@ -0,0 +1,374 @@
+use crate::bytecode::Chunk;
+use crate::env::Env;
+use crate::heap::Tracer;
+use crate::object::Object;
+use crate::places::{Modify, Place, PlaceExp};
+use crate::symbol::Symbol;
+use anyhow::{anyhow, Error};
+use std::rc::Rc;
+
+/// the seed used by Bel::new()
+pub const DEFAULT_SEED: u64 = 0x5eed;
+
+/// a thread runs for at most this many steps before the scheduler
+/// may switch to another thread
+pub const MAX_QUANTUM: u64 = 16;
+
+/// Something a thread has left to do.
+/// Tasks are pushed onto the thread's task stack and popped one per step.
+/// Tasks that need the values of subexpressions find them on the thread's
+/// value stack.
+#[derive(Debug, Clone)]
+pub enum Task {
+    /// evaluate exp and push the result onto the value stack
+    Eval { exp: Object, locals: Env },
+    /// pop the value of an if test and evaluate the appropriate branch
+    /// rest is the if expression following the test
+    If { rest: Object, locals: Env },
+    /// pop n evaluated arguments and perform the call
+    Collect { n: usize, call: Call, locals: Env },
+    /// apply the function f to args, both already evaluated
+    Apply {
+        f: Object,
+        args: Object,
+        locals: Env,
+    },
+    /// pop an expression (a macro expansion) and evaluate it
+    EvalResult { locals: Env },
+    /// pop the expansion of the macro call, cache it, and evaluate it
+    Expanded {
+        call: Object,
+        mac: Object,
+        locals: Env,
+    },
+    /// pop a value and throw it away
+    Discard,
+    /// leave an atomic expression
+    EndAtomic,
+    /// run compiled code, starting at pc
+    Exec {
+        chunk: Rc<Chunk>,
+        pc: usize,
+        locals: Env,
+    },
+    /// pop a value, and bind name to it dynamically while exp is evaluated
+    Bind {
+        name: Symbol,
+        exp: Object,
+        locals: Env,
+    },
+    /// drop the innermost dynamic binding
+    Unbind,
+}
+
+impl Task {
+    // mark the objects the task holds for the garbage collector
+    pub fn trace(&self, tracer: &mut Tracer) {
+        match self {
+            Task::Eval { exp, locals } => {
+                tracer.mark(exp);
+                locals.trace(tracer);
+            }
+            Task::If { rest, locals } => {
+                tracer.mark(rest);
+                locals.trace(tracer);
+            }
+            Task::Collect { call, locals, .. } => {
+                call.trace(tracer);
+                locals.trace(tracer);
+            }
+            Task::Apply { f, args, locals } => {
+                tracer.mark(f);
+                tracer.mark(args);
+                locals.trace(tracer);
+            }
+            Task::EvalResult { locals } => locals.trace(tracer),
+            Task::Expanded { call, mac, locals } => {
+                tracer.mark(call);
+                tracer.mark(mac);
+                locals.trace(tracer);
+            }
+            Task::Discard | Task::EndAtomic | Task::Unbind => {}
+            Task::Exec { chunk, locals, .. } => {
+                chunk.trace(tracer);
+                locals.trace(tracer);
+            }
+            Task::Bind { exp, locals, .. } => {
+                tracer.mark(exp);
+                locals.trace(tracer);
+            }
+        }
+    }
+}
+
+/// what to do with a list of evaluated arguments
+#[derive(Debug, Clone)]
+pub enum Call {
+    Apply,
+    Primative(Symbol),
+    Function(Object),
+    /// the first argument is the (evaluated) head of the expression
+    Dynamic,
+    /// the arguments are n_pre evaluated arguments, the values needed to
+    /// resolve places, then any remaining arguments
+    Modify {
+        op: Modify,
+        n_pre: usize,
+        places: Vec<PlaceExp>,
+    },
+    /// store the single argument in place
+    Store(Place),
+    /// the arguments are the results of calling a function on each of
+    /// items; keep the items where the result is true (or false)
+    Keep {
+        items: Vec<Object>,
+        keep_if: bool,
+    },
+    /// the arguments are the results of comparing x with each element of
+    /// list; join x onto list if none matched
+    Adjoin {
+        x: Object,
+        list: Object,
+    },
+    /// the arguments are the values of the template's commas, to be
+    /// filled in, see bquote.rs
+    Bquote(Object),
+    /// the single argument is a function to call with the continuation
+    Ccc,
+}
+
+impl Call {
+    pub fn trace(&self, tracer: &mut Tracer) {
+        match self {
+            Call::Function(f) | Call::Bquote(f) => tracer.mark(f),
+            Call::Store(Place::Car(cell)) | Call::Store(Place::Cdr(cell)) => tracer.mark(cell),
+            Call::Keep { items, .. } => items.iter().for_each(|item| tracer.mark(item)),
+            Call::Adjoin { x, list } => {
+                tracer.mark(x);
+                tracer.mark(list);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A green thread: an evaluation stack and a value stack.
+#[derive(Debug)]
+pub struct Thread {
+    pub id: usize,
+    pub tasks: Vec<Task>,
+    pub values: Vec<Object>,
+    /// nesting depth of atomic expressions; the scheduler will not
+    /// switch away from a thread while this is non-zero
+    pub atomic: usize,
+    /// the variables bound by dyn, innermost last
+    pub dynamic: Vec<(Symbol, Object)>,
+}
+
+impl Thread {
+    pub fn new(id: usize, exp: Object, locals: Env) -> Self {
+        Thread {
+            id,
+            tasks: vec![Task::Eval { exp, locals }],
+            values: Vec::new(),
+            atomic: 0,
+            dynamic: Vec::new(),
+        }
+    }
+
+    pub fn trace(&self, tracer: &mut Tracer) {
+        for task in &self.tasks {
+            task.trace(tracer);
+        }
+        for value in &self.values {
+            tracer.mark(value);
+        }
+        for (_, value) in &self.dynamic {
+            tracer.mark(value);
+        }
+    }
+
+    // the value name is dynamically bound to, if it is
+    pub fn dynamic_value(&self, name: Symbol) -> Option<Object> {
+        self.dynamic
+            .iter()
+            .rev()
+            .find(|(bound, _)| *bound == name)
+            .map(|(_, value)| value.clone())
+    }
+
+    // what's left for the thread to do, as it is now
+    pub fn continuation(&self) -> Continuation {
+        Continuation {
+            tasks: self.tasks.clone(),
+            values: self.values.clone(),
+            atomic: self.atomic,
+            dynamic: self.dynamic.clone(),
+        }
+    }
+
+    // carry on from k, as if the expression that made it had given value
+    pub fn resume(&mut self, k: &Continuation, value: Object) {
+        self.tasks = k.tasks.clone();
+        self.values = k.values.clone();
+        self.atomic = k.atomic;
+        self.dynamic = k.dynamic.clone();
+        self.push_value(value);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    pub fn push_task(&mut self, task: Task) {
+        self.tasks.push(task);
+    }
+
+    pub fn push_value(&mut self, obj: Object) {
+        self.values.push(obj);
+    }
+
+    pub fn pop_value(&mut self) -> Result<Object, Error> {
+        self.values
+            .pop()
+            .ok_or_else(|| anyhow!("thread {}: value stack is empty", self.id))
+    }
+
+    // the last n values, in the order they were pushed
+    pub fn pop_values(&mut self, n: usize) -> Result<Vec<Object>, Error> {
+        if self.values.len() < n {
+            return Err(anyhow!(
+                "thread {}: expected {} values; found {}",
+                self.id,
+                n,
+                self.values.len()
+            ));
+        }
+        Ok(self.values.split_off(self.values.len() - n))
+    }
+}
+
+/// The rest of a thread's evaluation, as it was when ccc was called.
+/// Calling it, even after that evaluation has finished, carries on from
+/// there again.
+#[derive(Debug)]
+pub struct Continuation {
+    tasks: Vec<Task>,
+    values: Vec<Object>,
+    atomic: usize,
+    dynamic: Vec<(Symbol, Object)>,
+}
+
+impl Continuation {
+    pub fn trace(&self, tracer: &mut Tracer) {
+        for task in &self.tasks {
+            task.trace(tracer);
+        }
+        for value in &self.values {
+            tracer.mark(value);
+        }
+        for (_, value) in &self.dynamic {
+            tracer.mark(value);
+        }
+    }
+}
+
+/// Cooperative scheduler for green threads.
+/// Which thread runs next, and for how many steps, is chosen by a
+/// pseudo random generator, so a given seed always gives the same
+/// interleaving.
+#[derive(Debug)]
+pub struct Scheduler {
+    pub threads: Vec<Thread>,
+    next_id: usize,
+    rng: Rng,
+}
+
+impl Scheduler {
+    pub fn new(seed: u64) -> Self {
+        Scheduler {
+            threads: Vec::new(),
+            next_id: 0,
+            rng: Rng::new(seed),
+        }
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    // create a new thread to evaluate exp, returning its id
+    pub fn spawn(&mut self, exp: Object, locals: Env) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.threads.push(Thread::new(id, exp, locals));
+        id
+    }
+
+    // remove the next thread to run from the run queue
+    pub fn pick(&mut self) -> Option<Thread> {
+        if self.threads.is_empty() {
+            None
+        } else {
+            let index = (self.rng.next() % self.threads.len() as u64) as usize;
+            Some(self.threads.swap_remove(index))
+        }
+    }
+
+    // number of steps the next thread may run before being preempted
+    pub fn quantum(&mut self) -> u64 {
+        1 + self.rng.next() % MAX_QUANTUM
+    }
+}
+
+/// xorshift64*
+/// we don't need good randomness, just a repeatable sequence
+#[derive(Debug)]
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // xorshift gets stuck at zero
+        let state = if seed == 0 { DEFAULT_SEED } else { seed };
+        Rng { state }
+    }
+
+    fn next(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::{new_env, new_object_map};
+
+    #[test]
+    fn rng_is_repeatable() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        for _ in 0..100 {
+            assert_eq!(a.next(), b.next());
+        }
+    }
+
+    #[test]
+    fn can_pop_values_in_order() -> Result<(), Error> {
+        let mut thread = Thread::new(0, nil!(), new_env(new_object_map()));
+        thread.push_value(symbol!("a"));
+        thread.push_value(symbol!("b"));
+        thread.push_value(symbol!("c"));
+        assert_eq!(thread.pop_values(2)?, vec![symbol!("b"), symbol!("c")]);
+        assert_eq!(thread.pop_value()?, symbol!("a"));
+        assert!(thread.pop_value().is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use crate::parser;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+
+/// An interned symbol: a small copyable id for a name.
+/// Symbols with the same name have the same id, so comparing them is an
+/// integer compare. The names are kept in a table shared by every Bel on
+/// the (OS) thread, and are never freed.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+// symbols the interpreter uses a lot, interned up front so
+// they can be constants
+const PREDEFINED: [&str; 8] = ["nil", "t", "o", "quote", "lit", "clo", "mac", "prim"];
+
+impl Symbol {
+    pub const NIL: Symbol = Symbol(0);
+    pub const T: Symbol = Symbol(1);
+    pub const O: Symbol = Symbol(2);
+    pub const QUOTE: Symbol = Symbol(3);
+    pub const LIT: Symbol = Symbol(4);
+    pub const CLO: Symbol = Symbol(5);
+    pub const MAC: Symbol = Symbol(6);
+    pub const PRIM: Symbol = Symbol(7);
+
+    // the symbol for name, adding it to the table if it's new
+    pub fn new(name: &str) -> Self {
+        SYMBOLS.with(|table| table.borrow_mut().intern(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        SYMBOLS.with(|table| table.borrow().names[self.0 as usize])
+    }
+}
+
+// printed so that it reads back as the same symbol
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = self.name();
+        if parser::reads_as_symbol(name) {
+            return write!(f, "{}", name);
+        }
+        write!(f, "|")?;
+        for c in name.chars() {
+            if c == '|' || c == '\\' {
+                write!(f, "\\")?;
+            }
+            write!(f, "{}", c)?;
+        }
+        write!(f, "|")
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.name())
+    }
+}
+
+struct SymbolTable {
+    names: Vec<&'static str>,
+    ids: HashMap<&'static str, Symbol>,
+}
+
+impl SymbolTable {
+    fn new() -> Self {
+        let mut table = SymbolTable {
+            names: Vec::new(),
+            ids: HashMap::new(),
+        };
+        for name in PREDEFINED {
+            table.intern(name);
+        }
+        table
+    }
+
+    fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(symbol) = self.ids.get(name) {
+            return *symbol;
+        }
+        // leaked so that names can be handed out without holding a borrow
+        // of the table; there is one copy of each name
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        let symbol = Symbol(self.names.len() as u32);
+        self.names.push(name);
+        self.ids.insert(name, symbol);
+        symbol
+    }
+}
+
+thread_local! {
+    static SYMBOLS: RefCell<SymbolTable> = RefCell::new(SymbolTable::new());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_intern_symbols() {
+        assert_eq!(Symbol::new("nil"), Symbol::NIL);
+        assert_eq!(Symbol::new("prim"), Symbol::PRIM);
+        assert_eq!(Symbol::new("foo"), Symbol::new("foo"));
+        assert_ne!(Symbol::new("foo"), Symbol::new("bar"));
+        assert_eq!(Symbol::new("foo").name(), "foo");
+        assert_eq!(Symbol::T.to_string(), "t");
+        assert_eq!(format!("{:?}", Symbol::new("a b")), "\"a b\"");
+    }
+}
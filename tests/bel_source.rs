@@ -4,7 +4,13 @@ mod tests {
 
     use smolbel::eval;
     use smolbel::loader;
-    use smolbel::parser;
+    use smolbel::object::{self, Object};
+    use smolbel::parser::{self, Reader};
+    use smolbel::symbol::Symbol;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::hint::black_box;
+    use std::time::{Duration, Instant};
 
     const SOURCE_PATH: &str = "bel_source/bel.bel";
     const LIMIT: usize = 3;
@@ -43,4 +49,192 @@ mod tests {
 
         Ok(())
     }
+
+    // the top level forms of bel.bel, for the benchmarks
+    fn bel_forms() -> Result<Vec<Object>, Error> {
+        let text = fs::read_to_string(SOURCE_PATH)?;
+        let mut forms = Vec::new();
+        for item in Reader::new(&text).named(SOURCE_PATH) {
+            let (_, exp) = item?;
+            forms.push(exp);
+        }
+        Ok(forms)
+    }
+
+    // load the forms into a new Bel, carrying on past the ones we can't
+    // handle yet, returning how many loaded
+    fn load_forms(forms: &[Object]) -> usize {
+        let mut bel = eval::Bel::new();
+        forms
+            .iter()
+            .filter(|exp| bel.eval(&eval::new_object_map(), exp).is_ok())
+            .count()
+    }
+
+    // The pairs made loading bel.bel, with either engine, when this was
+    // last looked at. Unlike the time it takes, this is the same on every
+    // machine, so it's checked by every test run. If a change brings it
+    // down, bring this down with it. It went up from 11,045 when set began
+    // evaluating its values, and the forms and macros in bel.bel that had
+    // failed to load were run. Reading the forms isn't counted.
+    const BASELINE_PAIRS: u64 = 44_988;
+
+    #[test]
+    fn load_allocates_no_more_than_baseline() -> Result<(), Error> {
+        let forms = bel_forms()?;
+        let before = object::pairs_allocated();
+        load_forms(&forms);
+        let pairs = object::pairs_allocated() - before;
+        println!("load: {} pairs made; baseline {}", pairs, BASELINE_PAIRS);
+        // a little slack, for changes in what bel.bel loads
+        assert!(
+            pairs <= BASELINE_PAIRS + BASELINE_PAIRS / 20,
+            "loading bel.bel made {} pairs, more than the baseline of {}",
+            pairs,
+            BASELINE_PAIRS
+        );
+
+        Ok(())
+    }
+
+    // every symbol in obj, as many times as it appears
+    fn symbols_in(obj: &Object, symbols: &mut Vec<Symbol>) {
+        let mut rest = obj.clone();
+        loop {
+            match rest {
+                Object::Symbol(symbol) => {
+                    symbols.push(symbol);
+                    return;
+                }
+                Object::Pair(_) => {
+                    let (car, cdr) = match rest.extract_pair() {
+                        Ok(pair) => pair,
+                        Err(_) => return,
+                    };
+                    symbols_in(&car, symbols);
+                    rest = cdr;
+                }
+                _ => return,
+            }
+        }
+    }
+
+    // the fastest of a few rounds of f
+    fn fastest_of(rounds: u32, mut f: impl FnMut()) -> Duration {
+        let mut fastest = Duration::MAX;
+        for _ in 0..rounds {
+            let start = Instant::now();
+            f();
+            fastest = fastest.min(start.elapsed());
+        }
+        fastest
+    }
+
+    // Micro-benchmark: look up every symbol in bel.bel in a table of
+    // globals, as the evaluator does, with symbols as they were before
+    // they were interned, names in Strings made afresh for each use (as
+    // symbol!("nil") did), and as they are now. Both are timed in the same
+    // run, so what's compared doesn't depend on the machine. Unoptimized,
+    // hashing takes most of the time either way, so it's only run with
+    //  cargo test --release --test bel_source
+    #[test]
+    #[cfg_attr(debug_assertions, ignore = "run with --release")]
+    fn interned_symbols_look_up_faster() -> Result<(), Error> {
+        let mut symbols = Vec::new();
+        for form in bel_forms()? {
+            symbols_in(&form, &mut symbols);
+        }
+        let names: Vec<&str> = symbols.iter().map(|symbol| symbol.name()).collect();
+        assert!(symbols.len() > 10_000);
+
+        let by_name: HashMap<String, usize> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.to_string(), i))
+            .collect();
+        let by_symbol: HashMap<Symbol, usize> = symbols
+            .iter()
+            .enumerate()
+            .map(|(i, symbol)| (*symbol, i))
+            .collect();
+
+        let before = fastest_of(50, || {
+            for name in &names {
+                let key = name.to_string();
+                black_box(by_name.get(&key));
+                black_box(key == "nil");
+            }
+        });
+        let after = fastest_of(50, || {
+            for symbol in &symbols {
+                black_box(by_symbol.get(symbol));
+                black_box(*symbol == Symbol::NIL);
+            }
+        });
+        println!(
+            "{} lookups: {:?} by name, {:?} interned",
+            symbols.len(),
+            before,
+            after
+        );
+        assert!(
+            after * 2 < before,
+            "interned lookups took {:?}, lookups by name {:?}",
+            after,
+            before
+        );
+
+        Ok(())
+    }
+
+    // The fastest round of bench_load when this was last looked at, on a
+    // modest x86-64 machine, in release and debug builds. They went up
+    // from 3ms and 20ms once all of bel.bel loaded.
+    const BASELINE_RELEASE: Duration = Duration::from_millis(28);
+    const BASELINE_DEBUG: Duration = Duration::from_millis(215);
+
+    // Micro-benchmark: load every definition in bel.bel, carrying on past
+    // the ones we can't handle yet, and check it's not much slower than
+    // the baseline. Times depend on the machine, so it's not run by
+    // default; interned_symbols_look_up_faster is what checks interning
+    // paid off. Run with
+    //  cargo test --release --test bel_source -- --ignored --nocapture
+    #[test]
+    #[ignore = "timing depends on the machine; run explicitly with --release"]
+    fn bench_load() -> Result<(), Error> {
+        const ROUNDS: u32 = 200;
+
+        let forms = bel_forms()?;
+
+        // the fastest round is the least disturbed by whatever else
+        // the machine is doing
+        let mut fastest = Duration::MAX;
+        let mut loaded = 0;
+        for _ in 0..ROUNDS {
+            let start = Instant::now();
+            loaded = load_forms(&forms);
+            fastest = fastest.min(start.elapsed());
+        }
+        let baseline = if cfg!(debug_assertions) {
+            BASELINE_DEBUG
+        } else {
+            BASELINE_RELEASE
+        };
+        println!(
+            "bench_load: {} of {} forms loaded; fastest of {} rounds {:?}; baseline {:?}",
+            loaded,
+            forms.len(),
+            ROUNDS,
+            fastest,
+            baseline
+        );
+        assert!(
+            fastest <= 2 * baseline,
+            "bench_load: {:?} is more than twice the baseline of {:?}",
+            fastest,
+            baseline
+        );
+
+        Ok(())
+    }
 }
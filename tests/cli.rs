@@ -0,0 +1,168 @@
+// Runs the smolbel binary the way a shell would, checking what it prints
+// and how it exits.
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use std::process::{Command, Output, Stdio};
+
+    fn smolbel(args: &[&str]) -> Result<Output, Error> {
+        let output = Command::new(env!("CARGO_BIN_EXE_smolbel"))
+            .args(args)
+            .env("RUST_BACKTRACE", "0")
+            .output()?;
+        Ok(output)
+    }
+
+    // smolbel with input piped to its stdin
+    fn smolbel_with_input(args: &[&str], input: &str) -> Result<Output, Error> {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_smolbel"))
+            .args(args)
+            .env("RUST_BACKTRACE", "0")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        child.stdin.take().unwrap().write_all(input.as_bytes())?;
+        Ok(child.wait_with_output()?)
+    }
+
+    fn stdout(output: &Output) -> String {
+        String::from_utf8_lossy(&output.stdout).to_string()
+    }
+
+    #[test]
+    fn can_evaluate_expressions() -> Result<(), Error> {
+        let output = smolbel(&["-e", "(car '(a b))", "-e", "(cdr argv)", "x", "y z"])?;
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(stdout(&output), "a\n((\\y \\sp \\z))\n");
+
+        // a list that goes round and round is printed with labels
+        let output = smolbel(&[
+            "-e",
+            "(set xs nil)",
+            "-e",
+            "(push 'a xs)",
+            "-e",
+            "(xdr xs xs)",
+        ])?;
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(stdout(&output), "nil\n(a)\n#0=(a . #0#)\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_run_scripts() -> Result<(), Error> {
+        let dir = env::temp_dir().join(format!("smolbel-cli-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let prelude = dir.join("prelude.bel");
+        let script = dir.join("script.bel");
+        let failing = dir.join("failing.bel");
+        fs::write(&prelude, "(def second (xs) (car (cdr xs)))\n")?;
+        fs::write(&script, "(set greeting 'hello)\n")?;
+        fs::write(&failing, "(set a 'b)\n(car 'a)\n(set c 'd)\n")?;
+        let prelude = prelude.to_string_lossy();
+        let script = script.to_string_lossy();
+        let failing = failing.to_string_lossy();
+
+        let output = smolbel(&["--load", &prelude, "-e", "(second '(a b))"]);
+        let with_script = smolbel(&["--load", &prelude, &script, "arg"]);
+        let failed = smolbel(&[&failing]);
+        let missing = smolbel(&["--load", &prelude, "--load", "no-such.bel", "-e", "t"]);
+        fs::remove_dir_all(&dir)?;
+
+        let output = output?;
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(stdout(&output), "b\n");
+        let with_script = with_script?;
+        assert_eq!(with_script.status.code(), Some(0));
+        assert_eq!(stdout(&with_script), "");
+        let failed = failed?;
+        assert_eq!(failed.status.code(), Some(1));
+        assert!(!failed.stderr.is_empty());
+        let missing = missing?;
+        assert_eq!(missing.status.code(), Some(1));
+        assert_eq!(stdout(&missing), "");
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_run_scripts_from_stdin() -> Result<(), Error> {
+        let output = smolbel_with_input(&["-", "x"], "(set a 'b)\n(thread (set c 'd))\n")?;
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(stdout(&output), "");
+
+        let output = smolbel_with_input(&["-"], "(set a 'b)\n(car 'a)\n")?;
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("stdin:2:1"), "{}", stderr);
+
+        Ok(())
+    }
+
+    // the REPL, reading from a pipe, run in a directory of its own as it
+    // saves its history. Gives what it printed to stdout and stderr
+    fn repl(input: &str) -> Result<(String, String), Error> {
+        let dir = env::temp_dir().join(format!("smolbel-repl-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let child = Command::new(env!("CARGO_BIN_EXE_smolbel"))
+            .current_dir(&dir)
+            .env("RUST_BACKTRACE", "0")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn();
+        let output = child.and_then(|mut child| {
+            child.stdin.take().unwrap().write_all(input.as_bytes())?;
+            child.wait_with_output()
+        });
+        fs::remove_dir_all(&dir)?;
+        let output = output?;
+        Ok((
+            String::from_utf8_lossy(&output.stdout).to_string(),
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        ))
+    }
+
+    #[test]
+    fn repl_reads_forms_over_lines() -> Result<(), Error> {
+        // each value is printed on a line of its own
+        let (stdout, _) = repl("(def f (x)\n  ; the first\n  (car x))\n(f '(a b)) (f '(c))\n")?;
+        assert_eq!(
+            stdout, "No previous history.\nnil\na\nc\nCTRL-D\n",
+            "{}",
+            stdout
+        );
+
+        // nothing on a line with an error is evaluated
+        let (stdout, stderr) = repl("(set a 'b) (car 'x))\n(f\n")?;
+        assert_eq!(stdout, "No previous history.\nCTRL-D\n", "{}", stdout);
+        assert!(stderr.contains("1:20: unexpected ')'"), "{}", stderr);
+        assert!(stderr.contains("1:1: unclosed '('"), "{}", stderr);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reports_errors_in_exit_code() -> Result<(), Error> {
+        assert_eq!(smolbel(&["-e", "(car 'a)"])?.status.code(), Some(1));
+        assert_eq!(smolbel(&["-e", "(car"])?.status.code(), Some(1));
+        assert_eq!(smolbel(&["-e", "(undefined)"])?.status.code(), Some(1));
+        assert_eq!(smolbel(&["--bad"])?.status.code(), Some(2));
+        assert_eq!(smolbel(&["-e"])?.status.code(), Some(2));
+        assert_eq!(smolbel(&["--help"])?.status.code(), Some(0));
+
+        // as is an error that ends a thread, with what caused it
+        let output = smolbel(&["-e", "(thread (car 'a))"])?;
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.starts_with("error: thread 1: "), "{}", stderr);
+
+        Ok(())
+    }
+}
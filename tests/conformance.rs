@@ -0,0 +1,36 @@
+// Tries every top level form in bel.bel, and prints which load.
+// Run with
+//  cargo test --test conformance -- --nocapture
+// to see the table.
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+
+    use smolbel::conformance;
+
+    // raise this as more of bel.bel loads, so it doesn't slip back.
+    // It all loads now
+    const MIN_LOADED: usize = 353;
+
+    // what bel.bel defines that's built in here, so isn't used. If this
+    // changes, say so in the README
+    const SHADOWED: &[&str] = &[
+        "set", "def", "mac", "atomic", "do", "fn", "syn", "loc", "bquote", "zap", "push", "pull",
+        "pop", "swap", "wipe", "clean", "pushnew",
+    ];
+
+    #[test]
+    fn bel_source_conformance() -> Result<(), Error> {
+        let report = conformance::check_source(conformance::SOURCE_PATH)?;
+        println!("{}", report);
+        assert!(
+            report.loaded() >= MIN_LOADED,
+            "{} forms loaded; expected at least {}",
+            report.loaded(),
+            MIN_LOADED
+        );
+        assert_eq!(report.shadowed, SHADOWED);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+// Runs the same programs with the tree-walker and the bytecode engine,
+// and checks they get the same results.
+// The rest of the tests can be run with the bytecode engine with
+//  SMOLBEL_ENGINE=bytecode cargo test
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+
+    use smolbel::eval::{self, Bel, Engine};
+    use smolbel::object::Object;
+    use smolbel::parser::{self, Reader};
+    use std::fs;
+
+    const SOURCE_PATH: &str = "bel_source/bel.bel";
+
+    const PROGRAMS: &[&[&str]] = &[
+        &["t", "nil", "'a", "(quote (a b))", "(set a 'b)", "a"],
+        &[
+            "(if)",
+            "(if 'a)",
+            "(if nil 'a 'b)",
+            "(if nil 'a nil 'b 'c)",
+            "(if t 'a 'b)",
+        ],
+        &[
+            "(no 'a)",
+            "(car '(a b))",
+            "(cdr '(a b))",
+            "(car nil)",
+            "(car 'a)",
+        ],
+        &[
+            "(def no (x) (id x nil))",
+            "(def atom (x) (no (id (type x) 'pair)))",
+            "(def all (f xs) (if (no xs) t (f (car xs)) (all f (cdr xs)) nil))",
+            "(all atom '(a b))",
+            "(all atom '(a (b)))",
+            "(atom `a)",
+            "(no nil)",
+        ],
+        &[
+            "(def last (xs) (if (cdr xs) (last (cdr xs)) (car xs)))",
+            "(last '(a b c))",
+            "(apply join 'a '((b)))",
+            "(apply last '((a b)))",
+            "(apply 'a)",
+        ],
+        &[
+            "(mac my-quote (x) (join 'quote (join x nil)))",
+            "(my-quote a)",
+            "(apply my-quote '(b))",
+        ],
+        &[
+            "(def adder (x) (fn (y) (join x y)))",
+            "(set add-a (adder 'a))",
+            "(add-a 'b)",
+            "((fn (x y) y x) 'a 'b)",
+            "(do)",
+            "(do 'a 'b)",
+        ],
+        &[
+            "(set x nil)",
+            "(push 'a x)",
+            "(push 'b x)",
+            "(pop x)",
+            "x",
+            "(swap x y)",
+            "(where (car y))",
+            "(wipe y)",
+            "(set tab (table))",
+            "(push 'v (tab 'k))",
+            "(tabref tab 'k)",
+        ],
+        &[
+            "(atomic (set a 'b) a)",
+            "(thread (set c 'd))",
+            "(atomic)",
+            "(type 'a 'b)",
+        ],
+        &[
+            "(set xs '(b c))",
+            "`(a ,(car xs) ,@xs d)",
+            "`(a . ,xs)",
+            "`(a `(b ,(c ,(car xs))))",
+            "((fn (x (o y 'b) . zs) (join x (join y zs))) 'a)",
+            "((fn ((x y) (o z x)) (join z y)) '(a b))",
+            "(set vmark (join))",
+            "(set v (join vmark nil))",
+            "(mac pair-v (x) `((fn (,v) (join ,v ,v)) ,x))",
+            "(pair-v 'a)",
+        ],
+        &[
+            "unbound",
+            "(unbound 'a)",
+            "((lit clo nil (x) x))",
+            "(lit foo)",
+        ],
+    ];
+
+    // the printed result, or the error message, of each form, and the Bel
+    // they were run in
+    fn run(engine: Engine, forms: &[Object]) -> (Vec<String>, Bel) {
+        let mut bel = Bel::new();
+        bel.engine = engine;
+        let results = forms
+            .iter()
+            .map(|exp| {
+                let result = bel.eval(&eval::new_object_map(), exp).and_then(|obj| {
+                    bel.run_threads()?;
+                    Ok(obj)
+                });
+                match result {
+                    Ok(obj) => obj.to_string(),
+                    Err(err) => format!("error: {:#}", err),
+                }
+            })
+            .collect();
+        (results, bel)
+    }
+
+    fn assert_engines_agree(forms: &[Object]) {
+        let (walked, walked_bel) = run(Engine::TreeWalk, forms);
+        let (compiled, compiled_bel) = run(Engine::Bytecode, forms);
+        for (i, form) in forms.iter().enumerate() {
+            assert_eq!(walked[i], compiled[i], "{}", form);
+        }
+
+        let mut names: Vec<&Object> = walked_bel.globals.keys().collect();
+        names.sort_by_key(|name| name.to_string());
+        for name in names {
+            assert_eq!(
+                walked_bel.global(name),
+                compiled_bel.global(name),
+                "global {}",
+                name
+            );
+        }
+        assert_eq!(walked_bel.globals.len(), compiled_bel.globals.len());
+    }
+
+    #[test]
+    fn engines_agree() -> Result<(), Error> {
+        for program in PROGRAMS {
+            let forms = program
+                .iter()
+                .map(|form| parser::parse(form))
+                .collect::<Result<Vec<Object>, Error>>()?;
+            assert_engines_agree(&forms);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn engines_agree_loading_bel_source() -> Result<(), Error> {
+        let text = fs::read_to_string(SOURCE_PATH)?;
+        let mut forms = Vec::new();
+        for item in Reader::new(&text).named(SOURCE_PATH) {
+            let (_, exp) = item?;
+            forms.push(exp);
+        }
+        assert_engines_agree(&forms);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+// Evaluates a corpus of expressions with our evaluator and with the
+// interpreter bel.bel defines, (bel 'e), and checks they agree.
+// A macro called by (bel 'e) never finishes expanding, as bel.bel's bquote
+// is written with let and let with bquote, so the corpus is forms and
+// functions, with closures written as (lit clo ...) rather than with fn.
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+
+    use smolbel::oracle::{Comparison, Oracle};
+    use smolbel::parser;
+
+    const SOURCE_PATH: &str = "bel_source/bel.bel";
+
+    const CORPUS: &[&str] = &[
+        "t",
+        "nil",
+        "'a",
+        "'(a b)",
+        "(id 'a 'a)",
+        "(id 'a 'b)",
+        "(join 'a 'b)",
+        "(car '(a b))",
+        "(cdr '(a b))",
+        "(car nil)",
+        "(car 'a)",
+        "(type 'a)",
+        "(type '(a))",
+        "(if nil 'a 'b)",
+        "(if nil 'a nil 'b 'c)",
+        "(if t 'a 'b)",
+        "(no nil)",
+        "(no 'a)",
+        "(atom 'a)",
+        "(atom '(a))",
+        "((lit clo nil (x) x) 'a)",
+        "((lit clo nil (x y) y) 'a 'b)",
+        "(apply join '(a b))",
+        "(apply join 'a '((b)))",
+        "(xar (join 'a 'b) 'c)",
+        "(all atom '(a b))",
+        "(some atom '((a) b))",
+        "(reduce join '(a b c))",
+        "(rev '(a b c))",
+        "(append '(a b) '(c))",
+        "(map car '((a) (b)))",
+        "unbound",
+    ];
+
+    #[test]
+    fn native_agrees_with_bel_source() -> Result<(), Error> {
+        let mut oracle = Oracle::load(SOURCE_PATH)?;
+        assert!(
+            oracle.is_available(),
+            "bel.bel's interpreter can't be run: {}",
+            oracle.compare(&parser::parse("t")?)
+        );
+
+        let mut disagreements: Vec<String> = Vec::new();
+        for text in CORPUS {
+            if let Comparison::Disagree { native, oracle } = oracle.compare(&parser::parse(text)?) {
+                disagreements.push(format!("{}: native {}; bel.bel {}", text, native, oracle));
+            }
+        }
+        assert!(disagreements.is_empty(), "\n{}", disagreements.join("\n"));
+
+        Ok(())
+    }
+}
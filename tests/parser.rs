@@ -0,0 +1,253 @@
+// Reading large inputs: the reader should take time in proportion to
+// the size of its input. Comments, chars and non-ASCII text read and
+// evaluated by a Bel. Reader macros added from outside the crate, and
+// symbols that print so they read back.
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+
+    use smolbel::eval::{self, Bel};
+    use smolbel::object;
+    use smolbel::parser::{self, Reader, Syntax};
+    use smolbel::symbol::Symbol;
+    use smolbel::Object;
+    use std::time::{Duration, Instant};
+
+    // definitions, comments, non-ASCII symbols and characters
+    fn generate(forms: usize) -> String {
+        (0..forms)
+            .map(|i| {
+                format!(
+                    "; definition {}\n(def fn-{}-λ (x é)\n  (if (no x) '\\λ\n      (join \\é (cdr x))))\n\n",
+                    i, i
+                )
+            })
+            .collect()
+    }
+
+    // the fastest of a few reads, to be less at the mercy of whatever else
+    // the machine is doing
+    fn time_to_read(text: &str) -> Result<(Duration, usize), Error> {
+        let mut fastest = Duration::MAX;
+        let mut count = 0;
+        for _ in 0..3 {
+            let start = Instant::now();
+            count = 0;
+            for item in Reader::new(text) {
+                item?;
+                count += 1;
+            }
+            fastest = fastest.min(start.elapsed());
+        }
+        Ok((fastest, count))
+    }
+
+    #[test]
+    fn reads_large_input_in_linear_time() -> Result<(), Error> {
+        let small = generate(10_000);
+        let large = generate(40_000);
+        assert!(large.len() > 2_000_000);
+
+        let (small_time, small_count) = time_to_read(&small)?;
+        let (large_time, large_count) = time_to_read(&large)?;
+        assert_eq!(small_count, 10_000);
+        assert_eq!(large_count, 40_000);
+
+        // four times the text should take about four times as long; a
+        // reader that copied the rest of the text at each step would take
+        // sixteen times as long
+        let ratio = large_time.as_secs_f64() / small_time.as_secs_f64();
+        assert!(
+            ratio < 8.0,
+            "{:?} for {} bytes; {:?} for {} bytes",
+            small_time,
+            small.len(),
+            large_time,
+            large.len()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn reads_non_ascii() -> Result<(), Error> {
+        let text = generate(2);
+        let objs: Vec<Object> = Reader::new(&text)
+            .map(|item| item.map(|(_, obj)| obj))
+            .collect::<Result<_, _>>()?;
+        let def = objs[1].to_vec()?;
+        assert!(def[1].is_symbol("fn-1-λ"));
+        assert!(def[2].to_vec()?[1].is_symbol("é"));
+
+        let body = def[3].to_vec()?;
+        assert_eq!(body[2].to_vec()?[1], Object::Char("λ".to_string()));
+
+        Ok(())
+    }
+
+    // read each form in text with the Bel's syntax, evaluate it, and
+    // return the value of the last
+    fn eval_text(bel: &mut Bel, text: &str) -> Result<Object, Error> {
+        let mut value = Object::Symbol(Symbol::NIL);
+        for item in Reader::new(text).with_syntax(&bel.syntax) {
+            let (_, exp) = item?;
+            value = bel.eval(&eval::new_object_map(), &exp)?;
+        }
+        Ok(value)
+    }
+
+    #[test]
+    fn evaluates_through_comments_chars_and_non_ascii() -> Result<(), Error> {
+        let mut bel = Bel::new();
+        let text = "
+            ; a function with a non-ASCII name
+            (def café (x #| the list |#)
+              ; the first
+              (car x)) #;(car 'a)
+            (café '(\\é \\sp #| not read |# b))";
+        assert_eq!(eval_text(&mut bel, text)?, Object::Char("é".to_string()));
+
+        // chars evaluate to themselves
+        assert_eq!(
+            eval_text(&mut bel, "(join \\a ; a comment\n (join \\λ nil))")?,
+            object::from_string("aλ")
+        );
+        assert!(eval_text(&mut bel, "(id 'é 'é)")?.is_true());
+        assert!(eval_text(&mut bel, "(id 'é 'e)")?.is_nil());
+        // the commented out form isn't evaluated
+        assert!(eval_text(&mut bel, "#;(car 'a) 'b")?.is_symbol("b"));
+
+        Ok(())
+    }
+
+    // #{a b} reads as (set-of a b)
+    fn add_set_syntax(syntax: &Syntax) -> Result<(), Error> {
+        syntax.insert('#', |source| {
+            if source.next_char() != Some('{') {
+                return Err(source.error("expected '{' after '#'"));
+            }
+            let items = source.read_until('}')?;
+            let mut list = vec![Object::Symbol(Symbol::new("set-of"))];
+            list.extend(items);
+            object::from_vec(list).map_err(|err| source.error(&err.to_string()))
+        })?;
+        // so } ends a symbol, as ) does
+        syntax.insert('}', |source| Err(source.error("unexpected '}'")))?;
+        Ok(())
+    }
+
+    #[test]
+    fn can_add_reader_macros() -> Result<(), Error> {
+        // #{a b} is a set literal
+        let syntax = Syntax::default();
+        add_set_syntax(&syntax)?;
+
+        let obj = parser::parse_with("(f #{a (b c)} d)", &syntax)?;
+        assert_eq!(obj, parser::parse("(f (set-of a (b c)) d)")?);
+        // which ends symbols, as the built in syntax does
+        let obj = parser::parse_with("(a#{b})", &syntax)?;
+        assert_eq!(obj, parser::parse("(a (set-of b))")?);
+
+        let err = parser::parse_with("(f #{a b)", &syntax).unwrap_err();
+        assert!(
+            err.to_string().starts_with("1:9: unexpected ')'"),
+            "{}",
+            err
+        );
+        let err = parser::parse_with("#{a", &syntax).unwrap_err();
+        assert!(err.to_string().starts_with("1:1: no '}'"), "{}", err);
+        let err = parser::parse_with("#a", &syntax).unwrap_err();
+        assert!(err.to_string().starts_with("1:1: expected '{'"), "{}", err);
+        let err = parser::parse_with("(a})", &syntax).unwrap_err();
+        assert!(
+            err.to_string().starts_with("1:3: unexpected '}'"),
+            "{}",
+            err
+        );
+
+        // the table a Bel reads with
+        let bel = Bel::new();
+        bel.syntax.insert('#', |source| source.read())?;
+        assert!(bel.parse("#a")?.is_symbol("a"));
+        assert!(bel.syntax.insert('(', |source| source.read()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn evaluates_quoted_symbols_and_reader_macros() -> Result<(), Error> {
+        let mut bel = Bel::new();
+
+        // |...| names a global and a parameter like any other symbol
+        eval_text(&mut bel, "(def |first of| (|a list|) (car |a list|))")?;
+        assert!(eval_text(&mut bel, "(|first of| '(|x y| z))")?.is_symbol("x y"));
+        assert!(eval_text(&mut bel, "(id '|a| 'a)")?.is_true());
+        assert!(eval_text(&mut bel, "(id '|(a)| 'a)")?.is_nil());
+
+        // a reader macro added from Rust reads #{a b} as (set-of a b)
+        add_set_syntax(&bel.syntax)?;
+        eval_text(&mut bel, "(mac set-of args (join 'quote (join args nil)))")?;
+        assert_eq!(
+            eval_text(&mut bel, "(car (cdr #{a |b c|}))")?,
+            Object::Symbol(Symbol::new("b c"))
+        );
+
+        // and one added by syn, which reads $a as ($ a)
+        eval_text(
+            &mut bel,
+            "(syn \\$ (x) (join 'quote (join (join x x) nil)))",
+        )?;
+        assert_eq!(
+            eval_text(&mut bel, "$|a b|")?,
+            parser::parse("(|a b| . |a b|)")?
+        );
+
+        Ok(())
+    }
+
+    // xorshift64*, so failures can be reproduced
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    // names made mostly of the chars the reader treats specially
+    fn random_name(rng: &mut Rng) -> String {
+        const CHARS: &[char] = &[
+            'a', 'z', '0', '9', '-', '_', '+', 'λ', 'é', ' ', '\t', '\n', '(', ')', '[', ']', '|',
+            '\\', ';', '#', '\'', '`', '~', ':', '.', '!', '{', '}', '"', ',', '\u{feff}',
+        ];
+        let len = rng.below(8);
+        (0..len).map(|_| CHARS[rng.below(CHARS.len())]).collect()
+    }
+
+    #[test]
+    fn printed_symbols_read_back() -> Result<(), Error> {
+        let mut rng = Rng(0x5eed);
+        for _ in 0..20_000 {
+            let name = random_name(&mut rng);
+            let symbol = Object::Symbol(Symbol::new(&name));
+            let printed = symbol.to_string();
+            let read = parser::parse(&printed)
+                .map_err(|err| err.context(format!("reading {:?} printed as {}", name, printed)))?;
+            assert_eq!(read, symbol, "{:?} printed as {}", name, printed);
+
+            // and in a list, with other things either side
+            let list = parser::parse(&format!("(a {} 'b)", printed))?.to_vec()?;
+            assert_eq!(list.len(), 3, "{:?} printed as {}", name, printed);
+            assert_eq!(list[1], symbol, "{:?} printed as {}", name, printed);
+        }
+
+        Ok(())
+    }
+}
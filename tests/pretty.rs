@@ -0,0 +1,30 @@
+// Lays out all of bel.bel, which is big enough to hit most of the layouts,
+// and should come out as it went in.
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use anyhow::Error;
+
+    use smolbel::conformance;
+    use smolbel::pretty;
+
+    #[test]
+    fn can_format_bel_source() -> Result<(), Error> {
+        let text = fs::read_to_string(conformance::SOURCE_PATH)?;
+        // format_text checks it reads the same, so just check it settles
+        let once = pretty::format_text(&text, pretty::WIDTH)?;
+        let twice = pretty::format_text(&once, pretty::WIDTH)?;
+        assert_eq!(once, twice);
+
+        // bel.bel is laid out as fmt would, so only the blank line at the
+        // end goes
+        let expected = format!("{}\n", text.trim_end());
+        for (line, (formatted, original)) in once.lines().zip(expected.lines()).enumerate() {
+            assert_eq!(formatted, original, "line {}", line + 1);
+        }
+        assert_eq!(once, expected);
+
+        Ok(())
+    }
+}